@@ -0,0 +1,6 @@
+CREATE TABLE sessions(
+    session_key TEXT NOT NULL,
+    state JSONB NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY(session_key)
+);
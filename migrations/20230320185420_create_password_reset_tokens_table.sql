@@ -0,0 +1,7 @@
+CREATE TABLE password_reset_tokens(
+    token_hash TEXT NOT NULL,
+    user_id uuid NOT NULL
+        REFERENCES users (user_id) ON DELETE CASCADE,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY(token_hash)
+);
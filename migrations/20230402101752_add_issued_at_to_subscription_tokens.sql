@@ -0,0 +1,2 @@
+ALTER TABLE subscription_tokens
+    ADD COLUMN issued_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,51 @@
+CREATE TABLE email_templates(
+    name TEXT NOT NULL,
+    source TEXT NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(name)
+);
+
+INSERT INTO email_templates (name, source) VALUES
+('layouts/base.html', $template$<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{% block title %}{% endblock %}</title>
+</head>
+<body>
+{% block content %}{% endblock %}
+{% block footer %}{% endblock %}
+</body>
+</html>
+$template$),
+('layouts/base.txt', $template${% block content %}{% endblock %}
+{% block footer %}{% endblock %}
+$template$),
+('partials/unsubscribe.html', $template$<hr /><p>To stop receiving this newsletter, <a href="{{ unsubscribe_url }}">unsubscribe</a>.</p>
+$template$),
+('partials/unsubscribe.txt', $template$
+--
+To stop receiving this newsletter, visit {{ unsubscribe_url }}
+$template$),
+('emails/newsletter.html', $template${% extends "layouts/base.html" %}
+{% block title %}{{ issue.title }}{% endblock %}
+{% block content %}{{ content }}{% endblock %}
+{% block footer %}{% include "partials/unsubscribe.html" %}{% endblock %}
+$template$),
+('emails/newsletter.txt', $template${% extends "layouts/base.txt" %}
+{% block content %}{{ content }}{% endblock %}
+{% block footer %}{% include "partials/unsubscribe.txt" %}{% endblock %}
+$template$),
+('emails/confirmation.html', $template${% extends "layouts/base.html" %}
+{% block title %}Welcome{% endblock %}
+{% block content %}
+<p>Welcome to our newsletter, {{ subscriber.name }}!</p>
+<p>Click <a href="{{ confirmation_link }}">here</a> to confirm your subscription.</p>
+{% endblock %}
+$template$),
+('emails/confirmation.txt', $template${% extends "layouts/base.txt" %}
+{% block content %}
+Welcome to our newsletter, {{ subscriber.name }}!
+Visit {{ confirmation_link }} to confirm your subscription.
+{% endblock %}
+$template$);
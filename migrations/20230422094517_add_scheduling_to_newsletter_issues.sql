@@ -0,0 +1,7 @@
+-- Scheduled issues are only published once their `send_at` is due.
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'published',
+    ADD COLUMN send_at timestamptz NULL,
+    ALTER COLUMN published_at DROP NOT NULL;
+CREATE INDEX newsletter_issues_due_idx ON newsletter_issues (send_at)
+    WHERE status = 'scheduled';
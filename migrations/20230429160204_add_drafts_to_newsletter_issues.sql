@@ -0,0 +1,3 @@
+-- Issues start out as drafts that editors can still change.
+ALTER TABLE newsletter_issues
+    ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,39 @@
+-- Published issues are served at `/issues/{slug}`.
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL UNIQUE;
+
+UPDATE newsletter_issues
+SET slug = coalesce(
+        nullif(left(trim(both '-' from regexp_replace(lower(title), '[^a-z0-9]+', '-', 'g')), 60), ''),
+        'issue'
+    ) || '-' || left(replace(newsletter_issue_id::text, '-', ''), 8)
+WHERE status = 'published';
+
+INSERT INTO email_templates (name, source) VALUES
+('partials/view_in_browser.html', $template$<p><a href="{{ issue.url }}">View this issue in your browser</a></p>
+$template$),
+('partials/view_in_browser.txt', $template$View this issue in your browser: {{ issue.url }}
+
+$template$);
+
+-- Only the layouts nobody customised yet get the link.
+UPDATE email_templates
+SET source = $template${% extends "layouts/base.html" %}
+{% block title %}{{ issue.title }}{% endblock %}
+{% block content %}{% include "partials/view_in_browser.html" %}{{ content }}{% endblock %}
+{% block footer %}{% include "partials/unsubscribe.html" %}{% endblock %}
+$template$, updated_at = now()
+WHERE name = 'emails/newsletter.html' AND source = $template${% extends "layouts/base.html" %}
+{% block title %}{{ issue.title }}{% endblock %}
+{% block content %}{{ content }}{% endblock %}
+{% block footer %}{% include "partials/unsubscribe.html" %}{% endblock %}
+$template$;
+
+UPDATE email_templates
+SET source = $template${% extends "layouts/base.txt" %}
+{% block content %}{% include "partials/view_in_browser.txt" %}{{ content }}{% endblock %}
+{% block footer %}{% include "partials/unsubscribe.txt" %}{% endblock %}
+$template$, updated_at = now()
+WHERE name = 'emails/newsletter.txt' AND source = $template${% extends "layouts/base.txt" %}
+{% block content %}{{ content }}{% endblock %}
+{% block footer %}{% include "partials/unsubscribe.txt" %}{% endblock %}
+$template$;
@@ -0,0 +1,15 @@
+-- The outcome of every (issue, subscriber) delivery: queued, sent, failed or bounced.
+CREATE TABLE issue_deliveries(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    status TEXT NOT NULL,
+    last_error TEXT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(newsletter_issue_id, subscriber_id)
+);
+CREATE INDEX issue_deliveries_status_idx ON issue_deliveries (newsletter_issue_id, status);
+
+INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_id, status)
+SELECT newsletter_issue_id, subscriber_id, 'queued' FROM issue_delivery_queue;
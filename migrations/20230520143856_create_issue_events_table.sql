@@ -0,0 +1,15 @@
+-- Open and click tracking is opt-in for every issue.
+ALTER TABLE newsletter_issues
+    ADD COLUMN tracking_enabled BOOLEAN NOT NULL DEFAULT false;
+
+-- Every open (tracking pixel load) and click (redirected link) of a tracked issue.
+CREATE TABLE issue_events(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    kind TEXT NOT NULL,
+    url TEXT NULL,
+    occurred_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX issue_events_issue_idx ON issue_events (newsletter_issue_id, kind);
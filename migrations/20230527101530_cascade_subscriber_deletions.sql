@@ -0,0 +1,17 @@
+-- Deleting a subscriber erases everything recorded about them.
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT subscription_tokens_subscriber_id_fkey,
+    ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+ALTER TABLE issue_delivery_queue
+    DROP CONSTRAINT issue_delivery_queue_subscriber_id_fkey,
+    ADD CONSTRAINT issue_delivery_queue_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+ALTER TABLE issue_deliveries
+    DROP CONSTRAINT issue_deliveries_subscriber_id_fkey,
+    ADD CONSTRAINT issue_deliveries_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+ALTER TABLE issue_events
+    DROP CONSTRAINT issue_events_subscriber_id_fkey,
+    ADD CONSTRAINT issue_events_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
@@ -0,0 +1,41 @@
+-- Mailing lists readers subscribe to. Before lists existed, everyone was on
+-- a single list: it becomes the default one.
+CREATE TABLE lists(
+    list_id uuid PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    is_default BOOLEAN NOT NULL DEFAULT false,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX lists_single_default_idx ON lists (is_default) WHERE is_default;
+INSERT INTO lists (list_id, name, is_default) VALUES (gen_random_uuid(), 'Newsletter', true);
+
+-- Whether a subscriber is pending, confirmed or unsubscribed on each list.
+-- Bounces and complaints stay on the subscriber: they apply to every list.
+CREATE TABLE list_memberships(
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(list_id, subscriber_id)
+);
+CREATE INDEX list_memberships_subscriber_idx ON list_memberships (subscriber_id);
+INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+SELECT l.list_id, s.id,
+    CASE WHEN s.status IN ('pending confirmation', 'unsubscribed') THEN s.status
+        ELSE 'confirmed' END,
+    s.subscribed_at
+FROM subscriptions s, lists l WHERE l.is_default;
+
+-- A confirmation link confirms the subscription to one list.
+ALTER TABLE subscription_tokens
+    ADD COLUMN list_id uuid REFERENCES lists (list_id);
+UPDATE subscription_tokens SET list_id = (SELECT list_id FROM lists WHERE is_default);
+ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+-- The list an issue is sent to, chosen when it is published or scheduled.
+ALTER TABLE newsletter_issues
+    ADD COLUMN list_id uuid NULL REFERENCES lists (list_id);
+UPDATE newsletter_issues SET list_id = (SELECT list_id FROM lists WHERE is_default)
+WHERE status <> 'draft';
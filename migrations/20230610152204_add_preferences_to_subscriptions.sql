@@ -0,0 +1,31 @@
+-- How often a subscriber wants to hear from us and, optionally, the last day
+-- of a break in deliveries. Both are managed from the preference center.
+ALTER TABLE subscriptions
+    ADD COLUMN digest_frequency TEXT NOT NULL DEFAULT 'immediate',
+    ADD COLUMN paused_until DATE NULL;
+
+-- A confirmation link can also confirm a new email address for an existing
+-- subscriber. Those links are not about any list.
+ALTER TABLE subscription_tokens
+    ADD COLUMN new_email TEXT NULL,
+    ALTER COLUMN list_id DROP NOT NULL;
+
+-- Newsletters link to the preference center. Templates edited by the
+-- administrator are left as they are.
+UPDATE email_templates
+SET source = '<hr /><p>To stop receiving this newsletter, <a href="{{ unsubscribe_url }}">unsubscribe</a>. To change how you receive it, <a href="{{ preferences_url }}">update your preferences</a>.</p>
+', updated_at = now()
+WHERE name = 'partials/unsubscribe.html'
+AND source = '<hr /><p>To stop receiving this newsletter, <a href="{{ unsubscribe_url }}">unsubscribe</a>.</p>
+';
+UPDATE email_templates
+SET source = '
+--
+To stop receiving this newsletter, visit {{ unsubscribe_url }}
+To change how you receive it, visit {{ preferences_url }}
+', updated_at = now()
+WHERE name = 'partials/unsubscribe.txt'
+AND source = '
+--
+To stop receiving this newsletter, visit {{ unsubscribe_url }}
+';
@@ -0,0 +1,30 @@
+-- The consent audit trail: every step of a subscription, with where the
+-- request came from. Events are never updated, and only go away together with
+-- the subscriber when they ask to be erased.
+CREATE TABLE subscription_events(
+    event_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    -- The address of the subscriber when the event happened.
+    email TEXT NOT NULL,
+    ip_address TEXT NULL,
+    user_agent TEXT NULL,
+    detail TEXT NULL,
+    occurred_at timestamptz NOT NULL DEFAULT clock_timestamp()
+);
+CREATE INDEX subscription_events_subscriber_idx ON subscription_events (subscriber_id);
+
+CREATE FUNCTION reject_subscription_event_updates() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'subscription events are append-only';
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER subscription_events_append_only
+BEFORE UPDATE ON subscription_events
+FOR EACH ROW EXECUTE FUNCTION reject_subscription_event_updates();
+
+-- What we know about subscriptions made before the audit trail existed.
+INSERT INTO subscription_events (subscriber_id, kind, email, detail, occurred_at)
+SELECT id, 'subscribed', email, 'Recorded before the audit trail existed', subscribed_at
+FROM subscriptions;
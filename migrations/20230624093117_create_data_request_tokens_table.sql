@@ -0,0 +1,9 @@
+-- Links emailed to subscribers who ask for a copy, or the erasure, of their
+-- data. Only a digest of each token is stored.
+CREATE TABLE data_request_tokens(
+    token_hash TEXT NOT NULL,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY(token_hash)
+);
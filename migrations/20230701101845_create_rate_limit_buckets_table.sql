@@ -0,0 +1,10 @@
+-- Token buckets of the rate limiter, when they are shared by several replicas.
+-- A bucket that is full again is no different from a missing one, so rows
+-- past `full_at` can be deleted at any time.
+CREATE TABLE rate_limit_buckets(
+    key TEXT NOT NULL,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at timestamptz NOT NULL,
+    full_at timestamptz NOT NULL,
+    PRIMARY KEY(key)
+);
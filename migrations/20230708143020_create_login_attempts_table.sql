@@ -0,0 +1,12 @@
+-- Every password check, so that repeated failures lock the username and the
+-- client address out for a while. Failed attempts double as an audit log.
+CREATE TABLE login_attempts(
+    attempt_id BIGINT GENERATED ALWAYS AS IDENTITY,
+    username TEXT NOT NULL,
+    ip_address TEXT NULL,
+    succeeded BOOLEAN NOT NULL,
+    attempted_at timestamptz NOT NULL DEFAULT clock_timestamp(),
+    PRIMARY KEY(attempt_id)
+);
+CREATE INDEX login_attempts_username_idx ON login_attempts (username, attempted_at);
+CREATE INDEX login_attempts_ip_address_idx ON login_attempts (ip_address, attempted_at);
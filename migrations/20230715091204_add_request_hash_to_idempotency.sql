@@ -0,0 +1,5 @@
+-- A digest of the request that claimed the key: reusing the key for another
+-- request is an error, not a replay. Keys saved before this column existed
+-- have no digest and are not checked.
+ALTER TABLE idempotency ADD COLUMN request_hash BYTEA NULL;
+CREATE INDEX idempotency_created_at_idx ON idempotency (created_at);
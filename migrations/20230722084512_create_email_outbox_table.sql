@@ -0,0 +1,12 @@
+-- Transactional emails waiting to be sent, enqueued together with whatever
+-- the request changed in the database.
+CREATE TABLE email_outbox(
+    email_id uuid NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(email_id)
+);
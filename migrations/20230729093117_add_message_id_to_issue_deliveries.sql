@@ -0,0 +1,4 @@
+-- The id the email provider gave the delivered issue, which its bounce and
+-- spam complaint webhooks refer to.
+ALTER TABLE issue_deliveries ADD COLUMN message_id TEXT NULL;
+CREATE UNIQUE INDEX issue_deliveries_message_id_idx ON issue_deliveries (message_id);
@@ -0,0 +1,14 @@
+-- Consent events only go away with their subscriber: when they ask to be
+-- erased, or when their subscription is purged before it was ever confirmed.
+-- Deleting the subscriber cascades to the events; any other delete is rejected.
+CREATE FUNCTION reject_subscription_event_deletes() RETURNS trigger AS $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM subscriptions WHERE id = OLD.subscriber_id) THEN
+        RAISE EXCEPTION 'subscription events are only deleted with their subscriber';
+    END IF;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER subscription_events_deleted_with_subscriber
+BEFORE DELETE ON subscription_events
+FOR EACH ROW EXECUTE FUNCTION reject_subscription_event_deletes();
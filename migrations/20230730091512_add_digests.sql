@@ -0,0 +1,48 @@
+-- Subscribers on a daily or weekly digest get the issues published in the
+-- meantime gathered in a single email. Their delivery tasks wait in the queue
+-- until the digest they belong to is sent.
+ALTER TABLE issue_delivery_queue ADD COLUMN digest BOOLEAN NOT NULL DEFAULT false;
+
+-- One pending digest per subscriber: the unit the worker locks, sends and retries.
+CREATE TABLE digest_queue(
+    subscriber_id uuid PRIMARY KEY
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL
+);
+
+UPDATE issue_delivery_queue q SET digest = true
+FROM subscriptions s
+WHERE s.id = q.subscriber_id AND s.digest_frequency <> 'immediate'
+AND q.execute_after > now();
+
+INSERT INTO digest_queue (subscriber_id, execute_after)
+SELECT subscriber_id, MIN(execute_after) FROM issue_delivery_queue
+WHERE digest GROUP BY subscriber_id;
+
+-- The issues of a digest share the message id of the email they were sent in.
+DROP INDEX issue_deliveries_message_id_idx;
+CREATE INDEX issue_deliveries_message_id_idx ON issue_deliveries (message_id);
+
+INSERT INTO email_templates (name, source) VALUES
+('emails/digest.html', $template${% extends "layouts/base.html" %}
+{% block title %}{{ title }}{% endblock %}
+{% block content %}
+{% for issue in issues %}
+<h2><a href="{{ issue.url }}">{{ issue.title }}</a></h2>
+{{ issue.html }}
+{% endfor %}
+{% endblock %}
+{% block footer %}{% include "partials/unsubscribe.html" %}{% endblock %}
+$template$),
+('emails/digest.txt', $template${% extends "layouts/base.txt" %}
+{% block content %}
+{% for issue in issues %}
+{{ issue.title }} ({{ issue.url }})
+
+{{ issue.text }}
+
+{% endfor %}
+{% endblock %}
+{% block footer %}{% include "partials/unsubscribe.txt" %}{% endblock %}
+$template$);
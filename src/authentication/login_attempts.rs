@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::configuration::LoginProtectionSettings;
+
+#[derive(serde::Serialize)]
+pub struct FailedLoginAttempt {
+    pub username: String,
+    pub ip_address: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// Records a password check before it happens, as a failure until
+/// `record_login_success` says otherwise. Returns the id of the attempt, or
+/// how long to wait if the username or the address is locked out.
+///
+/// Failures on a username count until its next successful login, failures
+/// from an address until they fall out of the address failure window: one
+/// valid credential must not let an address keep guessing other usernames.
+///
+/// Attempts on the same username are serialised, so a burst of concurrent
+/// guesses cannot slip past the lockout.
+#[tracing::instrument(name = "Starting a login attempt", skip(db_connection_pool, settings))]
+pub async fn start_login_attempt(
+    db_connection_pool: &PgPool,
+    username: &str,
+    ip_address: Option<&str>,
+    settings: &LoginProtectionSettings,
+) -> Result<Result<i64, Duration>, anyhow::Error> {
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")?;
+    sqlx::query!(
+        r#"SELECT pg_advisory_xact_lock(hashtext($1))"#,
+        format!("login:{}", username)
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to lock the login attempts of the username")?;
+
+    let by_username = sqlx::query!(
+        r#"SELECT COUNT(*) AS "n_failures!", MAX(attempted_at) AS last_failure
+        FROM login_attempts
+        WHERE username = $1 AND NOT succeeded
+        AND attempted_at > COALESCE(
+            (SELECT MAX(attempted_at) FROM login_attempts WHERE username = $1 AND succeeded),
+            '-infinity'
+        )"#,
+        username
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to count the failed attempts on the username")?;
+    let mut locked_until = locked_until(settings, by_username.n_failures, by_username.last_failure);
+
+    if let Some(ip_address) = ip_address {
+        let by_address = sqlx::query!(
+            r#"SELECT COUNT(*) AS "n_failures!", MAX(attempted_at) AS last_failure
+            FROM login_attempts
+            WHERE ip_address = $1 AND NOT succeeded AND attempted_at > $2"#,
+            ip_address,
+            Utc::now() - settings.address_failure_window()
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .context("Failed to count the failed attempts from the address")?;
+        locked_until = locked_until.max(self::locked_until(
+            settings,
+            by_address.n_failures,
+            by_address.last_failure,
+        ));
+    }
+
+    if let Some(retry_after) = locked_until.and_then(|until| (until - Utc::now()).to_std().ok()) {
+        return Ok(Err(retry_after));
+    }
+
+    let attempt_id = sqlx::query!(
+        r#"INSERT INTO login_attempts (username, ip_address, succeeded)
+        VALUES ($1, $2, false)
+        RETURNING attempt_id"#,
+        username,
+        ip_address
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to record the login attempt")?
+    .attempt_id;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transaction")?;
+    Ok(Ok(attempt_id))
+}
+
+fn locked_until(
+    settings: &LoginProtectionSettings,
+    n_failures: i64,
+    last_failure: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    let lockout = chrono::Duration::from_std(settings.lockout(n_failures)?).ok()?;
+    Some(last_failure? + lockout)
+}
+
+/// Marks the attempt as successful, which resets the count of failures on
+/// its username.
+pub async fn record_login_success(
+    db_connection_pool: &PgPool,
+    attempt_id: i64,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE login_attempts SET succeeded = true WHERE attempt_id = $1"#,
+        attempt_id
+    )
+    .execute(db_connection_pool)
+    .await
+    .context("Failed to record the successful login attempt")?;
+    Ok(())
+}
+
+/// Deletes the attempts older than the retention window, returning how many
+/// were deleted.
+#[tracing::instrument(skip_all, fields(n_purged=tracing::field::Empty), err)]
+pub async fn purge_old_login_attempts(
+    db_connection_pool: &PgPool,
+    settings: &LoginProtectionSettings,
+) -> Result<u64, anyhow::Error> {
+    let n_purged = sqlx::query!(
+        r#"DELETE FROM login_attempts WHERE attempted_at < $1"#,
+        Utc::now() - settings.attempt_retention()
+    )
+    .execute(db_connection_pool)
+    .await
+    .context("Failed to delete old login attempts")?
+    .rows_affected();
+
+    tracing::Span::current().record("n_purged", n_purged);
+    Ok(n_purged)
+}
+
+/// Failed attempts, most recent first.
+pub async fn list_failed_login_attempts(
+    db_connection_pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<FailedLoginAttempt>, anyhow::Error> {
+    sqlx::query_as!(
+        FailedLoginAttempt,
+        r#"SELECT username, ip_address, attempted_at FROM login_attempts
+        WHERE NOT succeeded
+        ORDER BY attempted_at DESC, attempt_id DESC
+        LIMIT $1 OFFSET $2"#,
+        limit,
+        offset
+    )
+    .fetch_all(db_connection_pool)
+    .await
+    .context("Failed to list the failed login attempts")
+}
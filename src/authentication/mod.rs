@@ -0,0 +1,14 @@
+mod login_attempts;
+mod middleware;
+mod password;
+mod password_reset;
+
+pub use login_attempts::{
+    list_failed_login_attempts, purge_old_login_attempts, FailedLoginAttempt,
+};
+pub use middleware::{reject_anonymous_users, UserId};
+pub use password::{change_password, validate_credentials, AuthError, Credentials};
+pub use password_reset::{
+    get_user_by_email, get_user_id_from_reset_token, issue_password_reset_token,
+    purge_expired_password_reset_tokens, PasswordResetRecipient,
+};
@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{
+        login_attempts::{record_login_success, start_login_attempt},
+        password_reset::delete_password_reset_tokens,
+    },
+    configuration::LoginProtectionSettings,
+    domain::NewPassword,
+    session_store::delete_user_sessions,
+    telemetry::{error_chain_fmt, spawn_blocking_with_tracing},
+};
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(
+        "Too many failed attempts, try again in {} seconds.",
+        retry_after.as_secs()
+    )]
+    TooManyAttempts { retry_after: Duration },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Checked when the username is unknown, so that telling unknown usernames
+/// from wrong passwords takes as long as an actual check.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+    gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+#[tracing::instrument(
+    name = "Validating credentials",
+    skip(credentials, settings, db_connection_pool)
+)]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    ip_address: Option<&str>,
+    settings: &LoginProtectionSettings,
+    db_connection_pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    let attempt_id = match start_login_attempt(
+        db_connection_pool,
+        &credentials.username,
+        ip_address,
+        settings,
+    )
+    .await?
+    {
+        Ok(attempt_id) => attempt_id,
+        Err(retry_after) => {
+            // Rounded up to whole seconds, not to invite an early retry.
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            return Err(AuthError::TooManyAttempts {
+                retry_after: Duration::from_secs(seconds),
+            });
+        }
+    };
+
+    let mut user_id = None;
+    let mut expected_password = Secret::new(DUMMY_PASSWORD_HASH.to_string());
+    if let Some((stored_user_id, stored_password)) =
+        get_stored_credentials(&credentials.username, db_connection_pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password = stored_password;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        validate_password_hash(expected_password, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking task")??;
+
+    // Only reached with a stored password: the dummy hash matches no password.
+    let user_id = user_id
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username.")))?;
+    record_login_success(db_connection_pool, attempt_id).await?;
+    Ok(user_id)
+}
+
+#[tracing::instrument(
+    name = "Validating password hash",
+    skip(expected_password, password_candidate)
+)]
+fn validate_password_hash(
+    expected_password: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password.expose_secret())
+        .context("Failed to parse hash in PHC string format")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(db_connection_pool, username))]
+async fn get_stored_credentials(
+    username: &str,
+    db_connection_pool: &PgPool,
+) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id, password FROM users WHERE username = $1"#,
+        username
+    )
+    .fetch_optional(db_connection_pool)
+    .await
+    .context("Failed to retrieve stored credentials")?
+    .map(|row| (row.user_id, Secret::new(row.password)));
+
+    Ok(row)
+}
+
+/// Replaces the password of the user, logging them out everywhere.
+#[tracing::instrument(name = "Change password", skip(password, db_connection_pool))]
+pub async fn change_password(
+    user_id: uuid::Uuid,
+    password: NewPassword,
+    db_connection_pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password.into()))
+        .await?
+        .context("Failed to hash password")?;
+
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")?;
+    sqlx::query!(
+        r#"UPDATE users SET password = $1 WHERE user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to change user's password in the database.")?;
+    // Whoever got hold of a reset link or of a session is locked out too.
+    delete_password_reset_tokens(&mut transaction, user_id).await?;
+    delete_user_sessions(&mut transaction, user_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transaction")?;
+    Ok(())
+}
+
+fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+    Ok(Secret::new(password_hash))
+}
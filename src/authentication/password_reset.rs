@@ -0,0 +1,101 @@
+use anyhow::Context;
+use chrono::Duration;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::random_token::{generate_token, hash_token};
+
+/// How long a "forgot password" link stays valid.
+const RESET_TOKEN_TTL_MINUTES: i64 = 60;
+
+pub struct PasswordResetRecipient {
+    pub user_id: Uuid,
+    pub email: String,
+}
+
+#[tracing::instrument(name = "Get user by email", skip(email, db_connection_pool))]
+pub async fn get_user_by_email(
+    email: &str,
+    db_connection_pool: &PgPool,
+) -> Result<Option<PasswordResetRecipient>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id, email as "email!" FROM users WHERE email = $1"#,
+        email
+    )
+    .fetch_optional(db_connection_pool)
+    .await
+    .context("Failed to retrieve the user from their email")?
+    .map(|row| PasswordResetRecipient {
+        user_id: row.user_id,
+        email: row.email,
+    });
+    Ok(row)
+}
+
+/// Stores a new single-use reset token and returns it in clear.
+/// Only its SHA-256 digest is persisted.
+#[tracing::instrument(name = "Issue password reset token", skip(executor))]
+pub async fn issue_password_reset_token(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<String, anyhow::Error> {
+    let token = generate_token();
+    sqlx::query!(
+        r#"INSERT INTO password_reset_tokens (token_hash, user_id, expires_at)
+        VALUES ($1, $2, $3)"#,
+        hash_token(&token),
+        user_id,
+        chrono::Utc::now() + Duration::minutes(RESET_TOKEN_TTL_MINUTES)
+    )
+    .execute(executor)
+    .await
+    .context("Failed to store the password reset token")?;
+    Ok(token)
+}
+
+/// Returns the user the token was issued to, if it exists and has not expired.
+#[tracing::instrument(name = "Validate password reset token", skip_all)]
+pub async fn get_user_id_from_reset_token(
+    token: &str,
+    db_connection_pool: &PgPool,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id FROM password_reset_tokens WHERE token_hash = $1 AND expires_at > now()"#,
+        hash_token(token)
+    )
+    .fetch_optional(db_connection_pool)
+    .await
+    .context("Failed to retrieve the password reset token")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+/// Invalidates every outstanding reset token of the user.
+#[tracing::instrument(name = "Delete password reset tokens", skip(executor))]
+pub(super) async fn delete_password_reset_tokens(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to delete the password reset tokens")?;
+    Ok(())
+}
+
+/// Removes the reset tokens past their expiry, returning how many were deleted.
+#[tracing::instrument(skip_all, fields(n_purged=tracing::field::Empty), err)]
+pub async fn purge_expired_password_reset_tokens(
+    db_connection_pool: &PgPool,
+) -> Result<u64, anyhow::Error> {
+    let n_purged = sqlx::query!(r#"DELETE FROM password_reset_tokens WHERE expires_at <= now()"#)
+        .execute(db_connection_pool)
+        .await
+        .context("Failed to delete expired password reset tokens")?
+        .rows_affected();
+
+    tracing::Span::current().record("n_purged", n_purged);
+    Ok(n_purged)
+}
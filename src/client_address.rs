@@ -0,0 +1,100 @@
+use std::net::IpAddr;
+
+use actix_web::{web, HttpRequest};
+
+/// The reverse proxies allowed to tell who they forward requests for.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        Self(proxies)
+    }
+
+    /// The address the request comes from. `X-Forwarded-For` is only believed
+    /// when a trusted proxy sent it: any client can make the header up.
+    pub fn client_address(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let mut client = request.peer_addr()?.ip();
+        // Every proxy appends the address it got the request from: the client
+        // is the last one that did not come through one of ours.
+        let mut forwarded_for = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev();
+        while self.0.contains(&client) {
+            match forwarded_for.next().map(str::parse) {
+                Some(Ok(address)) => client = address,
+                _ => break,
+            }
+        }
+        Some(client)
+    }
+}
+
+/// The address of the client behind the request, as told by the proxies
+/// registered in the application data.
+pub fn client_address(request: &HttpRequest) -> Option<String> {
+    let client_address = match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) => proxies.client_address(request),
+        None => TrustedProxies::default().client_address(request),
+    };
+    client_address.map(|address| address.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use actix_web::{test::TestRequest, HttpRequest};
+    use claim::assert_some_eq;
+
+    use super::TrustedProxies;
+
+    const PROXY: &str = "10.0.0.1";
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new(vec![PROXY.parse().unwrap()])
+    }
+
+    fn request(peer: &str, forwarded_for: Option<&str>) -> HttpRequest {
+        let peer: IpAddr = peer.parse().unwrap();
+        let mut request = TestRequest::default().peer_addr(SocketAddr::new(peer, 4242));
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.insert_header(("X-Forwarded-For", forwarded_for));
+        }
+        request.to_http_request()
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_forward_another_address() {
+        let request = request("203.0.113.7", Some("198.51.100.1"));
+        assert_some_eq!(proxies().client_address(&request), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn trusted_proxies_forward_the_address_of_the_client() {
+        let request = request(PROXY, Some("198.51.100.1"));
+        assert_some_eq!(proxies().client_address(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn addresses_prepended_by_the_client_are_ignored() {
+        let request = request(PROXY, Some("192.0.2.9, 198.51.100.1"));
+        assert_some_eq!(proxies().client_address(&request), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn trusted_proxies_without_forwarded_address_are_the_client() {
+        let request = request(PROXY, None);
+        assert_some_eq!(proxies().client_address(&request), ip(PROXY));
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{net::IpAddr, time::Duration};
 
 use reqwest::Url;
 use secrecy::{ExposeSecret, Secret};
@@ -6,16 +6,32 @@ use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
-    ConnectOptions,
+    ConnectOptions, PgPool,
 };
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{
+        EmailClient, EmailSender, PostmarkSender, RetryPolicy, RetryingSender, SesSender,
+        SmtpSender,
+    },
+    rate_limiting::{
+        InMemoryRateLimitStore, PgRateLimitStore, RateLimitStore, RateLimiter, TokenBucket,
+    },
+};
 
 #[derive(Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub issue_delivery: IssueDeliverySettings,
+    pub subscriptions: SubscriptionSettings,
+    pub retention: RetentionSettings,
+    pub newsletter_scheduler: NewsletterSchedulerSettings,
+    pub webhooks: WebhookSettings,
+    pub rate_limiting: RateLimitingSettings,
+    pub login_protection: LoginProtectionSettings,
 }
 
 #[derive(Deserialize, Clone)]
@@ -24,6 +40,10 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: Url,
+    pub hmac_secret: Secret<String>,
+    /// The reverse proxies whose `X-Forwarded-For` header tells the client address.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 pub enum Environment {
@@ -84,7 +104,7 @@ impl DatabaseSettings {
             .host(&self.host)
             .port(self.port)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .ssl_mode(ssl_mode)
     }
 }
@@ -111,18 +131,143 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         )
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    let settings = settings.try_deserialize::<Settings>()?;
+    if settings.webhooks.postmark_secret.expose_secret().is_empty() {
+        return Err(config::ConfigError::Message(
+            "The Postmark webhook secret must not be empty".into(),
+        ));
+    }
+    Ok(settings)
 }
 
 #[derive(Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub provider: EmailProvider,
     pub base_url: Url,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub retry: EmailRetrySettings,
+    pub smtp: Option<SmtpSettings>,
+    pub ses: Option<SesSettings>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    #[default]
+    Postmark,
+    Smtp,
+    Ses,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EmailRetrySettings {
+    /// Total number of attempts per email, the first one included.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+}
+
+impl EmailRetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.max(1),
+            base_delay: Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: Duration::from_millis(self.max_delay_milliseconds),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+/// How the connection to the SMTP relay is secured.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text connection, only meant for local SMTP sinks.
+    None,
+    #[default]
+    StartTls,
+    /// TLS from the first byte (usually port 465).
+    Tls,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SesSettings {
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: Secret<String>,
+    /// Overrides the regional endpoint, e.g. to point at a mock server.
+    pub base_url: Option<Url>,
+}
+
+impl SesSettings {
+    pub fn endpoint(&self) -> Url {
+        match &self.base_url {
+            Some(base_url) => base_url.clone(),
+            None => Url::parse(&format!("https://email.{}.amazonaws.com", self.region))
+                .expect("Invalid SES region"),
+        }
+    }
 }
 
 impl EmailClientSettings {
+    /// A client retrying transient failures, for emails sent while a request
+    /// waits for them.
+    pub fn client(self) -> EmailClient {
+        let policy = self.retry.policy();
+        let (sender_email, backend) = self.backend();
+        let backend = RetryingSender::new(backend, policy);
+        EmailClient::new(sender_email, Box::new(backend))
+    }
+
+    /// A client giving up at the first failure, for the queue workers: they
+    /// reschedule failed emails themselves rather than wait with the queue
+    /// rows locked.
+    pub fn client_without_retries(self) -> EmailClient {
+        let (sender_email, backend) = self.backend();
+        EmailClient::new(sender_email, backend)
+    }
+
+    fn backend(self) -> (SubscriberEmail, Box<dyn EmailSender>) {
+        let sender_email = self.sender().expect("Invalid sender email address");
+        let timeout = self.timeout();
+        let backend: Box<dyn EmailSender> = match self.provider {
+            EmailProvider::Postmark => Box::new(PostmarkSender::new(
+                self.base_url,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailProvider::Smtp => {
+                let settings = self
+                    .smtp
+                    .expect("The 'smtp' section is required by the SMTP email provider");
+                Box::new(SmtpSender::new(&settings, timeout).expect("Invalid SMTP relay settings"))
+            }
+            EmailProvider::Ses => {
+                let settings = self
+                    .ses
+                    .expect("The 'ses' section is required by the SES email provider");
+                Box::new(SesSender::new(settings, timeout))
+            }
+        };
+        (sender_email, backend)
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -131,3 +276,185 @@ impl EmailClientSettings {
         Duration::from_millis(self.timeout_milliseconds)
     }
 }
+
+#[derive(Deserialize, Clone)]
+pub struct IssueDeliverySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: i16,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_retry_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_milliseconds: u64,
+    /// Maximum number of delivery tasks handed to the email provider at once.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub batch_size: i64,
+}
+
+impl IssueDeliverySettings {
+    /// Delay before the next delivery attempt, doubling with every failed attempt.
+    pub fn retry_delay(&self, n_retries: i16) -> Duration {
+        let exponent = n_retries.clamp(0, 16) as u32;
+        Duration::from_millis(
+            self.base_retry_delay_milliseconds
+                .saturating_mul(2u64.pow(exponent)),
+        )
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_milliseconds)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_token_ttl_hours: u64,
+    /// How long the preference center links sent with issues keep working.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub preferences_link_ttl_days: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn confirmation_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.confirmation_token_ttl_hours as i64)
+    }
+
+    pub fn preferences_link_ttl(&self) -> chrono::Duration {
+        chrono::Duration::days(self.preferences_link_ttl_days as i64)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RetentionSettings {
+    /// How often the retention worker deletes the data past its retention.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub purge_interval_seconds: u64,
+    /// How long an idempotency key protects against retries before it is purged.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idempotency_key_ttl_hours: u64,
+}
+
+impl RetentionSettings {
+    pub fn purge_interval(&self) -> Duration {
+        Duration::from_secs(self.purge_interval_seconds)
+    }
+
+    pub fn idempotency_key_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.idempotency_key_ttl_hours as i64)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct NewsletterSchedulerSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_seconds: u64,
+}
+
+impl NewsletterSchedulerSettings {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebhookSettings {
+    /// Sent by Postmark in the `X-Webhook-Secret` header of every webhook call.
+    /// It has no default: the application refuses to start without one.
+    pub postmark_secret: Secret<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RateLimitingSettings {
+    #[serde(default)]
+    pub store: RateLimitStoreKind,
+    /// Requests from a single client address.
+    pub per_ip: TokenBucketSettings,
+    /// Requests about a single target email address.
+    pub per_email: TokenBucketSettings,
+}
+
+/// Where the token buckets are kept.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    /// Every replica limits requests on its own.
+    #[default]
+    Memory,
+    /// Replicas share their buckets through the database.
+    Postgres,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TokenBucketSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub refill_interval_seconds: u64,
+}
+
+impl TokenBucketSettings {
+    pub fn bucket(&self) -> TokenBucket {
+        TokenBucket {
+            capacity: self.capacity,
+            refill_interval: Duration::from_secs(self.refill_interval_seconds),
+        }
+    }
+}
+
+impl RateLimitingSettings {
+    pub fn limiter(&self, db_connection_pool: PgPool) -> RateLimiter {
+        let store: Box<dyn RateLimitStore> = match self.store {
+            RateLimitStoreKind::Memory => Box::new(InMemoryRateLimitStore::new()),
+            RateLimitStoreKind::Postgres => Box::new(PgRateLimitStore::new(db_connection_pool)),
+        };
+        RateLimiter::new(store, self.per_ip.bucket(), self.per_email.bucket())
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LoginProtectionSettings {
+    /// Consecutive failed attempts, for a username or from an address, before
+    /// logins are locked.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failed_attempts: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_lockout_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_lockout_seconds: u64,
+    /// How far back failed attempts from an address are counted. Unlike the
+    /// failures on a username, they are not reset by a successful login.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub address_failure_window_seconds: u64,
+    /// How long login attempts are kept, for the lockouts and the audit log.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub attempt_retention_days: u64,
+}
+
+impl LoginProtectionSettings {
+    /// How long logins stay locked after the last of `n_failures` consecutive
+    /// failed attempts, doubling with every failure past the threshold.
+    pub fn lockout(&self, n_failures: i64) -> Option<Duration> {
+        let excess = n_failures - self.max_failed_attempts;
+        if excess < 0 {
+            return None;
+        }
+        let exponent = excess.min(16) as u32;
+        Some(Duration::from_secs(
+            self.base_lockout_seconds
+                .saturating_mul(2u64.pow(exponent))
+                .min(self.max_lockout_seconds),
+        ))
+    }
+
+    pub fn address_failure_window(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.address_failure_window_seconds as i64)
+    }
+
+    /// Never shorter than a lockout or the address failure window, which the
+    /// purged attempts would cut short.
+    pub fn attempt_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.attempt_retention_days as i64)
+            .max(chrono::Duration::seconds(self.max_lockout_seconds as i64))
+            .max(self.address_failure_window())
+    }
+}
@@ -1,11 +1,13 @@
 use std::{error::Error, fmt::Debug};
 
 use crate::{
-    domain::{Subscriber, SubscriberEmail},
+    domain::{DigestFrequency, IssueSlug, Subscriber, SubscriberEmail, SubscriberName},
+    random_token::{generate_token, hash_token},
+    subscription_events::{EventSource, SubscriptionEventKind},
     telemetry::error_chain_fmt,
 };
-use chrono::Utc;
-use sqlx::{PgPool, Postgres, Transaction};
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 pub struct StoreTokenError(sqlx::Error);
@@ -31,29 +33,6 @@ impl Debug for StoreTokenError {
     }
 }
 
-pub struct RetrieveTokenError(sqlx::Error);
-
-impl std::fmt::Display for RetrieveTokenError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "A database error was encountered while trying to retrieve the subcription token from the database"
-        )
-    }
-}
-
-impl Error for RetrieveTokenError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        Some(&self.0)
-    }
-}
-
-impl Debug for RetrieveTokenError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        error_chain_fmt(self, f)
-    }
-}
-
 pub struct RetrieveSubscriberError(sqlx::Error);
 
 impl std::fmt::Display for RetrieveSubscriberError {
@@ -96,6 +75,130 @@ pub async fn insert_subscriber(
     Ok(subscriber_id)
 }
 
+/// What importing a row did.
+pub enum ImportOutcome {
+    /// A new subscriber, member of the list.
+    Created(Uuid),
+    /// A known subscriber, now also member of the list.
+    AddedToList(Uuid),
+    AlreadyOnList,
+    /// A known subscriber whose address bounced or complained: they are left
+    /// out of the list.
+    Undeliverable,
+}
+
+/// Saves a subscriber, and their membership of `list_id`, with the given
+/// status. Known subscribers keep their details and only join the list.
+#[tracing::instrument(name = "Importing subscriber", skip(subscriber, transaction))]
+pub async fn import_subscriber(
+    subscriber: &Subscriber,
+    status: &str,
+    list_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<ImportOutcome, sqlx::Error> {
+    let created = sqlx::query!(
+        r#"WITH subscriber AS (
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, $3, now(), $4)
+            ON CONFLICT (email) DO NOTHING
+            RETURNING id
+        )
+        INSERT INTO list_memberships (list_id, subscriber_id, status)
+        SELECT $5, id, $4 FROM subscriber
+        RETURNING subscriber_id"#,
+        Uuid::new_v4(),
+        subscriber.email.as_ref(),
+        subscriber.name.as_ref(),
+        status,
+        list_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    if let Some(record) = created {
+        return Ok(ImportOutcome::Created(record.subscriber_id));
+    }
+
+    let existing = sqlx::query!(
+        r#"SELECT id, status FROM subscriptions WHERE email = $1"#,
+        subscriber.email.as_ref()
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    if existing.status == "bounced" || existing.status == "complained" {
+        return Ok(ImportOutcome::Undeliverable);
+    }
+    let added = sqlx::query!(
+        r#"INSERT INTO list_memberships (list_id, subscriber_id, status)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (list_id, subscriber_id) DO NOTHING"#,
+        list_id,
+        existing.id,
+        status
+    )
+    .execute(transaction)
+    .await?
+    .rows_affected();
+
+    Ok(if added == 1 {
+        ImportOutcome::AddedToList(existing.id)
+    } else {
+        ImportOutcome::AlreadyOnList
+    })
+}
+
+/// Adds a subscriber to a list, pending their confirmation. Subscribers who
+/// already confirmed their membership stay confirmed.
+#[tracing::instrument(name = "Joining a list", skip(transaction))]
+pub async fn join_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO list_memberships (list_id, subscriber_id, status)
+        VALUES ($1, $2, 'pending confirmation')
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = 'pending confirmation', subscribed_at = now()
+        WHERE list_memberships.status <> 'confirmed'"#,
+        list_id,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Subscribers who leave the last list they were confirmed on become
+/// unsubscribed. Returns `false` if the subscriber is not on the list.
+#[tracing::instrument(name = "Leaving a list", skip(transaction))]
+pub async fn leave_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE list_memberships SET status = 'unsubscribed'
+        WHERE list_id = $1 AND subscriber_id = $2"#,
+        list_id,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE id = $1 AND status = 'confirmed' AND NOT EXISTS (
+            SELECT 1 FROM list_memberships
+            WHERE subscriber_id = $1 AND status = 'confirmed'
+        )"#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
 #[tracing::instrument(
     name = "Storing subscription token into database",
     skip(transaction, subscription_token)
@@ -103,20 +206,65 @@ pub async fn insert_subscriber(
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id)
+        VALUES ($1, $2, $3)"#,
         subscription_token,
         subscriber_id,
+        list_id
     )
     .execute(transaction)
     .await
-    .map_err(|e| StoreTokenError(e))?;
+    .map_err(StoreTokenError)?;
 
     Ok(())
 }
 
+/// Stores a token confirming that `new_email` belongs to the subscriber.
+/// Previous email change links stop working.
+#[tracing::instrument(
+    name = "Storing email change token into database",
+    skip(transaction, new_email, subscription_token)
+)]
+pub async fn store_email_change_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+    subscription_token: &str,
+) -> Result<(), StoreTokenError> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1 AND new_email IS NOT NULL"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(StoreTokenError)?;
+    sqlx::query!(
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, new_email)
+        VALUES ($1, $2, $3)"#,
+        subscription_token,
+        subscriber_id,
+        new_email.as_ref()
+    )
+    .execute(transaction)
+    .await
+    .map_err(StoreTokenError)?;
+
+    Ok(())
+}
+
+/// A token confirms either a subscription to `list_id` or, when `new_email`
+/// is set, a change of address.
+pub struct StoredSubscriptionToken {
+    pub subscriber_id: Uuid,
+    pub list_id: Option<Uuid>,
+    pub new_email: Option<String>,
+    pub issued_at: DateTime<Utc>,
+}
+
 #[tracing::instrument(
     name = "Retrieving subscription_id from subscription_token",
     skip(subscription_token, db_connection_pool)
@@ -124,32 +272,102 @@ pub async fn store_token(
 pub async fn get_subscriber_id_from_token(
     db_connection_pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, RetrieveSubscriberError> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token=$1"#,
+) -> Result<Option<StoredSubscriptionToken>, RetrieveSubscriberError> {
+    let result = sqlx::query_as!(
+        StoredSubscriptionToken,
+        r#"SELECT subscriber_id, list_id, new_email, issued_at FROM subscription_tokens
+        WHERE subscription_token=$1"#,
         subscription_token
     )
     .fetch_optional(db_connection_pool)
     .await
-    .map_err(|e| RetrieveSubscriberError(e))?;
-    Ok(result.map(|r| r.subscriber_id))
+    .map_err(RetrieveSubscriberError)?;
+    Ok(result)
 }
 
+/// Confirms the subscriber together with their membership of `list_id`, or
+/// with every membership still pending if no list is given. Addresses that
+/// bounced or complained stay out of the mailing list.
+/// Returns `false` if there is no such subscriber who can be emailed.
 #[tracing::instrument(
     name = "Mark subscription as confirmed",
-    skip(subscriber_id, db_connection_pool)
+    skip(subscriber_id, transaction)
 )]
 pub async fn confirm_subscriber(
-    db_connection_pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
-) -> Result<(), sqlx::Error> {
+    list_id: Option<Uuid>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status='confirmed'
+        WHERE id=$1 AND status NOT IN ('bounced', 'complained')"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
     sqlx::query!(
-        r#"UPDATE subscriptions SET status='confirmed' WHERE id=$1"#,
+        r#"UPDATE list_memberships SET status='confirmed'
+        WHERE subscriber_id=$1
+        AND (list_id=$2 OR ($2::uuid IS NULL AND status='pending confirmation'))"#,
+        subscriber_id,
+        list_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(true)
+}
+
+/// Replaces the email of a subscriber and consumes their email change tokens.
+/// Returns `false`, changing nothing, if the address was taken in the meantime.
+#[tracing::instrument(name = "Changing subscriber email", skip(transaction, new_email))]
+pub async fn change_subscriber_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_email: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET email = $2
+        WHERE id = $1 AND NOT EXISTS (SELECT 1 FROM subscriptions WHERE email = $2)"#,
+        subscriber_id,
+        new_email
+    )
+    .execute(&mut *transaction)
+    .await?;
+    if result.rows_affected() == 0 {
+        // The tokens stay valid: the subscriber can retry once the address is free.
+        return Ok(false);
+    }
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1 AND new_email IS NOT NULL"#,
         subscriber_id
     )
-    .execute(db_connection_pool)
+    .execute(transaction)
     .await?;
-    Ok(())
+
+    Ok(true)
+}
+
+/// Returns `false` if there is no such subscriber, or if their address bounced
+/// or complained: that status must stick, or they could subscribe again.
+#[tracing::instrument(
+    name = "Mark subscription as unsubscribed",
+    skip(subscriber_id, transaction)
+)]
+pub async fn unsubscribe_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status='unsubscribed'
+        WHERE id=$1 AND status NOT IN ('bounced', 'complained')"#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(result.rows_affected() == 1)
 }
 
 #[tracing::instrument(
@@ -166,41 +384,278 @@ pub async fn get_subscriber_id_from_email(
     )
     .fetch_optional(db_connection_pool)
     .await
-    .map_err(|e| RetrieveSubscriberError(e))?;
+    .map_err(RetrieveSubscriberError)?;
     Ok(record.map(|r| r.id))
 }
 
-pub async fn get_subscription_token_from_id(
+/// Whether the address bounced or its owner complained about our emails.
+#[tracing::instrument(
+    name = "Checking if an email is undeliverable",
+    skip(db_connection_pool)
+)]
+pub async fn is_undeliverable(
     db_connection_pool: &PgPool,
-    subscriber_id: Uuid,
-) -> Result<Option<String>, RetrieveTokenError> {
+    subscriber_email: &str,
+) -> Result<bool, sqlx::Error> {
     let record = sqlx::query!(
-        r#"SELECT subscription_token FROM subscription_tokens WHERE subscriber_id=$1"#,
+        r#"SELECT EXISTS (
+            SELECT 1 FROM subscriptions
+            WHERE lower(email) = lower($1) AND status IN ('bounced', 'complained')
+        ) AS "undeliverable!""#,
+        subscriber_email
+    )
+    .fetch_one(db_connection_pool)
+    .await?;
+    Ok(record.undeliverable)
+}
+
+#[tracing::instrument(
+    name = "Deleting the subscription tokens of a subscriber",
+    skip(transaction)
+)]
+pub async fn delete_subscription_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id=$1 AND list_id=$2"#,
+        subscriber_id,
+        list_id
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+/// Subscribers ordered by email, optionally filtered by status and by a part
+/// of their email (case-insensitive).
+#[tracing::instrument(name = "Listing subscribers", skip(db_connection_pool))]
+pub async fn list_subscribers(
+    db_connection_pool: &PgPool,
+    status: Option<&str>,
+    email: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<SubscriberRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberRecord,
+        r#"SELECT id, email, name, status, subscribed_at FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+        AND ($2::text IS NULL OR strpos(lower(email), lower($2)) > 0)
+        ORDER BY email
+        LIMIT $3 OFFSET $4"#,
+        status,
+        email,
+        limit,
+        offset
+    )
+    .fetch_all(db_connection_pool)
+    .await
+}
+
+/// The next `limit` subscribers whose email sorts after `after_email`:
+/// unlike offsets, this never skips rows when the list changes between pages.
+#[tracing::instrument(name = "Listing subscribers after an email", skip(db_connection_pool))]
+pub async fn list_subscribers_after(
+    db_connection_pool: &PgPool,
+    status: Option<&str>,
+    after_email: Option<&str>,
+    limit: i64,
+) -> Result<Vec<SubscriberRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberRecord,
+        r#"SELECT id, email, name, status, subscribed_at FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+        AND ($2::text IS NULL OR email > $2)
+        ORDER BY email
+        LIMIT $3"#,
+        status,
+        after_email,
+        limit
+    )
+    .fetch_all(db_connection_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Retrieving subscriber", skip(db_connection_pool))]
+pub async fn get_subscriber(
+    db_connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberRecord,
+        r#"SELECT id, email, name, status, subscribed_at FROM subscriptions WHERE id = $1"#,
         subscriber_id
     )
     .fetch_optional(db_connection_pool)
     .await
-    .map_err(|e| RetrieveTokenError(e))?;
+}
+
+/// Erases a subscriber together with every row about them: tokens, list
+/// memberships, deliveries, tracking and consent events.
+/// Returns `false` if there is no such subscriber.
+#[tracing::instrument(name = "Deleting subscriber", skip(db_connection_pool))]
+pub async fn delete_subscriber(
+    db_connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = db_connection_pool.begin().await?;
+    // Queued emails are not tied to the subscriber: they go by address, the
+    // current one or one they asked to change to.
+    sqlx::query!(
+        r#"DELETE FROM email_outbox WHERE lower(recipient) IN (
+            SELECT lower(email) FROM subscriptions WHERE id = $1
+            UNION SELECT lower(new_email) FROM subscription_tokens
+            WHERE subscriber_id = $1 AND new_email IS NOT NULL
+        )"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    // The foreign keys cascade, but spelling the tables out keeps erasure
+    // complete should one of them stop doing so.
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM data_request_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM list_memberships WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM digest_queue WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM issue_deliveries WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM issue_events WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    // Consent events can't be deleted on their own: they follow the subscriber.
+    let result = sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Every row we hold about a subscriber, as a JSON document with one entry
+/// per table. Token values are left out: they are secrets, not personal data.
+#[tracing::instrument(name = "Exporting subscriber data", skip(db_connection_pool))]
+pub async fn export_subscriber_data(
+    db_connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<serde_json::Value>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"SELECT json_build_object(
+            'subscription', (SELECT row_to_json(s) FROM (
+                SELECT id, email, name, status, subscribed_at, digest_frequency, paused_until
+                FROM subscriptions WHERE id = $1) s),
+            'list_memberships', (SELECT COALESCE(json_agg(m), '[]') FROM (
+                SELECT m.list_id, l.name AS list_name, m.status, m.subscribed_at
+                FROM list_memberships m JOIN lists l ON l.list_id = m.list_id
+                WHERE m.subscriber_id = $1 ORDER BY m.subscribed_at) m),
+            'subscription_tokens', (SELECT COALESCE(json_agg(t), '[]') FROM (
+                SELECT list_id, new_email, issued_at FROM subscription_tokens
+                WHERE subscriber_id = $1 ORDER BY issued_at) t),
+            'data_request_tokens', (SELECT COALESCE(json_agg(t), '[]') FROM (
+                SELECT expires_at FROM data_request_tokens
+                WHERE subscriber_id = $1 ORDER BY expires_at) t),
+            'queued_deliveries', (SELECT COALESCE(json_agg(q), '[]') FROM (
+                SELECT newsletter_issue_id, n_retries, execute_after, digest
+                FROM issue_delivery_queue
+                WHERE subscriber_id = $1 ORDER BY execute_after) q),
+            'deliveries', (SELECT COALESCE(json_agg(d), '[]') FROM (
+                SELECT newsletter_issue_id, status, last_error, updated_at FROM issue_deliveries
+                WHERE subscriber_id = $1 ORDER BY updated_at) d),
+            'issue_events', (SELECT COALESCE(json_agg(e), '[]') FROM (
+                SELECT newsletter_issue_id, kind, url, occurred_at FROM issue_events
+                WHERE subscriber_id = $1 ORDER BY occurred_at) e),
+            'subscription_events', (SELECT COALESCE(json_agg(e), '[]') FROM (
+                SELECT kind, email, ip_address, user_agent, detail, occurred_at
+                FROM subscription_events WHERE subscriber_id = $1 ORDER BY event_id) e),
+            'queued_emails', (SELECT COALESCE(json_agg(o), '[]') FROM (
+                SELECT recipient, subject, text_content, html_content, execute_after
+                FROM email_outbox WHERE lower(recipient) IN (
+                    SELECT lower(email) FROM subscriptions WHERE id = $1
+                    UNION SELECT lower(new_email) FROM subscription_tokens
+                    WHERE subscriber_id = $1 AND new_email IS NOT NULL
+                ) ORDER BY execute_after) o)
+        ) AS "data!"
+        FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(db_connection_pool)
+    .await?;
 
-    Ok(record.map(|r| r.subscription_token))
+    Ok(record.map(|r| r.data))
 }
 
 pub struct ConfirmedSubscriber {
+    pub id: Uuid,
     pub email: SubscriberEmail,
 }
 
+/// The confirmed members of a list. Subscribers who bounced, complained or
+/// unsubscribed have another status and are left out, as are those who paused
+/// deliveries.
 pub async fn get_confirmed_subscribers(
     db_connection_pool: &PgPool,
+    list_id: Uuid,
 ) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, RetrieveSubscriberError> {
-    let rows = sqlx::query!(r#"SELECT email FROM subscriptions WHERE status='confirmed'"#,)
-        .fetch_all(db_connection_pool)
-        .await
-        .map_err(|e| RetrieveSubscriberError(e))?;
+    let rows = sqlx::query!(
+        r#"SELECT s.id, s.email FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        WHERE s.status='confirmed' AND m.list_id=$1 AND m.status='confirmed'
+        AND (s.paused_until IS NULL OR s.paused_until < current_date)"#,
+        list_id
+    )
+    .fetch_all(db_connection_pool)
+    .await
+    .map_err(RetrieveSubscriberError)?;
 
     let confirmed_subscribers = rows
         .into_iter()
         .map(|row| match SubscriberEmail::parse(row.email) {
             Ok(subscriber_email) => Ok(ConfirmedSubscriber {
+                id: row.id,
                 email: subscriber_email,
             }),
             Err(error) => Err(anyhow::anyhow!(error)),
@@ -209,3 +664,728 @@ pub async fn get_confirmed_subscribers(
 
     Ok(confirmed_subscribers)
 }
+
+#[derive(serde::Serialize)]
+pub struct StoredIssue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub status: String,
+    pub slug: Option<String>,
+    pub tracking_enabled: bool,
+    pub send_at: Option<DateTime<Utc>>,
+    pub published_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Saving draft issue in database", skip_all)]
+pub async fn insert_draft_issue(
+    db_connection_pool: &PgPool,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    tracking_enabled: bool,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"INSERT INTO newsletter_issues
+            (newsletter_issue_id, title, text_content, html_content, tracking_enabled, status)
+        VALUES ($1, $2, $3, $4, $5, 'draft')"#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        tracking_enabled
+    )
+    .execute(db_connection_pool)
+    .await?;
+
+    Ok(newsletter_issue_id)
+}
+
+/// Returns `false` if the issue is not (or no longer) a draft.
+#[tracing::instrument(
+    name = "Updating draft issue",
+    skip(db_connection_pool, text_content, html_content)
+)]
+pub async fn update_draft_issue(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    tracking_enabled: bool,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, tracking_enabled = $5,
+            updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'draft'"#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        tracking_enabled
+    )
+    .execute(db_connection_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Returns `false` if the issue is not (or no longer) a draft.
+#[tracing::instrument(name = "Deleting draft issue", skip(db_connection_pool))]
+pub async fn delete_draft_issue(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM newsletter_issues WHERE newsletter_issue_id = $1 AND status = 'draft'"#,
+        newsletter_issue_id
+    )
+    .execute(db_connection_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(name = "Retrieving newsletter issue", skip(db_connection_pool))]
+pub async fn get_newsletter_issue(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<StoredIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredIssue,
+        r#"SELECT newsletter_issue_id, title, text_content, html_content, status, slug,
+            tracking_enabled, send_at, published_at, updated_at
+        FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_optional(db_connection_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Listing newsletter issues", skip(db_connection_pool))]
+pub async fn list_newsletter_issues(
+    db_connection_pool: &PgPool,
+) -> Result<Vec<StoredIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredIssue,
+        r#"SELECT newsletter_issue_id, title, text_content, html_content, status, slug,
+            tracking_enabled, send_at, published_at, updated_at
+        FROM newsletter_issues ORDER BY updated_at DESC"#
+    )
+    .fetch_all(db_connection_pool)
+    .await
+}
+
+/// Returns `false` if the issue is not (or no longer) a draft.
+#[tracing::instrument(name = "Publishing draft issue", skip(transaction))]
+pub async fn publish_draft_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    slug: &IssueSlug,
+    list_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE newsletter_issues
+        SET status = 'published', slug = $2, list_id = $3, published_at = now(),
+            updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'draft'"#,
+        newsletter_issue_id,
+        slug.as_ref(),
+        list_id
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Returns `false` if the issue is not (or no longer) a draft.
+#[tracing::instrument(name = "Scheduling draft issue", skip(transaction))]
+pub async fn schedule_draft_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    send_at: DateTime<Utc>,
+    list_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE newsletter_issues
+        SET status = 'scheduled', send_at = $2, list_id = $3, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'draft'"#,
+        newsletter_issue_id,
+        send_at,
+        list_id
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(
+    name = "Enqueueing newsletter issue delivery tasks",
+    skip(transaction, subscriber_ids)
+)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    // Issues for subscribers on a digest wait for the start of the next day or
+    // week, to be sent along with the others published in the meantime.
+    sqlx::query!(
+        r#"INSERT INTO issue_delivery_queue
+            (newsletter_issue_id, subscriber_id, execute_after, digest)
+        SELECT $1, s.id, CASE s.digest_frequency
+            WHEN 'daily' THEN date_trunc('day', now()) + interval '1 day'
+            WHEN 'weekly' THEN date_trunc('week', now()) + interval '1 week'
+            ELSE now() END, s.digest_frequency <> 'immediate'
+        FROM subscriptions s WHERE s.id = ANY($2::uuid[])"#,
+        newsletter_issue_id,
+        subscriber_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // A digest already pending for the subscriber picks the issue up.
+    sqlx::query!(
+        r#"INSERT INTO digest_queue (subscriber_id, execute_after)
+        SELECT subscriber_id, execute_after FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND digest
+        ON CONFLICT (subscriber_id) DO NOTHING"#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_id, status)
+        SELECT $1, subscriber_id, 'queued' FROM UNNEST($2::uuid[]) AS subscriber_id"#,
+        newsletter_issue_id,
+        subscriber_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Retrieving newsletter issue status", skip(db_connection_pool))]
+pub async fn get_newsletter_issue_status(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let status = sqlx::query!(
+        r#"SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_optional(db_connection_pool)
+    .await?
+    .map(|r| r.status);
+
+    Ok(status)
+}
+
+/// Returns `false` if the issue is not (or no longer) scheduled.
+#[tracing::instrument(
+    name = "Cancelling scheduled newsletter issue",
+    skip(db_connection_pool)
+)]
+pub async fn cancel_scheduled_issue(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE newsletter_issues SET status = 'cancelled', updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'"#,
+        newsletter_issue_id
+    )
+    .execute(db_connection_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Returns `false` if the issue is not (or no longer) scheduled.
+#[tracing::instrument(name = "Rescheduling newsletter issue", skip(db_connection_pool))]
+pub async fn reschedule_issue(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    send_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE newsletter_issues SET send_at = $2, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'"#,
+        newsletter_issue_id,
+        send_at
+    )
+    .execute(db_connection_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub struct PublishedIssue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub slug: String,
+    pub html_content: String,
+    pub published_at: DateTime<Utc>,
+}
+
+/// Published issues, most recent first.
+#[tracing::instrument(name = "Listing published issues", skip(db_connection_pool))]
+pub async fn list_published_issues(
+    db_connection_pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PublishedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        PublishedIssue,
+        r#"SELECT newsletter_issue_id, title, slug AS "slug!", html_content,
+            published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE status = 'published' AND slug IS NOT NULL
+        ORDER BY published_at DESC, newsletter_issue_id
+        LIMIT $1 OFFSET $2"#,
+        limit,
+        offset
+    )
+    .fetch_all(db_connection_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Retrieving published issue", skip(db_connection_pool))]
+pub async fn get_published_issue(
+    db_connection_pool: &PgPool,
+    slug: &str,
+) -> Result<Option<StoredIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredIssue,
+        r#"SELECT newsletter_issue_id, title, text_content, html_content, status, slug,
+            tracking_enabled, send_at, published_at, updated_at
+        FROM newsletter_issues WHERE slug = $1 AND status = 'published'"#,
+        slug
+    )
+    .fetch_optional(db_connection_pool)
+    .await
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueDelivery {
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub status: String,
+    pub last_error: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The number of deliveries of an issue in each status.
+#[tracing::instrument(name = "Counting issue deliveries", skip(db_connection_pool))]
+pub async fn count_issue_deliveries(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    let counts = sqlx::query!(
+        r#"SELECT status, COUNT(*) AS "count!" FROM issue_deliveries
+        WHERE newsletter_issue_id = $1 GROUP BY status"#,
+        newsletter_issue_id
+    )
+    .fetch_all(db_connection_pool)
+    .await?
+    .into_iter()
+    .map(|r| (r.status, r.count))
+    .collect();
+
+    Ok(counts)
+}
+
+#[tracing::instrument(name = "Listing issue deliveries", skip(db_connection_pool))]
+pub async fn list_issue_deliveries(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    status: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<IssueDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueDelivery,
+        r#"SELECT d.subscriber_id, s.email, d.status, d.last_error, d.updated_at
+        FROM issue_deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.newsletter_issue_id = $1 AND ($2::text IS NULL OR d.status = $2)
+        ORDER BY s.email
+        LIMIT $3 OFFSET $4"#,
+        newsletter_issue_id,
+        status,
+        limit,
+        offset
+    )
+    .fetch_all(db_connection_pool)
+    .await
+}
+
+/// Moves a subscriber who can no longer be emailed out of the mailing list,
+/// flagging the deliveries the provider reports on when `bounced_delivery` is set
+/// (a digest carries several issues under one message id).
+/// The deliveries named by `message_id` tell who it was sent to, whatever their
+/// address is now; other emails are matched on the address.
+/// Returns the id of the subscriber, `None` if no subscriber has the given email.
+#[tracing::instrument(name = "Flagging undeliverable subscriber", skip(transaction))]
+pub async fn flag_undeliverable_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    message_id: Option<&str>,
+    status: &str,
+    bounced_delivery: bool,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let mut delivered_to = None;
+    if let Some(message_id) = message_id {
+        delivered_to = sqlx::query!(
+            r#"SELECT DISTINCT subscriber_id FROM issue_deliveries WHERE message_id = $1"#,
+            message_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        .map(|r| r.subscriber_id);
+    }
+    let Some(subscriber_id) = sqlx::query!(
+        r#"UPDATE subscriptions SET status = $3
+        WHERE id = $1 OR ($1::uuid IS NULL AND lower(email) = lower($2))
+        RETURNING id"#,
+        delivered_to,
+        email,
+        status
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .map(|r| r.id) else {
+        return Ok(None);
+    };
+
+    if let (true, Some(message_id)) = (bounced_delivery, message_id) {
+        sqlx::query!(
+            r#"UPDATE issue_deliveries SET status = 'bounced', updated_at = now()
+            WHERE message_id = $1"#,
+            message_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    Ok(Some(subscriber_id))
+}
+
+#[tracing::instrument(name = "Recording issue event", skip(db_connection_pool))]
+pub async fn record_issue_event(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    kind: &str,
+    url: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO issue_events (newsletter_issue_id, subscriber_id, kind, url)
+        VALUES ($1, $2, $3, $4)"#,
+        newsletter_issue_id,
+        subscriber_id,
+        kind,
+        url
+    )
+    .execute(db_connection_pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueEngagement {
+    pub tracking_enabled: bool,
+    pub delivered: i64,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+}
+
+/// How many subscribers received, opened and clicked through an issue.
+#[tracing::instrument(name = "Retrieving issue engagement", skip(db_connection_pool))]
+pub async fn get_issue_engagement(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueEngagement>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueEngagement,
+        r#"SELECT i.tracking_enabled,
+            (SELECT COUNT(*) FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND d.status = 'sent') AS "delivered!",
+            (SELECT COUNT(DISTINCT e.subscriber_id) FROM issue_events e
+                WHERE e.newsletter_issue_id = i.newsletter_issue_id
+                AND e.kind = 'open') AS "unique_opens!",
+            (SELECT COUNT(DISTINCT e.subscriber_id) FROM issue_events e
+                WHERE e.newsletter_issue_id = i.newsletter_issue_id
+                AND e.kind = 'click') AS "unique_clicks!"
+        FROM newsletter_issues i WHERE i.newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_optional(db_connection_pool)
+    .await
+}
+
+#[derive(serde::Serialize)]
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub name: String,
+    pub is_default: bool,
+    pub confirmed_members: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Listing mailing lists", skip(db_connection_pool))]
+pub async fn list_mailing_lists(
+    db_connection_pool: &PgPool,
+) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"SELECT l.list_id, l.name, l.is_default, l.created_at,
+            (SELECT COUNT(*) FROM list_memberships m
+                JOIN subscriptions s ON s.id = m.subscriber_id
+                WHERE m.list_id = l.list_id AND m.status = 'confirmed'
+                AND s.status = 'confirmed') AS "confirmed_members!"
+        FROM lists l ORDER BY l.name"#
+    )
+    .fetch_all(db_connection_pool)
+    .await
+}
+
+/// Returns `None` if a list with the same name exists.
+#[tracing::instrument(name = "Creating mailing list", skip(db_connection_pool))]
+pub async fn insert_mailing_list(
+    db_connection_pool: &PgPool,
+    name: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"INSERT INTO lists (list_id, name) VALUES ($1, $2)
+        ON CONFLICT (name) DO NOTHING
+        RETURNING list_id"#,
+        Uuid::new_v4(),
+        name
+    )
+    .fetch_optional(db_connection_pool)
+    .await?;
+
+    Ok(record.map(|r| r.list_id))
+}
+
+/// Checks that `list_id` exists, falling back to the default list when no
+/// list is requested.
+#[tracing::instrument(name = "Resolving mailing list", skip(db_connection_pool))]
+pub async fn resolve_list_id(
+    db_connection_pool: &PgPool,
+    list_id: Option<Uuid>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"SELECT list_id FROM lists
+        WHERE list_id = $1 OR ($1::uuid IS NULL AND is_default)"#,
+        list_id
+    )
+    .fetch_optional(db_connection_pool)
+    .await?;
+
+    Ok(record.map(|r| r.list_id))
+}
+
+/// What a subscriber can change from the preference center.
+#[derive(serde::Serialize)]
+pub struct SubscriberPreferences {
+    pub name: String,
+    pub email: String,
+    /// The address waiting to be confirmed, if the subscriber asked to change it.
+    pub pending_email: Option<String>,
+    pub digest_frequency: String,
+    pub paused_until: Option<NaiveDate>,
+}
+
+#[tracing::instrument(name = "Retrieving subscriber preferences", skip(db_connection_pool))]
+pub async fn get_subscriber_preferences(
+    db_connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberPreferences>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberPreferences,
+        r#"SELECT s.name, s.email, s.digest_frequency, s.paused_until,
+            (SELECT t.new_email FROM subscription_tokens t
+                WHERE t.subscriber_id = s.id AND t.new_email IS NOT NULL
+                ORDER BY t.issued_at DESC LIMIT 1) AS pending_email
+        FROM subscriptions s WHERE s.id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(db_connection_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Updating subscriber preferences", skip(transaction, name))]
+pub async fn update_subscriber_preferences(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+    digest_frequency: DigestFrequency,
+    paused_until: Option<NaiveDate>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET name = $2, digest_frequency = $3, paused_until = $4
+        WHERE id = $1"#,
+        subscriber_id,
+        name.as_ref(),
+        digest_frequency.as_str(),
+        paused_until
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Appends an event to the consent audit trail of a subscriber, together with
+/// their current email.
+#[tracing::instrument(name = "Recording subscription event", skip(executor))]
+pub async fn record_subscription_event(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    kind: SubscriptionEventKind,
+    source: &EventSource,
+    detail: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO subscription_events
+            (subscriber_id, kind, email, ip_address, user_agent, detail)
+        SELECT id, $2, email, $3, $4, $5 FROM subscriptions WHERE id = $1"#,
+        subscriber_id,
+        kind.as_str(),
+        source.ip_address,
+        source.user_agent,
+        detail
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionEvent {
+    pub kind: String,
+    pub email: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub detail: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// The consent audit trail of a subscriber, oldest event first.
+#[tracing::instrument(name = "Listing subscription events", skip(db_connection_pool))]
+pub async fn list_subscription_events(
+    db_connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<SubscriptionEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriptionEvent,
+        r#"SELECT kind, email, ip_address, user_agent, detail, occurred_at
+        FROM subscription_events WHERE subscriber_id = $1 ORDER BY event_id"#,
+        subscriber_id
+    )
+    .fetch_all(db_connection_pool)
+    .await
+}
+
+/// Stores a token letting the subscriber export or erase their data, and
+/// returns it in clear. Only its SHA-256 digest is persisted.
+#[tracing::instrument(name = "Issuing data request token", skip(executor))]
+pub async fn issue_data_request_token(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    ttl: chrono::Duration,
+) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+    sqlx::query!(
+        r#"INSERT INTO data_request_tokens (token_hash, subscriber_id, expires_at)
+        VALUES ($1, $2, $3)"#,
+        hash_token(&token),
+        subscriber_id,
+        Utc::now() + ttl
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(token)
+}
+
+/// Returns the subscriber the token was issued to, if it has not expired.
+#[tracing::instrument(name = "Validating data request token", skip_all)]
+pub async fn get_subscriber_id_from_data_request_token(
+    db_connection_pool: &PgPool,
+    token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"SELECT subscriber_id FROM data_request_tokens
+        WHERE token_hash = $1 AND expires_at > now()"#,
+        hash_token(token)
+    )
+    .fetch_optional(db_connection_pool)
+    .await?;
+
+    Ok(record.map(|r| r.subscriber_id))
+}
+
+/// Data request links work once: the token goes once it has been used.
+#[tracing::instrument(name = "Deleting data request token", skip_all)]
+pub async fn delete_data_request_token(
+    db_connection_pool: &PgPool,
+    token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM data_request_tokens WHERE token_hash = $1"#,
+        hash_token(token)
+    )
+    .execute(db_connection_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The username and email of a user.
+#[tracing::instrument(name = "Get user contact details", skip(db_connection_pool))]
+pub async fn get_user_contact(
+    db_connection_pool: &PgPool,
+    user_id: Uuid,
+) -> Result<(String, Option<String>), sqlx::Error> {
+    let record = sqlx::query!(
+        r#"SELECT username, email FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(db_connection_pool)
+    .await?;
+
+    Ok((record.username, record.email))
+}
+
+/// Sets the address password reset links are sent to.
+/// Returns `false` if another user has this address.
+#[tracing::instrument(name = "Set user email", skip(db_connection_pool, email))]
+pub async fn set_user_email(
+    db_connection_pool: &PgPool,
+    user_id: Uuid,
+    email: &SubscriberEmail,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE users SET email = $2
+        WHERE user_id = $1
+        AND NOT EXISTS (SELECT 1 FROM users WHERE email = $2 AND user_id <> $1)"#,
+        user_id,
+        email.as_ref()
+    )
+    .execute(db_connection_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
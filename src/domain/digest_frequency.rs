@@ -0,0 +1,55 @@
+/// How often a subscriber wants to receive issues.
+///
+/// Subscribers on a digest get every issue published in the meantime in a
+/// single email, at the start of the next day (or week).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestFrequency {
+    Immediate,
+    Daily,
+    Weekly,
+}
+
+impl DigestFrequency {
+    pub fn parse(s: &str) -> Result<DigestFrequency, String> {
+        match s {
+            "immediate" => Ok(Self::Immediate),
+            "daily" => Ok(Self::Daily),
+            "weekly" => Ok(Self::Weekly),
+            other => Err(format!(
+                "{} is not a digest frequency: use immediate, daily or weekly.",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestFrequency::Immediate => "immediate",
+            DigestFrequency::Daily => "daily",
+            DigestFrequency::Weekly => "weekly",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::DigestFrequency;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn known_frequencies_round_trip() {
+        for frequency in [
+            DigestFrequency::Immediate,
+            DigestFrequency::Daily,
+            DigestFrequency::Weekly,
+        ] {
+            assert_ok_eq!(DigestFrequency::parse(frequency.as_str()), frequency);
+        }
+    }
+
+    #[test]
+    fn unknown_frequencies_are_rejected() {
+        assert_err!(DigestFrequency::parse("Daily"));
+        assert_err!(DigestFrequency::parse("monthly"));
+    }
+}
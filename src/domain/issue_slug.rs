@@ -0,0 +1,89 @@
+use reqwest::Url;
+use uuid::Uuid;
+
+/// The URL-friendly name of a published issue, e.g. `spring-news-5f0c2e1a`.
+///
+/// The title makes it readable, the prefix of the issue id keeps it unique
+/// when two issues share a title.
+#[derive(Debug)]
+pub struct IssueSlug(String);
+
+/// How much of the title, in bytes, makes it into the slug.
+const MAX_TITLE_LENGTH: usize = 60;
+
+impl IssueSlug {
+    pub fn new(title: &str, newsletter_issue_id: Uuid) -> Self {
+        let mut slug = String::new();
+        for word in title
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+        {
+            if slug.len() + word.len() > MAX_TITLE_LENGTH {
+                // A first word too long to fit is cut rather than dropped.
+                if slug.is_empty() {
+                    slug.push_str(&word[..MAX_TITLE_LENGTH].to_ascii_lowercase());
+                    slug.push('-');
+                }
+                break;
+            }
+            slug.push_str(&word.to_ascii_lowercase());
+            slug.push('-');
+        }
+        if slug.is_empty() {
+            slug.push_str("issue-");
+        }
+        slug.push_str(&newsletter_issue_id.simple().to_string()[..8]);
+        Self(slug)
+    }
+}
+
+impl AsRef<str> for IssueSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The public page of an issue. Issues that aren't published yet have no page,
+/// so they link to the archive instead.
+pub fn issue_url(base_url: &Url, slug: Option<&str>) -> Url {
+    match slug {
+        Some(slug) => base_url.join(&format!("/issues/{}", slug)).unwrap(),
+        None => base_url.join("/issues").unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::domain::IssueSlug;
+
+    fn id() -> Uuid {
+        Uuid::parse_str("5f0c2e1a-0000-4000-8000-000000000000").unwrap()
+    }
+
+    #[test]
+    fn slugs_are_lowercase_words_joined_by_dashes() {
+        let slug = IssueSlug::new("Spring News: what's  NEW?", id());
+        assert_eq!("spring-news-what-s-new-5f0c2e1a", slug.as_ref());
+    }
+
+    #[test]
+    fn titles_without_ascii_words_fall_back_to_a_generic_slug() {
+        let slug = IssueSlug::new("¡¿ ☃ ?!", id());
+        assert_eq!("issue-5f0c2e1a", slug.as_ref());
+    }
+
+    #[test]
+    fn long_titles_are_truncated_on_a_word_boundary() {
+        let slug = IssueSlug::new(&"word ".repeat(50), id());
+        assert!(slug.as_ref().len() <= 70);
+        assert!(slug.as_ref().ends_with("-word-5f0c2e1a"));
+    }
+
+    #[test]
+    fn a_long_first_word_is_truncated() {
+        let slug = IssueSlug::new(&format!("{} news", "A".repeat(80)), id());
+        assert_eq!(format!("{}-5f0c2e1a", "a".repeat(60)), slug.as_ref());
+    }
+}
@@ -1,7 +1,13 @@
+mod digest_frequency;
+mod issue_slug;
+mod new_password;
 mod subscriber;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use digest_frequency::DigestFrequency;
+pub use issue_slug::{issue_url, IssueSlug};
+pub use new_password::NewPassword;
 pub use subscriber::Subscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
@@ -0,0 +1,87 @@
+use secrecy::{ExposeSecret, Secret};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A password candidate that satisfies our length and strength rules.
+#[derive(Debug)]
+pub struct NewPassword(Secret<String>);
+
+impl NewPassword {
+    pub fn parse(s: Secret<String>) -> Result<NewPassword, String> {
+        let password = s.expose_secret();
+        let length = password.graphemes(true).count();
+        if length < 12 {
+            return Err("The new password must be at least 12 characters long.".into());
+        }
+        if length > 128 {
+            return Err("The new password must be at most 128 characters long.".into());
+        }
+
+        let has_lowercase = password.chars().any(char::is_lowercase);
+        let has_uppercase = password.chars().any(char::is_uppercase);
+        let has_digit = password.chars().any(|c| c.is_ascii_digit());
+        let has_symbol = password
+            .chars()
+            .any(|c| !c.is_alphanumeric() && !c.is_whitespace());
+        let character_classes = [has_lowercase, has_uppercase, has_digit, has_symbol]
+            .into_iter()
+            .filter(|present| *present)
+            .count();
+        if character_classes < 3 {
+            return Err(
+                "The new password must mix at least three of lowercase letters, \
+                uppercase letters, digits and symbols."
+                    .into(),
+            );
+        }
+
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<Secret<String>> for NewPassword {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+impl From<NewPassword> for Secret<String> {
+    fn from(password: NewPassword) -> Self {
+        password.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::NewPassword;
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    fn parse(s: &str) -> Result<NewPassword, String> {
+        NewPassword::parse(Secret::new(s.to_string()))
+    }
+
+    #[test]
+    fn a_password_shorter_than_12_characters_is_rejected() {
+        assert_err!(parse("Sh0rt-pass"));
+    }
+
+    #[test]
+    fn a_password_longer_than_128_characters_is_rejected() {
+        assert_err!(parse(&format!("Aa1{}", "a".repeat(126))));
+    }
+
+    #[test]
+    fn a_password_with_a_single_character_class_is_rejected() {
+        assert_err!(parse("onlylowercaseletters"));
+    }
+
+    #[test]
+    fn a_password_with_two_character_classes_is_rejected() {
+        assert_err!(parse("lowercaseand12345"));
+    }
+
+    #[test]
+    fn a_long_password_mixing_three_character_classes_is_accepted() {
+        assert_ok!(parse("Correct-horse-battery"));
+    }
+}
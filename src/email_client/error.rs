@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+
+use crate::telemetry::error_chain_fmt;
+
+/// Why an email provider failed to accept an email.
+#[derive(thiserror::Error)]
+pub enum EmailError {
+    /// Outages, timeouts and 5xx responses: trying again later may succeed.
+    #[error("The email provider is temporarily unavailable")]
+    Transient(#[source] anyhow::Error),
+    /// The email itself was rejected (e.g. an invalid or inactive recipient).
+    #[error("The email provider rejected the email")]
+    Permanent(#[source] anyhow::Error),
+    #[error("The email provider is throttling our requests")]
+    RateLimited {
+        retry_after: Option<Duration>,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("The email provider rejected our credentials")]
+    AuthFailure(#[source] anyhow::Error),
+}
+
+impl std::fmt::Debug for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl EmailError {
+    /// Whether sending the very same email again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            EmailError::Transient(_) | EmailError::RateLimited { .. }
+        )
+    }
+
+    /// Classifies a non-successful HTTP response from an email provider.
+    pub(crate) async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs);
+        let body = response.text().await.unwrap_or_default();
+        let source = anyhow::anyhow!("{} response from the email provider: {}", status, body);
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => EmailError::AuthFailure(source),
+            StatusCode::TOO_MANY_REQUESTS => EmailError::RateLimited {
+                retry_after,
+                source,
+            },
+            StatusCode::REQUEST_TIMEOUT => EmailError::Transient(source),
+            status if status.is_server_error() => EmailError::Transient(source),
+            _ => EmailError::Permanent(source),
+        }
+    }
+
+    /// A copy of this error, used when a single failure applies to a whole batch.
+    pub(crate) fn duplicate(&self) -> Self {
+        let source = anyhow::anyhow!("{:#}", self.source_error());
+        match self {
+            EmailError::Transient(_) => EmailError::Transient(source),
+            EmailError::Permanent(_) => EmailError::Permanent(source),
+            EmailError::RateLimited { retry_after, .. } => EmailError::RateLimited {
+                retry_after: *retry_after,
+                source,
+            },
+            EmailError::AuthFailure(_) => EmailError::AuthFailure(source),
+        }
+    }
+
+    fn source_error(&self) -> &anyhow::Error {
+        match self {
+            EmailError::Transient(source)
+            | EmailError::Permanent(source)
+            | EmailError::RateLimited { source, .. }
+            | EmailError::AuthFailure(source) => source,
+        }
+    }
+}
+
+/// Failures to reach the provider (connection errors, timeouts, ...) are
+/// always worth another attempt.
+impl From<reqwest::Error> for EmailError {
+    fn from(e: reqwest::Error) -> Self {
+        EmailError::Transient(e.into())
+    }
+}
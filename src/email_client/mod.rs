@@ -0,0 +1,130 @@
+mod error;
+mod postmark;
+mod retry;
+mod ses;
+mod smtp;
+
+pub use error::EmailError;
+pub use postmark::PostmarkSender;
+pub use retry::{RetryPolicy, RetryingSender};
+pub use ses::SesSender;
+pub use smtp::SmtpSender;
+
+use crate::domain::SubscriberEmail;
+
+/// A custom header added to an outgoing email, e.g. `List-Unsubscribe`.
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl EmailHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+/// A fully assembled email, ready to be handed over to a provider.
+#[derive(Clone, Copy)]
+pub struct Email<'a> {
+    pub sender: &'a SubscriberEmail,
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub text_content: &'a str,
+    pub html_content: &'a str,
+    pub headers: &'a [EmailHeader],
+}
+
+/// An email addressed to a single recipient, owning its (personalised) content.
+pub struct EmailMessage {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub headers: Vec<EmailHeader>,
+}
+
+/// The outcome of one email of a batch: the id the provider gave the email,
+/// if it reports one. Its webhooks refer to the email by this id.
+pub type BatchOutcome = Result<Option<String>, EmailError>;
+
+/// A backend able to deliver emails (Postmark, SMTP, Amazon SES, ...).
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, email: &Email<'_>) -> Result<(), EmailError>;
+
+    /// Sends many emails, returning the outcome of each one in the same order.
+    ///
+    /// Backends without a bulk API fall back to one request per email.
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<BatchOutcome> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await.map(|_| None));
+        }
+        outcomes
+    }
+}
+
+/// Sends emails on behalf of the application through the configured backend.
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    backend: Box<dyn EmailSender>,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, backend: Box<dyn EmailSender>) -> Self {
+        Self { sender, backend }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        text_content: &str,
+        html_content: &str,
+    ) -> Result<(), EmailError> {
+        self.send_email_with_headers(recipient, subject, text_content, html_content, &[])
+            .await
+    }
+
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        text_content: &str,
+        html_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), EmailError> {
+        let email = Email {
+            sender: &self.sender,
+            recipient,
+            subject,
+            text_content,
+            html_content,
+            headers,
+        };
+        self.backend.send(&email).await
+    }
+
+    /// Sends every message, reporting failures per recipient rather than
+    /// aborting at the first one.
+    pub async fn send_email_batch(&self, messages: &[EmailMessage]) -> Vec<BatchOutcome> {
+        let emails: Vec<_> = messages
+            .iter()
+            .map(|message| Email {
+                sender: &self.sender,
+                recipient: &message.recipient,
+                subject: &message.subject,
+                text_content: &message.text_content,
+                html_content: &message.html_content,
+                headers: &message.headers,
+            })
+            .collect();
+        self.backend.send_batch(&emails).await
+    }
+}
@@ -0,0 +1,483 @@
+use reqwest::{Client, Response, Url};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{BatchOutcome, Email, EmailError, EmailHeader, EmailSender};
+
+/// Postmark accepts at most 500 messages per `/email/batch` call.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Delivers emails through Postmark's `/email` API.
+pub struct PostmarkSender {
+    client: Client,
+    base_url: Url,
+    authorization_token: Secret<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEMailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader],
+}
+
+impl<'a> From<&Email<'a>> for SendEMailRequest<'a> {
+    fn from(email: &Email<'a>) -> Self {
+        Self {
+            from: email.sender.as_ref(),
+            to: email.recipient.as_ref(),
+            subject: email.subject,
+            html_body: email.html_content,
+            text_body: email.text_content,
+            headers: email.headers,
+        }
+    }
+}
+
+/// The outcome of a single message within a batch.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchMessageResult {
+    error_code: i64,
+    message: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
+impl PostmarkSender {
+    pub fn new(
+        base_url: Url,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            client: Client::builder().timeout(timeout).build().unwrap(),
+            base_url,
+            authorization_token,
+        }
+    }
+
+    async fn post<T: serde::Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<Response, EmailError> {
+        let url = self.base_url.join(path).unwrap();
+        let response = self
+            .client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(body)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(EmailError::from_response(response).await)
+        }
+    }
+
+    async fn send_chunk(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<BatchMessageResult>, EmailError> {
+        let request_body: Vec<_> = emails.iter().map(SendEMailRequest::from).collect();
+        // Postmark has accepted the batch by now: failing to read its answer
+        // must not lead to the emails being sent again.
+        let results: Vec<BatchMessageResult> = self
+            .post("/email/batch", &request_body)
+            .await?
+            .json()
+            .await
+            .map_err(|e| EmailError::Permanent(e.into()))?;
+
+        if results.len() != emails.len() {
+            // Some of the emails may have gone out already: sending them again
+            // is worse than dropping them.
+            return Err(EmailError::Permanent(anyhow::anyhow!(
+                "Postmark returned {} results for a batch of {} emails",
+                results.len(),
+                emails.len()
+            )));
+        }
+        Ok(results)
+    }
+}
+
+impl BatchMessageResult {
+    /// Maps Postmark's per-message error codes onto [`EmailError`].
+    fn into_outcome(self) -> BatchOutcome {
+        let source = || {
+            anyhow::anyhow!(
+                "Postmark rejected the email (error code {}): {}",
+                self.error_code,
+                self.message
+            )
+        };
+        match self.error_code {
+            0 => Ok(self.message_id),
+            // Bad or missing server token.
+            10 => Err(EmailError::AuthFailure(source())),
+            429 => Err(EmailError::RateLimited {
+                retry_after: None,
+                source: source(),
+            }),
+            _ => Err(EmailError::Permanent(source())),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), EmailError> {
+        self.post("/email", &SendEMailRequest::from(email)).await?;
+        Ok(())
+    }
+
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<BatchOutcome> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(results) => outcomes.extend(results.into_iter().map(|r| r.into_outcome())),
+                Err(error) => outcomes.extend(chunk.iter().map(|_| Err(error.duplicate()))),
+            }
+        }
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailClient, EmailError, EmailHeader, EmailMessage, PostmarkSender},
+    };
+    use claim::{assert_err, assert_ok, assert_ok_eq};
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake, Faker,
+    };
+    use reqwest::Url;
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, body_partial_json, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    struct SendEmailBodyMatcher;
+
+    // implementing Match trait to check if the body received by the wiremock server
+    // contains the expected fields
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("TextBody").is_some()
+                    && body.get("HtmlBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    /// Answers a batch request with one successful result per message.
+    struct BatchResponder;
+
+    impl wiremock::Respond for BatchResponder {
+        fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+            let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = body
+                .iter()
+                .map(|_| serde_json::json!({"ErrorCode": 0, "Message": "OK"}))
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    fn messages(n: usize) -> Vec<EmailMessage> {
+        (0..n)
+            .map(|_| EmailMessage {
+                recipient: email(),
+                subject: subject(),
+                text_content: content(),
+                html_content: content(),
+                headers: vec![],
+            })
+            .collect()
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        let sender = PostmarkSender::new(
+            Url::parse(&base_url).unwrap(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        );
+        EmailClient::new(email(), Box::new(sender))
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_expected_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let content = content();
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content, &content)
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_forwards_custom_headers() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{"Name": "List-Unsubscribe", "Value": "<https://example.com>"}]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let content = content();
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                &subject(),
+                &content,
+                &content,
+                &[EmailHeader::new(
+                    "List-Unsubscribe",
+                    "<https://example.com>",
+                )],
+            )
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let content = content();
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content, &content)
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let response = ResponseTemplate::new(500).set_delay(std::time::Duration::from_secs(120));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let content = content();
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content, &content)
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_splits_recipients_in_chunks_of_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .and(header_exists("X-Postmark-Server-Token"))
+            .respond_with(BatchResponder)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_email_batch(&messages(501)).await;
+
+        assert_eq!(501, outcomes.len());
+        assert!(outcomes.iter().all(|outcome| outcome.is_ok()));
+        let requests = mock_server.received_requests().await.unwrap();
+        let sizes: Vec<_> = requests
+            .iter()
+            .map(|r| {
+                serde_json::from_slice::<Vec<serde_json::Value>>(&r.body)
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert_eq!(vec![500, 1], sizes);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_reports_failures_per_recipient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK", "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817"},
+                {"ErrorCode": 406, "Message": "Inactive recipient"},
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_email_batch(&messages(2)).await;
+
+        assert_ok_eq!(
+            &outcomes[0],
+            &Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817".to_string())
+        );
+        assert_err!(&outcomes[1]);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_fails_every_recipient_if_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_email_batch(&messages(3)).await;
+
+        assert_eq!(3, outcomes.len());
+        assert!(outcomes.iter().all(|outcome| outcome.is_err()));
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_is_not_retried_if_the_response_cannot_be_read() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("not json"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_email_batch(&messages(2)).await;
+
+        assert!(outcomes
+            .iter()
+            .all(|outcome| matches!(outcome, Err(EmailError::Permanent(_)))));
+    }
+
+    #[tokio::test]
+    async fn send_email_reports_rate_limiting_with_the_retry_after_delay() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let content = content();
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content, &content)
+            .await;
+
+        assert!(matches!(
+            outcome,
+            Err(EmailError::RateLimited {
+                retry_after: Some(delay),
+                ..
+            }) if delay == std::time::Duration::from_secs(7)
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_email_errors_are_classified_by_status_code() {
+        let cases = [
+            (401, "auth failure"),
+            (422, "permanent"),
+            (500, "transient"),
+        ];
+        for (status, expected) in cases {
+            let mock_server = MockServer::start().await;
+            let email_client = email_client(mock_server.uri());
+            Mock::given(any())
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&mock_server)
+                .await;
+
+            let content = content();
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content, &content)
+                .await;
+
+            let actual = match outcome {
+                Err(EmailError::AuthFailure(_)) => "auth failure",
+                Err(EmailError::Permanent(_)) => "permanent",
+                Err(EmailError::Transient(_)) => "transient",
+                _ => "other",
+            };
+            assert_eq!(
+                expected, actual,
+                "Unexpected outcome for a {} response",
+                status
+            );
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use super::{BatchOutcome, Email, EmailError, EmailSender};
+
+/// How many times, and how far apart, failed sends are attempted again.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with full jitter, unless the provider told us how
+    /// long to wait.
+    fn delay(&self, attempt: u32, error: &EmailError) -> Duration {
+        if let EmailError::RateLimited {
+            retry_after: Some(retry_after),
+            ..
+        } = error
+        {
+            return (*retry_after).min(self.max_delay);
+        }
+        let exponent = attempt.min(16);
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_delay);
+        let jitter = rand::thread_rng().gen_range(0..=cap.as_millis() as u64);
+        Duration::from_millis(jitter)
+    }
+}
+
+/// Wraps a backend, retrying sends that failed with a retryable [`EmailError`].
+pub struct RetryingSender {
+    inner: Box<dyn EmailSender>,
+    policy: RetryPolicy,
+}
+
+impl RetryingSender {
+    pub fn new(inner: Box<dyn EmailSender>, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for RetryingSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), EmailError> {
+        let mut attempt = 1;
+        loop {
+            match self.inner.send(email).await {
+                Err(error) if error.is_retryable() && attempt < self.policy.max_attempts => {
+                    let delay = self.policy.delay(attempt, &error);
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        attempt,
+                        "Failed to send an email. Retrying in {:?}",
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                outcome => return outcome,
+            }
+        }
+    }
+
+    /// Only the emails that failed with a retryable error are sent again.
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<BatchOutcome> {
+        let mut outcomes = self.inner.send_batch(emails).await;
+        let mut attempt = 1;
+        while attempt < self.policy.max_attempts {
+            let pending: Vec<_> = outcomes
+                .iter()
+                .enumerate()
+                .filter(|(_, outcome)| matches!(outcome, Err(e) if e.is_retryable()))
+                .map(|(i, _)| i)
+                .collect();
+            let delay = match pending.first().and_then(|&i| outcomes[i].as_ref().err()) {
+                Some(error) => self.policy.delay(attempt, error),
+                None => break,
+            };
+            tracing::warn!(
+                n_failed = pending.len(),
+                attempt,
+                "Failed to send part of an email batch. Retrying in {:?}",
+                delay
+            );
+            tokio::time::sleep(delay).await;
+
+            let retried: Vec<Email<'_>> = pending.iter().map(|&i| emails[i]).collect();
+            let retried_outcomes = self.inner.send_batch(&retried).await;
+            for (i, outcome) in pending.into_iter().zip(retried_outcomes) {
+                outcomes[i] = outcome;
+            }
+            attempt += 1;
+        }
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claim::{assert_err, assert_ok};
+    use fake::{faker::internet::en::SafeEmail, Fake};
+    use reqwest::Url;
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{RetryPolicy, RetryingSender};
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailClient, EmailError, EmailMessage, PostmarkSender},
+    };
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        let postmark = PostmarkSender::new(
+            Url::parse(&base_url).unwrap(),
+            Secret::new("token".into()),
+            Duration::from_millis(200),
+        );
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+        };
+        EmailClient::new(
+            email(),
+            Box::new(RetryingSender::new(Box::new(postmark), policy)),
+        )
+    }
+
+    #[test]
+    fn backoff_never_exceeds_the_maximum_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+        let error = EmailError::Transient(anyhow::anyhow!("Outage"));
+
+        for attempt in 1..10 {
+            assert!(policy.delay(attempt, &error) <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn retry_after_takes_precedence_over_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(60),
+        };
+        let error = EmailError::RateLimited {
+            retry_after: Some(Duration::from_secs(7)),
+            source: anyhow::anyhow!("Throttled"),
+        };
+
+        assert_eq!(Duration::from_secs(7), policy.delay(1, &error));
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), "Subject", "text", "<p>html</p>")
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_after_max_attempts() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), "Subject", "text", "<p>html</p>")
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_are_not_retried() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), "Subject", "text", "<p>html</p>")
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn only_retryable_batch_failures_are_sent_again() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK"},
+                {"ErrorCode": 406, "Message": "Inactive recipient"},
+                {"ErrorCode": 429, "Message": "Rate limit exceeded"},
+            ])))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!([{"ErrorCode": 0, "Message": "OK"}])),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let messages: Vec<_> = (0..3)
+            .map(|_| EmailMessage {
+                recipient: email(),
+                subject: "Subject".into(),
+                text_content: "text".into(),
+                html_content: "<p>html</p>".into(),
+                headers: vec![],
+            })
+            .collect();
+        let outcomes = email_client.send_email_batch(&messages).await;
+
+        assert_ok!(&outcomes[0]);
+        assert_err!(&outcomes[1]);
+        assert_ok!(&outcomes[2]);
+    }
+}
@@ -0,0 +1,337 @@
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+use super::{Email, EmailError, EmailSender};
+use crate::configuration::SesSettings;
+
+const SEND_EMAIL_PATH: &str = "/v2/email/outbound-emails";
+const SERVICE: &str = "ses";
+
+/// Delivers emails through Amazon SES' v2 HTTP API, signing every request with
+/// AWS Signature Version 4.
+pub struct SesSender {
+    client: Client,
+    base_url: Url,
+    region: String,
+    access_key_id: String,
+    secret_access_key: Secret<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from_email_address: &'a str,
+    destination: Destination<'a>,
+    content: Content<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Destination<'a> {
+    to_addresses: [&'a str; 1],
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Content<'a> {
+    simple: SimpleContent<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SimpleContent<'a> {
+    subject: ContentData<'a>,
+    body: Body<'a>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [super::EmailHeader],
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Body<'a> {
+    text: ContentData<'a>,
+    html: ContentData<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContentData<'a> {
+    data: &'a str,
+    charset: &'static str,
+}
+
+impl<'a> ContentData<'a> {
+    fn utf8(data: &'a str) -> Self {
+        Self {
+            data,
+            charset: "UTF-8",
+        }
+    }
+}
+
+impl SesSender {
+    pub fn new(settings: SesSettings, timeout: std::time::Duration) -> Self {
+        Self {
+            client: Client::builder().timeout(timeout).build().unwrap(),
+            base_url: settings.endpoint(),
+            region: settings.region,
+            access_key_id: settings.access_key_id,
+            secret_access_key: settings.secret_access_key,
+        }
+    }
+
+    /// Builds the `Authorization` header for a JSON `POST` to `url`.
+    fn authorization(&self, url: &Url, amz_date: &str, payload: &[u8]) -> String {
+        let date = &amz_date[..8];
+        let host = host_header(url);
+        let signed_headers = "content-type;host;x-amz-date";
+        let canonical_request = format!(
+            "POST\n{}\n\ncontent-type:application/json\nhost:{}\nx-amz-date:{}\n\n{}\n{}",
+            url.path(),
+            host,
+            amz_date,
+            signed_headers,
+            hex_sha256(payload)
+        );
+        let credential_scope = format!("{}/{}/{}/aws4_request", date, self.region, SERVICE);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            credential_scope,
+            hex_sha256(canonical_request.as_bytes())
+        );
+        let signing_key = signing_key(
+            self.secret_access_key.expose_secret(),
+            date,
+            &self.region,
+            SERVICE,
+        );
+        let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, credential_scope, signed_headers, signature
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SesSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), EmailError> {
+        let url = self.base_url.join(SEND_EMAIL_PATH).unwrap();
+        let request_body = SendEmailRequest {
+            from_email_address: email.sender.as_ref(),
+            destination: Destination {
+                to_addresses: [email.recipient.as_ref()],
+            },
+            content: Content {
+                simple: SimpleContent {
+                    subject: ContentData::utf8(email.subject),
+                    body: Body {
+                        text: ContentData::utf8(email.text_content),
+                        html: ContentData::utf8(email.html_content),
+                    },
+                    headers: email.headers,
+                },
+            },
+        };
+        let payload =
+            serde_json::to_vec(&request_body).map_err(|e| EmailError::Permanent(e.into()))?;
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = self.authorization(&url, &amz_date, &payload);
+
+        let response = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Amz-Date", &amz_date)
+            .header("Authorization", authorization)
+            .body(payload)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(EmailError::from_response(response).await)
+        }
+    }
+}
+
+/// The `Host` header as sent by reqwest: the port is only included when it is
+/// not the scheme's default one.
+fn host_header(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    }
+}
+
+fn signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(
+        format!("AWS4{}", secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use fake::{faker::internet::en::SafeEmail, Fake};
+    use reqwest::Url;
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, body_partial_json, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{hex, signing_key};
+    use crate::{
+        configuration::SesSettings,
+        domain::SubscriberEmail,
+        email_client::{EmailClient, EmailError, EmailHeader, SesSender},
+    };
+
+    struct SigV4AuthorizationMatcher;
+
+    // wiremock splits header values on commas, so the credential, the signed
+    // headers and the signature are checked one by one.
+    impl wiremock::Match for SigV4AuthorizationMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let parts: Vec<_> = match request.headers.get(&"Authorization".into()) {
+                Some(values) => values
+                    .iter()
+                    .map(|v| v.as_str().trim().to_owned())
+                    .collect(),
+                None => return false,
+            };
+            parts.len() == 3
+                && parts[0].starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/")
+                && parts[0].ends_with("/eu-west-1/ses/aws4_request")
+                && parts[1] == "SignedHeaders=content-type;host;x-amz-date"
+                && parts[2].strip_prefix("Signature=").map(str::len) == Some(64)
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        let settings = SesSettings {
+            region: "eu-west-1".into(),
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: Secret::new("secret".into()),
+            base_url: Some(Url::parse(&base_url).unwrap()),
+        };
+        let sender = SesSender::new(settings, std::time::Duration::from_millis(200));
+        EmailClient::new(email(), Box::new(sender))
+    }
+
+    #[test]
+    fn signing_key_matches_the_aws_reference_example() {
+        // Taken from the AWS Signature Version 4 documentation.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_a_signed_request_to_the_v2_api() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/v2/email/outbound-emails"))
+            .and(method("POST"))
+            .and(header("Content-Type", "application/json"))
+            .and(header_exists("X-Amz-Date"))
+            .and(SigV4AuthorizationMatcher)
+            .and(body_partial_json(serde_json::json!({
+                "Content": {"Simple": {
+                    "Subject": {"Data": "Weekly digest"},
+                    "Headers": [{"Name": "List-Unsubscribe", "Value": "<https://example.com>"}]
+                }}
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                "Weekly digest",
+                "text",
+                "<p>html</p>",
+                &[EmailHeader::new(
+                    "List-Unsubscribe",
+                    "<https://example.com>",
+                )],
+            )
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn throttling_is_reported_as_rate_limiting() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), "Subject", "text", "<p>html</p>")
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::RateLimited { .. })));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_server_returns_400() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), "Subject", "text", "<p>html</p>")
+            .await;
+
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::Context;
+use lettre::{
+    message::{
+        header::{HeaderName, HeaderValue},
+        Mailbox, MultiPart,
+    },
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use secrecy::ExposeSecret;
+
+use super::{Email, EmailError, EmailSender};
+use crate::configuration::{SmtpSettings, SmtpTls};
+
+/// Delivers emails to an SMTP relay.
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpSender {
+    pub fn new(
+        settings: &SmtpSettings,
+        timeout: std::time::Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let builder = match settings.tls {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            }
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+        };
+        let mut builder = builder.port(settings.port).timeout(Some(timeout));
+        if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                password.expose_secret().clone(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+fn build_message(email: &Email<'_>) -> Result<Message, anyhow::Error> {
+    let from: Mailbox = email
+        .sender
+        .as_ref()
+        .parse()
+        .context("Invalid sender address")?;
+    let to: Mailbox = email
+        .recipient
+        .as_ref()
+        .parse()
+        .context("Invalid recipient address")?;
+
+    let mut builder = Message::builder().from(from).to(to).subject(email.subject);
+    for header in email.headers {
+        let name = HeaderName::new_from_ascii(header.name.clone())
+            .with_context(|| format!("Invalid header name: {}", header.name))?;
+        builder = builder.raw_header(HeaderValue::new(name, header.value.clone()));
+    }
+
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_content.to_owned(),
+            email.html_content.to_owned(),
+        ))
+        .context("Failed to build the email message")
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), EmailError> {
+        let message = build_message(email).map_err(EmailError::Permanent)?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(classify_smtp_error)?;
+        Ok(())
+    }
+}
+
+/// 4xx replies are transient, 5xx replies permanent (535 being a failed
+/// authentication), anything else is a connection problem worth retrying.
+fn classify_smtp_error(e: lettre::transport::smtp::Error) -> EmailError {
+    let is_auth_failure = e.status().map(u16::from) == Some(535);
+    let is_permanent = e.is_permanent();
+    let source = anyhow::Error::new(e).context("The SMTP relay did not accept the email");
+    if is_auth_failure {
+        EmailError::AuthFailure(source)
+    } else if is_permanent {
+        EmailError::Permanent(source)
+    } else {
+        EmailError::Transient(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+    use fake::{faker::internet::en::SafeEmail, Fake};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::oneshot,
+    };
+
+    use crate::{
+        configuration::{SmtpSettings, SmtpTls},
+        domain::SubscriberEmail,
+        email_client::{EmailClient, EmailError, EmailHeader, SmtpSender},
+    };
+
+    /// A minimal SMTP sink accepting a single message. It answers `rcpt_reply` to
+    /// `RCPT TO` and hands over the received `DATA` section.
+    async fn smtp_sink(rcpt_reply: &'static str) -> (u16, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (data_tx, data_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+            let mut data = String::new();
+            let mut in_data = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        writer.write_all(b"250 Queued\r\n").await.unwrap();
+                    } else {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let command = line.to_uppercase();
+                let reply = if command.starts_with("EHLO") {
+                    "250 localhost"
+                } else if command.starts_with("RCPT") {
+                    rcpt_reply
+                } else if command.starts_with("DATA") {
+                    in_data = true;
+                    "354 End data with <CR><LF>.<CR><LF>"
+                } else if command.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    "250 OK"
+                };
+                writer
+                    .write_all(format!("{}\r\n", reply).as_bytes())
+                    .await
+                    .unwrap();
+            }
+            let _ = data_tx.send(data);
+        });
+
+        (port, data_rx)
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(port: u16) -> EmailClient {
+        let settings = SmtpSettings {
+            host: "127.0.0.1".into(),
+            port,
+            username: None,
+            password: None,
+            tls: SmtpTls::None,
+        };
+        let sender = SmtpSender::new(&settings, std::time::Duration::from_secs(2)).unwrap();
+        EmailClient::new(email(), Box::new(sender))
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_message_to_the_relay() {
+        let (port, data) = smtp_sink("250 OK").await;
+        let email_client = email_client(port);
+
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                "Weekly digest",
+                "Plain text body",
+                "<p>HTML body</p>",
+                &[EmailHeader::new(
+                    "List-Unsubscribe",
+                    "<https://example.com>",
+                )],
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let data = data.await.unwrap();
+        assert!(data.contains("Subject: Weekly digest"));
+        assert!(data.contains("List-Unsubscribe: <https://example.com>"));
+        assert!(data.contains("Plain text body"));
+        assert!(data.contains("<p>HTML body</p>"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_relay_rejects_the_recipient() {
+        let (port, _data) = smtp_sink("550 No such user").await;
+        let email_client = email_client(port);
+
+        let outcome = email_client
+            .send_email(&email(), "Subject", "text", "<p>html</p>")
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn temporary_relay_rejections_are_transient() {
+        let (port, _data) = smtp_sink("451 Try again later").await;
+        let email_client = email_client(port);
+
+        let outcome = email_client
+            .send_email(&email(), "Subject", "text", "<p>html</p>")
+            .await;
+
+        assert!(matches!(outcome, Err(EmailError::Transient(_))));
+    }
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{IssueDeliverySettings, Settings},
+    domain::SubscriberEmail,
+    email_client::{EmailClient, EmailError},
+    issue_delivery_worker::ExecutionOutcome,
+};
+
+struct OutboxEmail {
+    email_id: Uuid,
+    recipient: String,
+    subject: String,
+    text_content: String,
+    html_content: String,
+    n_retries: i16,
+}
+
+/// Queues an email for the outbox worker. Enqueued within the transaction of
+/// the request, the email goes out if and only if the request's changes are
+/// committed, and the request never waits for the email provider.
+#[tracing::instrument(skip(executor, text_content, html_content))]
+pub async fn enqueue_email(
+    executor: impl PgExecutor<'_>,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO email_outbox (email_id, recipient, subject, text_content, html_content)
+        VALUES ($1, $2, $3, $4, $5)"#,
+        Uuid::new_v4(),
+        recipient.as_ref(),
+        subject,
+        text_content,
+        html_content
+    )
+    .execute(executor)
+    .await
+    .context("Failed to enqueue the email")?;
+    Ok(())
+}
+
+/// Sends the emails of the outbox, with the polling and retry policy of the
+/// issue deliveries.
+pub struct EmailOutboxWorker {
+    db_connection_pool: PgPool,
+    email_client: EmailClient,
+    settings: IssueDeliverySettings,
+}
+
+impl EmailOutboxWorker {
+    pub fn build(configuration: Settings, db_connection_pool: PgPool) -> Self {
+        Self {
+            db_connection_pool,
+            email_client: configuration.email_client.client_without_retries(),
+            settings: configuration.issue_delivery,
+        }
+    }
+
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        loop {
+            match self.try_execute_task().await {
+                Ok(ExecutionOutcome::EmptyQueue) => {
+                    tokio::time::sleep(self.settings.poll_interval()).await;
+                }
+                Ok(ExecutionOutcome::TaskCompleted) => {}
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    /// Sends the next ready email. Failed emails go back to the outbox, to be
+    /// retried once their delay has passed.
+    #[tracing::instrument(skip_all, fields(email_id = tracing::field::Empty), err)]
+    pub async fn try_execute_task(&self) -> Result<ExecutionOutcome, anyhow::Error> {
+        let mut transaction = self
+            .db_connection_pool
+            .begin()
+            .await
+            .context("Failed to begin the transaction")?;
+        let email = sqlx::query_as!(
+            OutboxEmail,
+            r#"SELECT email_id, recipient, subject, text_content, html_content, n_retries
+            FROM email_outbox
+            WHERE execute_after <= now()
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1"#
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to dequeue an email")?;
+        let email = match email {
+            Some(email) => email,
+            None => return Ok(ExecutionOutcome::EmptyQueue),
+        };
+        tracing::Span::current().record("email_id", tracing::field::display(email.email_id));
+
+        let outcome = match SubscriberEmail::parse(email.recipient.clone()) {
+            Ok(recipient) => {
+                self.email_client
+                    .send_email(
+                        &recipient,
+                        &email.subject,
+                        &email.text_content,
+                        &email.html_content,
+                    )
+                    .await
+            }
+            Err(error) => Err(EmailError::Permanent(anyhow::anyhow!(error))),
+        };
+        match outcome {
+            Err(error) if error.is_retryable() && email.n_retries < self.settings.max_retries => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    error.message = %error,
+                    n_retries = email.n_retries,
+                    "Failed to send an email. Retrying later"
+                );
+                let mut delay = self.settings.retry_delay(email.n_retries);
+                if let EmailError::RateLimited {
+                    retry_after: Some(retry_after),
+                    ..
+                } = error
+                {
+                    delay = delay.max(retry_after);
+                }
+                sqlx::query!(
+                    r#"UPDATE email_outbox
+                    SET n_retries = n_retries + 1, execute_after = now() + make_interval(secs => $2)
+                    WHERE email_id = $1"#,
+                    email.email_id,
+                    delay.as_secs_f64()
+                )
+                .execute(&mut transaction)
+                .await
+                .context("Failed to reschedule the email")?;
+            }
+            outcome => {
+                if let Err(error) = outcome {
+                    tracing::error!(
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        n_retries = email.n_retries,
+                        "Failed to send an email. Giving up"
+                    );
+                }
+                sqlx::query!(
+                    r#"DELETE FROM email_outbox WHERE email_id = $1"#,
+                    email.email_id
+                )
+                .execute(&mut transaction)
+                .await
+                .context("Failed to delete the email from the outbox")?;
+            }
+        }
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the transaction")?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+}
@@ -0,0 +1,462 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use minijinja::{context, Environment, UndefinedBehavior, Value};
+use reqwest::Url;
+use sqlx::PgPool;
+
+pub const CONFIRMATION_EMAIL: &str = "emails/confirmation";
+pub const NEWSLETTER_EMAIL: &str = "emails/newsletter";
+pub const DIGEST_EMAIL: &str = "emails/digest";
+
+/// The email templates stored in the `email_templates` table, compiled and
+/// ready to be rendered.
+///
+/// Templates can extend layouts and include partials by name (e.g.
+/// `{% extends "layouts/base.html" %}`). Escaping follows the extension:
+/// `.html` templates are HTML-escaped, `.txt` ones are left untouched.
+pub struct EmailTemplates {
+    env: Environment<'static>,
+}
+
+/// The two alternative bodies of an email.
+#[derive(serde::Serialize)]
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct StoredTemplate {
+    pub name: String,
+    pub source: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl EmailTemplates {
+    pub fn new(
+        templates: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, minijinja::Error> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        for (name, source) in templates {
+            env.add_template_owned(name, source)?;
+        }
+        Ok(Self { env })
+    }
+
+    #[tracing::instrument(name = "Loading email templates", skip_all)]
+    pub async fn load(db_connection_pool: &PgPool) -> Result<Self, anyhow::Error> {
+        let templates = sqlx::query!(r#"SELECT name, source FROM email_templates"#)
+            .fetch_all(db_connection_pool)
+            .await
+            .context("Failed to retrieve the email templates")?;
+        let templates = Self::new(templates.into_iter().map(|t| (t.name, t.source)))
+            .context("A stored email template is invalid")?;
+        Ok(templates)
+    }
+
+    /// Renders the `.html` and `.txt` variants of a stored email template.
+    pub fn render_email(
+        &self,
+        name: &str,
+        context: &Value,
+    ) -> Result<RenderedEmail, minijinja::Error> {
+        Ok(RenderedEmail {
+            html: self
+                .env
+                .get_template(&format!("{}.html", name))?
+                .render(context)?,
+            text: self
+                .env
+                .get_template(&format!("{}.txt", name))?
+                .render(context)?,
+        })
+    }
+
+    /// Renders a newsletter issue for a single subscriber: the issue content is
+    /// a template itself, then wrapped in the stored newsletter layout.
+    pub fn render_newsletter(
+        &self,
+        html_content: &str,
+        text_content: &str,
+        context: &Value,
+    ) -> Result<RenderedEmail, minijinja::Error> {
+        self.render_tracked_newsletter(html_content, text_content, context, str::to_owned)
+    }
+
+    /// Like [`Self::render_newsletter`], passing the rendered issue HTML through
+    /// `track` before wrapping it: links added by the layout are left alone.
+    pub fn render_tracked_newsletter(
+        &self,
+        html_content: &str,
+        text_content: &str,
+        context: &Value,
+        track: impl FnOnce(&str) -> String,
+    ) -> Result<RenderedEmail, minijinja::Error> {
+        let issue = self.render_tracked_issue(html_content, text_content, context, track)?;
+        Ok(RenderedEmail {
+            html: self
+                .env
+                .get_template(&format!("{}.html", NEWSLETTER_EMAIL))?
+                .render(
+                    context! { content => Value::from_safe_string(issue.html), ..context.clone() },
+                )?,
+            text: self
+                .env
+                .get_template(&format!("{}.txt", NEWSLETTER_EMAIL))?
+                .render(context! { content => issue.text, ..context.clone() })?,
+        })
+    }
+
+    /// Renders the content of an issue alone, passing its HTML through `track`.
+    /// Digests gather several of them in a single email.
+    pub fn render_tracked_issue(
+        &self,
+        html_content: &str,
+        text_content: &str,
+        context: &Value,
+        track: impl FnOnce(&str) -> String,
+    ) -> Result<RenderedEmail, minijinja::Error> {
+        Ok(RenderedEmail {
+            html: track(
+                &self
+                    .env
+                    .render_named_str("issue.html", html_content, context)?,
+            ),
+            text: self
+                .env
+                .render_named_str("issue.txt", text_content, context)?,
+        })
+    }
+
+    /// Renders the HTML content of an issue alone, without the email layout.
+    pub fn render_issue_html(
+        &self,
+        html_content: &str,
+        context: &Value,
+    ) -> Result<String, minijinja::Error> {
+        self.env
+            .render_named_str("issue.html", html_content, context)
+    }
+
+    /// Renders an issue for a placeholder subscriber.
+    pub fn preview_newsletter(
+        &self,
+        title: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<RenderedEmail, minijinja::Error> {
+        self.render_newsletter(
+            html_content,
+            text_content,
+            &sample_newsletter_context(title),
+        )
+    }
+
+    /// Checks that an issue renders, using placeholder subscriber details.
+    pub fn validate_newsletter(
+        &self,
+        title: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), minijinja::Error> {
+        self.preview_newsletter(title, html_content, text_content)?;
+        Ok(())
+    }
+
+    /// Checks that the emails sent by the application still render.
+    pub fn validate(&self) -> Result<(), minijinja::Error> {
+        self.render_email(CONFIRMATION_EMAIL, &sample_confirmation_context())?;
+        self.render_email(DIGEST_EMAIL, &sample_digest_context())?;
+        self.validate_newsletter("Title", "", "")
+    }
+}
+
+/// Variables available to newsletter issues and to the newsletter layout.
+pub fn newsletter_context(
+    subscriber_name: &str,
+    subscriber_email: &str,
+    title: &str,
+    issue_url: &Url,
+    unsubscribe_url: &Url,
+    preferences_url: &Url,
+) -> Value {
+    context! {
+        subscriber => context! { name => subscriber_name, email => subscriber_email },
+        issue => context! {
+            title => title,
+            url => Value::from_safe_string(issue_url.to_string()),
+        },
+        // Links are built by us: escaping them would mangle them.
+        unsubscribe_url => Value::from_safe_string(unsubscribe_url.to_string()),
+        preferences_url => Value::from_safe_string(preferences_url.to_string()),
+    }
+}
+
+/// Variables available to the digest layout, `issues` being built with
+/// [`digest_issue`].
+pub fn digest_context(
+    subscriber_name: &str,
+    subscriber_email: &str,
+    title: &str,
+    issues: Vec<Value>,
+    unsubscribe_url: &Url,
+    preferences_url: &Url,
+) -> Value {
+    context! {
+        subscriber => context! { name => subscriber_name, email => subscriber_email },
+        title => title,
+        issues => issues,
+        unsubscribe_url => Value::from_safe_string(unsubscribe_url.to_string()),
+        preferences_url => Value::from_safe_string(preferences_url.to_string()),
+    }
+}
+
+/// An issue of a digest, with both bodies: the `.html` layout shows
+/// `issue.html`, the `.txt` one `issue.text`.
+pub fn digest_issue(title: &str, issue_url: &Url, content: RenderedEmail) -> Value {
+    context! {
+        title => title,
+        url => Value::from_safe_string(issue_url.to_string()),
+        html => Value::from_safe_string(content.html),
+        text => content.text,
+    }
+}
+
+/// Variables available to the confirmation email.
+pub fn confirmation_context(subscriber_name: &str, confirmation_link: &Url) -> Value {
+    context! {
+        subscriber => context! { name => subscriber_name },
+        confirmation_link => Value::from_safe_string(confirmation_link.to_string()),
+    }
+}
+
+fn sample_newsletter_context(title: &str) -> Value {
+    let issue_url = Url::parse("https://example.com/issues/sample").unwrap();
+    let url = Url::parse("https://example.com/subscriptions/unsubscribe").unwrap();
+    let preferences_url = Url::parse("https://example.com/preferences").unwrap();
+    newsletter_context(
+        "Subscriber",
+        "subscriber@example.com",
+        title,
+        &issue_url,
+        &url,
+        &preferences_url,
+    )
+}
+
+fn sample_digest_context() -> Value {
+    let issue_url = Url::parse("https://example.com/issues/sample").unwrap();
+    let url = Url::parse("https://example.com/subscriptions/unsubscribe").unwrap();
+    let preferences_url = Url::parse("https://example.com/preferences").unwrap();
+    let issue = RenderedEmail {
+        html: String::new(),
+        text: String::new(),
+    };
+    digest_context(
+        "Subscriber",
+        "subscriber@example.com",
+        "Your weekly digest",
+        vec![digest_issue("Title", &issue_url, issue)],
+        &url,
+        &preferences_url,
+    )
+}
+
+fn sample_confirmation_context() -> Value {
+    let url = Url::parse("https://example.com/subscriptions/confirm").unwrap();
+    confirmation_context("Subscriber", &url)
+}
+
+#[tracing::instrument(name = "Listing email templates", skip(db_connection_pool))]
+pub async fn list_templates(
+    db_connection_pool: &PgPool,
+) -> Result<Vec<StoredTemplate>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredTemplate,
+        r#"SELECT name, source, updated_at FROM email_templates ORDER BY name"#
+    )
+    .fetch_all(db_connection_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Retrieving an email template", skip(db_connection_pool))]
+pub async fn get_template(
+    db_connection_pool: &PgPool,
+    name: &str,
+) -> Result<Option<StoredTemplate>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredTemplate,
+        r#"SELECT name, source, updated_at FROM email_templates WHERE name = $1"#,
+        name
+    )
+    .fetch_optional(db_connection_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Saving an email template", skip(db_connection_pool, source))]
+pub async fn save_template(
+    db_connection_pool: &PgPool,
+    name: &str,
+    source: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO email_templates (name, source, updated_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT (name) DO UPDATE SET source = EXCLUDED.source, updated_at = now()"#,
+        name,
+        source
+    )
+    .execute(db_connection_pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use reqwest::Url;
+
+    use super::{
+        confirmation_context, digest_context, digest_issue, newsletter_context, EmailTemplates,
+        CONFIRMATION_EMAIL, DIGEST_EMAIL,
+    };
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::new(
+            [
+                ("layouts/base.html", "<body>{% block content %}{% endblock %}{% block footer %}{% endblock %}</body>"),
+                ("layouts/base.txt", "{% block content %}{% endblock %}{% block footer %}{% endblock %}"),
+                ("emails/newsletter.html", "{% extends \"layouts/base.html\" %}{% block content %}{{ content }}{% endblock %}{% block footer %}<a href=\"{{ unsubscribe_url }}\">unsubscribe</a>{% endblock %}"),
+                ("emails/newsletter.txt", "{% extends \"layouts/base.txt\" %}{% block content %}{{ content }}{% endblock %}{% block footer %} {{ unsubscribe_url }}{% endblock %}"),
+                ("emails/digest.html", "{% for issue in issues %}<h2>{{ issue.title }}</h2>{{ issue.html }}{% endfor %}<a href=\"{{ unsubscribe_url }}\">unsubscribe</a>"),
+                ("emails/digest.txt", "{% for issue in issues %}{{ issue.title }}: {{ issue.text }} {% endfor %}{{ unsubscribe_url }}"),
+                ("emails/confirmation.html", "<a href=\"{{ confirmation_link }}\">{{ subscriber.name }}</a>"),
+                ("emails/confirmation.txt", "{{ subscriber.name }}: {{ confirmation_link }}"),
+            ]
+            .map(|(name, source)| (name.to_owned(), source.to_owned())),
+        )
+        .unwrap()
+    }
+
+    fn context(subscriber_name: &str) -> minijinja::Value {
+        let url = Url::parse("https://example.com/subscriptions/unsubscribe?token=abc").unwrap();
+        let issue_url = Url::parse("https://example.com/issues/issue-1").unwrap();
+        let preferences_url = Url::parse("https://example.com/preferences?token=abc").unwrap();
+        newsletter_context(
+            subscriber_name,
+            "jondoe@email.com",
+            "Issue #1",
+            &issue_url,
+            &url,
+            &preferences_url,
+        )
+    }
+
+    #[test]
+    fn newsletters_are_personalised_and_wrapped_in_the_layout() {
+        let email = templates()
+            .render_newsletter(
+                "<p>Hi {{ subscriber.name }}</p>",
+                "Hi {{ subscriber.name }}",
+                &context("Jon"),
+            )
+            .unwrap();
+
+        assert_eq!(
+            "<body><p>Hi Jon</p><a href=\"https://example.com/subscriptions/unsubscribe?token=abc\">unsubscribe</a></body>",
+            email.html
+        );
+        assert_eq!(
+            "Hi Jon https://example.com/subscriptions/unsubscribe?token=abc",
+            email.text
+        );
+    }
+
+    #[test]
+    fn subscriber_details_are_escaped_in_html_only() {
+        let email = templates()
+            .render_newsletter(
+                "{{ subscriber.name }}",
+                "{{ subscriber.name }}",
+                &context("<script>"),
+            )
+            .unwrap();
+
+        assert!(email.html.contains("&lt;script&gt;"));
+        assert!(email.text.starts_with("<script>"));
+    }
+
+    #[test]
+    fn digests_gather_the_issues_in_one_email() {
+        let templates = templates();
+        let issues = ["Issue #1", "Issue #2"]
+            .into_iter()
+            .map(|title| {
+                let content = templates
+                    .render_tracked_issue(
+                        "<p>Hi {{ subscriber.name }}</p>",
+                        "Hi {{ subscriber.name }}",
+                        &context("Jon"),
+                        str::to_owned,
+                    )
+                    .unwrap();
+                let issue_url = Url::parse("https://example.com/issues/issue").unwrap();
+                digest_issue(title, &issue_url, content)
+            })
+            .collect();
+        let url = Url::parse("https://example.com/subscriptions/unsubscribe?token=abc").unwrap();
+        let preferences_url = Url::parse("https://example.com/preferences?token=abc").unwrap();
+        let context = digest_context(
+            "Jon",
+            "jondoe@email.com",
+            "Your weekly digest",
+            issues,
+            &url,
+            &preferences_url,
+        );
+
+        let email = templates.render_email(DIGEST_EMAIL, &context).unwrap();
+
+        assert_eq!(
+            "<h2>Issue #1</h2><p>Hi Jon</p><h2>Issue #2</h2><p>Hi Jon</p><a href=\"https://example.com/subscriptions/unsubscribe?token=abc\">unsubscribe</a>",
+            email.html
+        );
+        assert_eq!(
+            "Issue #1: Hi Jon Issue #2: Hi Jon https://example.com/subscriptions/unsubscribe?token=abc",
+            email.text
+        );
+    }
+
+    #[test]
+    fn unknown_variables_fail_validation() {
+        assert_err!(templates().validate_newsletter("Title", "{{ subscriber.age }}", ""));
+    }
+
+    #[test]
+    fn syntax_errors_fail_validation() {
+        assert_err!(templates().validate_newsletter("Title", "", "{% if %}"));
+    }
+
+    #[test]
+    fn confirmation_emails_render_from_the_stored_templates() {
+        let templates = templates();
+        let link = Url::parse("https://example.com/subscriptions/confirm?token=abc").unwrap();
+
+        let email = templates
+            .render_email(CONFIRMATION_EMAIL, &confirmation_context("Jon", &link))
+            .unwrap();
+
+        assert_ok!(templates.validate());
+        assert_eq!(
+            "<a href=\"https://example.com/subscriptions/confirm?token=abc\">Jon</a>",
+            email.html
+        );
+        assert_eq!(
+            "Jon: https://example.com/subscriptions/confirm?token=abc",
+            email.text
+        );
+    }
+}
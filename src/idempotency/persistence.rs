@@ -0,0 +1,169 @@
+use actix_web::{body::to_bytes, http::StatusCode, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{postgres::PgHasArrayType, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::IdempotencyKey;
+use crate::configuration::RetentionSettings;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+    /// The key was claimed by a request with a different body.
+    RejectReusedKey,
+}
+
+struct SavedResponse {
+    request_hash: Option<Vec<u8>>,
+    response: HttpResponse,
+}
+
+/// Claims the idempotency key for the current request.
+///
+/// A concurrent request holding the same key blocks on the insert until the first
+/// one commits (or rolls back), so the saved response is always visible afterwards.
+/// `request_hash` identifies the request body: the saved response is only
+/// replayed for the same body.
+#[tracing::instrument(
+    name = "Trying to process idempotent request",
+    skip(db_connection_pool, request_hash)
+)]
+pub async fn try_processing(
+    db_connection_pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    request_hash: &[u8],
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = db_connection_pool.begin().await?;
+    let n_inserted_rows = sqlx::query!(
+        r#"INSERT INTO idempotency (user_id, idempotency_key, request_hash, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT DO NOTHING"#,
+        user_id,
+        idempotency_key.as_ref(),
+        request_hash
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved = get_saved_response(db_connection_pool, idempotency_key, user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+        match saved.request_hash {
+            Some(saved_hash) if saved_hash != request_hash => Ok(NextAction::RejectReusedKey),
+            _ => Ok(NextAction::ReturnSavedResponse(saved.response)),
+        }
+    }
+}
+
+#[tracing::instrument(name = "Retrieving saved response", skip(db_connection_pool))]
+async fn get_saved_response(
+    db_connection_pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<SavedResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"SELECT
+            request_hash,
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers!: Vec<HeaderPairRecord>",
+            response_body as "response_body!"
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2"#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(db_connection_pool)
+    .await?;
+
+    if let Some(r) = saved_response {
+        let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+        let mut response = HttpResponse::build(status_code);
+        for HeaderPairRecord { name, value } in r.response_headers {
+            response.append_header((name, value));
+        }
+        Ok(Some(SavedResponse {
+            request_hash: r.request_hash,
+            response: response.body(r.response_body),
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+#[tracing::instrument(name = "Saving response", skip(transaction, http_response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect::<Vec<_>>();
+
+    sqlx::query_unchecked!(
+        r#"UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE user_id = $1 AND idempotency_key = $2"#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    let http_response = response_head.set_body(body).map_into_boxed_body();
+    Ok(http_response)
+}
+
+/// Deletes the keys older than their TTL, returning how many were deleted.
+#[tracing::instrument(skip_all, fields(n_purged=tracing::field::Empty), err)]
+pub async fn purge_expired_idempotency_keys(
+    db_connection_pool: &PgPool,
+    settings: &RetentionSettings,
+) -> Result<u64, anyhow::Error> {
+    let n_purged = sqlx::query!(
+        r#"DELETE FROM idempotency WHERE created_at < $1"#,
+        Utc::now() - settings.idempotency_key_ttl()
+    )
+    .execute(db_connection_pool)
+    .await
+    .context("Failed to delete the expired idempotency keys")?
+    .rows_affected();
+    tracing::Span::current().record("n_purged", n_purged);
+    Ok(n_purged)
+}
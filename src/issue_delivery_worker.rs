@@ -0,0 +1,903 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::Utc;
+use reqwest::Url;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{IssueDeliverySettings, Settings},
+    database_helper::{enqueue_delivery_tasks, get_confirmed_subscribers},
+    domain::{issue_url, SubscriberEmail},
+    email_client::{EmailClient, EmailError, EmailHeader, EmailMessage},
+    email_templates::{
+        digest_context, digest_issue, newsletter_context, EmailTemplates, DIGEST_EMAIL,
+    },
+    signed_token::{TokenPurpose, TokenSigner},
+    tracking::IssueTracker,
+};
+
+/// Where the delivery of an issue to a subscriber stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Queued,
+    Sent,
+    Failed,
+    Bounced,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Queued => "queued",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Bounced => "bounced",
+        }
+    }
+}
+
+impl TryFrom<&str> for DeliveryStatus {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "queued" => Ok(Self::Queued),
+            "sent" => Ok(Self::Sent),
+            "failed" => Ok(Self::Failed),
+            "bounced" => Ok(Self::Bounced),
+            other => Err(format!("{} is not a delivery status", other)),
+        }
+    }
+}
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    subscriber_email: String,
+    subscriber_name: String,
+    subscriber_status: String,
+    n_retries: i16,
+}
+
+/// The pending digest of a subscriber, gathering the issues of their
+/// digest tasks.
+struct Digest {
+    subscriber_id: Uuid,
+    subscriber_email: String,
+    subscriber_name: String,
+    digest_frequency: String,
+    n_retries: i16,
+}
+
+struct NewsletterIssue {
+    title: String,
+    slug: Option<String>,
+    text_content: String,
+    html_content: String,
+    tracking_enabled: bool,
+    list_id: Option<Uuid>,
+}
+
+pub struct IssueDeliveryWorker {
+    db_connection_pool: PgPool,
+    email_client: EmailClient,
+    settings: IssueDeliverySettings,
+    base_url: Url,
+    token_signer: TokenSigner,
+    preferences_link_ttl: chrono::Duration,
+}
+
+impl IssueDeliveryWorker {
+    pub fn build(configuration: Settings, db_connection_pool: PgPool) -> Self {
+        Self {
+            db_connection_pool,
+            email_client: configuration.email_client.client_without_retries(),
+            settings: configuration.issue_delivery,
+            base_url: configuration.application.base_url,
+            token_signer: TokenSigner::new(configuration.application.hmac_secret),
+            preferences_link_ttl: configuration.subscriptions.preferences_link_ttl(),
+        }
+    }
+
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        loop {
+            match self.try_execute_task().await {
+                Ok(ExecutionOutcome::EmptyQueue) => {
+                    tokio::time::sleep(self.settings.poll_interval()).await;
+                }
+                Ok(ExecutionOutcome::TaskCompleted) => {}
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    /// Delivers the next batch of issues due to be sent on their own, then the
+    /// next batch of digests.
+    pub async fn try_execute_task(&self) -> Result<ExecutionOutcome, anyhow::Error> {
+        let issues = self.deliver_issues().await?;
+        let digests = self.deliver_digests().await?;
+        match (issues, digests) {
+            (ExecutionOutcome::EmptyQueue, ExecutionOutcome::EmptyQueue) => {
+                Ok(ExecutionOutcome::EmptyQueue)
+            }
+            _ => Ok(ExecutionOutcome::TaskCompleted),
+        }
+    }
+
+    /// Delivers the next batch of ready tasks with a single call to the email
+    /// provider, then settles every task according to its own outcome.
+    #[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+    async fn deliver_issues(&self) -> Result<ExecutionOutcome, anyhow::Error> {
+        let (mut transaction, tasks) =
+            dequeue_tasks(&self.db_connection_pool, self.settings.batch_size).await?;
+        if tasks.is_empty() {
+            return Ok(ExecutionOutcome::EmptyQueue);
+        }
+        tracing::Span::current().record("n_tasks", tasks.len());
+
+        let templates = EmailTemplates::load(&self.db_connection_pool).await?;
+        let mut issues: HashMap<Uuid, NewsletterIssue> = HashMap::new();
+        let mut deliverable_tasks = Vec::with_capacity(tasks.len());
+        let mut messages = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            if task.subscriber_status != "confirmed" {
+                tracing::info!(
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_id = %task.subscriber_id,
+                    subscriber_status = %task.subscriber_status,
+                    "Skipping a subscriber who is no longer confirmed"
+                );
+                let error = format!("The subscriber is {}", task.subscriber_status);
+                settle_task(
+                    &mut transaction,
+                    &task,
+                    DeliveryStatus::Failed,
+                    Some(&error),
+                )
+                .await?;
+                continue;
+            }
+
+            let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+                Ok(email) => email,
+                Err(error) => {
+                    tracing::error!(
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        subscriber_id = %task.subscriber_id,
+                        error.message = %error,
+                        "Skipping a confirmed subscriber. Their stored contact details are invalid"
+                    );
+                    settle_task(
+                        &mut transaction,
+                        &task,
+                        DeliveryStatus::Failed,
+                        Some(&error),
+                    )
+                    .await?;
+                    continue;
+                }
+            };
+
+            let issue = match issues.entry(task.newsletter_issue_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry
+                    .insert(get_issue(&self.db_connection_pool, task.newsletter_issue_id).await?),
+            };
+            match self.personalise(&templates, issue, email, &task) {
+                Ok(message) => {
+                    messages.push(message);
+                    deliverable_tasks.push(task);
+                }
+                Err(error) => {
+                    tracing::error!(
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        subscriber_id = %task.subscriber_id,
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        "Failed to render the issue for a confirmed subscriber. Giving up"
+                    );
+                    let error = error.to_string();
+                    settle_task(
+                        &mut transaction,
+                        &task,
+                        DeliveryStatus::Failed,
+                        Some(&error),
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        let outcomes = self.email_client.send_email_batch(&messages).await;
+        for (task, outcome) in deliverable_tasks.iter().zip(outcomes) {
+            match outcome {
+                Ok(message_id) => {
+                    settle_task(&mut transaction, task, DeliveryStatus::Sent, None).await?;
+                    if let Some(message_id) = message_id {
+                        record_message_id(&mut transaction, task, &message_id).await?;
+                    }
+                }
+                Err(error @ EmailError::Permanent(_)) => {
+                    tracing::error!(
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        subscriber_id = %task.subscriber_id,
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        "The email provider rejected the issue for a confirmed subscriber. Giving up"
+                    );
+                    let error = error.to_string();
+                    settle_task(&mut transaction, task, DeliveryStatus::Failed, Some(&error))
+                        .await?;
+                }
+                Err(error) if task.n_retries < self.settings.max_retries => {
+                    tracing::warn!(
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        subscriber_id = %task.subscriber_id,
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        n_retries = task.n_retries,
+                        "Failed to deliver issue to a confirmed subscriber. Retrying later"
+                    );
+                    let mut delay = self.settings.retry_delay(task.n_retries);
+                    if let EmailError::RateLimited {
+                        retry_after: Some(retry_after),
+                        ..
+                    } = error
+                    {
+                        delay = delay.max(retry_after);
+                    }
+                    retry_task_later(&mut transaction, task, delay, &error.to_string()).await?;
+                }
+                Err(error) => {
+                    tracing::error!(
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        subscriber_id = %task.subscriber_id,
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        n_retries = task.n_retries,
+                        "Failed to deliver issue to a confirmed subscriber. Giving up"
+                    );
+                    let error = error.to_string();
+                    settle_task(&mut transaction, task, DeliveryStatus::Failed, Some(&error))
+                        .await?;
+                }
+            }
+        }
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the transaction")?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+
+    /// Sends the next batch of ready digests, one email per subscriber with
+    /// every issue queued for them, then settles the issues of each digest
+    /// according to the outcome of its email.
+    #[tracing::instrument(skip_all, fields(n_digests = tracing::field::Empty), err)]
+    async fn deliver_digests(&self) -> Result<ExecutionOutcome, anyhow::Error> {
+        let (mut transaction, digests) =
+            dequeue_digests(&self.db_connection_pool, self.settings.batch_size).await?;
+        if digests.is_empty() {
+            return Ok(ExecutionOutcome::EmptyQueue);
+        }
+        tracing::Span::current().record("n_digests", digests.len());
+
+        let subscriber_ids: Vec<_> = digests.iter().map(|d| d.subscriber_id).collect();
+        let mut tasks: HashMap<Uuid, Vec<DeliveryTask>> = HashMap::new();
+        for task in dequeue_digest_tasks(&mut transaction, &subscriber_ids).await? {
+            tasks.entry(task.subscriber_id).or_default().push(task);
+        }
+
+        let templates = EmailTemplates::load(&self.db_connection_pool).await?;
+        let mut issues: HashMap<Uuid, NewsletterIssue> = HashMap::new();
+        let mut deliverable_digests = Vec::with_capacity(digests.len());
+        let mut messages = Vec::with_capacity(digests.len());
+        for digest in digests {
+            let mut deliverable_tasks = Vec::new();
+            for task in tasks.remove(&digest.subscriber_id).unwrap_or_default() {
+                if task.subscriber_status == "confirmed" {
+                    deliverable_tasks.push(task);
+                    continue;
+                }
+                tracing::info!(
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_id = %task.subscriber_id,
+                    subscriber_status = %task.subscriber_status,
+                    "Leaving an issue out of the digest of a subscriber who is no longer confirmed"
+                );
+                let error = format!("The subscriber is {}", task.subscriber_status);
+                settle_task(
+                    &mut transaction,
+                    &task,
+                    DeliveryStatus::Failed,
+                    Some(&error),
+                )
+                .await?;
+            }
+            if deliverable_tasks.is_empty() {
+                advance_digest(&mut transaction, digest.subscriber_id).await?;
+                continue;
+            }
+
+            let email = match SubscriberEmail::parse(digest.subscriber_email.clone()) {
+                Ok(email) => email,
+                Err(error) => {
+                    tracing::error!(
+                        subscriber_id = %digest.subscriber_id,
+                        error.message = %error,
+                        "Skipping the digest of a confirmed subscriber. Their stored contact details are invalid"
+                    );
+                    settle_digest(
+                        &mut transaction,
+                        &digest,
+                        &deliverable_tasks,
+                        DeliveryStatus::Failed,
+                        Some(&error),
+                    )
+                    .await?;
+                    continue;
+                }
+            };
+
+            for task in &deliverable_tasks {
+                if let Entry::Vacant(entry) = issues.entry(task.newsletter_issue_id) {
+                    entry.insert(
+                        get_issue(&self.db_connection_pool, task.newsletter_issue_id).await?,
+                    );
+                }
+            }
+            match self.personalise_digest(&templates, &issues, email, &digest, &deliverable_tasks) {
+                Ok(message) => {
+                    messages.push(message);
+                    deliverable_digests.push((digest, deliverable_tasks));
+                }
+                Err(error) => {
+                    tracing::error!(
+                        subscriber_id = %digest.subscriber_id,
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        "Failed to render the digest of a confirmed subscriber. Giving up"
+                    );
+                    let error = error.to_string();
+                    settle_digest(
+                        &mut transaction,
+                        &digest,
+                        &deliverable_tasks,
+                        DeliveryStatus::Failed,
+                        Some(&error),
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        let outcomes = self.email_client.send_email_batch(&messages).await;
+        for ((digest, tasks), outcome) in deliverable_digests.iter().zip(outcomes) {
+            match outcome {
+                Ok(message_id) => {
+                    settle_digest(&mut transaction, digest, tasks, DeliveryStatus::Sent, None)
+                        .await?;
+                    if let Some(message_id) = message_id {
+                        for task in tasks {
+                            record_message_id(&mut transaction, task, &message_id).await?;
+                        }
+                    }
+                }
+                Err(error @ EmailError::Permanent(_)) => {
+                    tracing::error!(
+                        subscriber_id = %digest.subscriber_id,
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        "The email provider rejected the digest of a confirmed subscriber. Giving up"
+                    );
+                    let error = error.to_string();
+                    settle_digest(
+                        &mut transaction,
+                        digest,
+                        tasks,
+                        DeliveryStatus::Failed,
+                        Some(&error),
+                    )
+                    .await?;
+                }
+                Err(error) if digest.n_retries < self.settings.max_retries => {
+                    tracing::warn!(
+                        subscriber_id = %digest.subscriber_id,
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        n_retries = digest.n_retries,
+                        "Failed to deliver the digest of a confirmed subscriber. Retrying later"
+                    );
+                    let mut delay = self.settings.retry_delay(digest.n_retries);
+                    if let EmailError::RateLimited {
+                        retry_after: Some(retry_after),
+                        ..
+                    } = error
+                    {
+                        delay = delay.max(retry_after);
+                    }
+                    retry_digest_later(&mut transaction, digest, tasks, delay, &error.to_string())
+                        .await?;
+                }
+                Err(error) => {
+                    tracing::error!(
+                        subscriber_id = %digest.subscriber_id,
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        n_retries = digest.n_retries,
+                        "Failed to deliver the digest of a confirmed subscriber. Giving up"
+                    );
+                    let error = error.to_string();
+                    settle_digest(
+                        &mut transaction,
+                        digest,
+                        tasks,
+                        DeliveryStatus::Failed,
+                        Some(&error),
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the transaction")?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+
+    /// Renders the issue templates for the subscriber of the given task.
+    fn personalise(
+        &self,
+        templates: &EmailTemplates,
+        issue: &NewsletterIssue,
+        recipient: SubscriberEmail,
+        task: &DeliveryTask,
+    ) -> Result<EmailMessage, minijinja::Error> {
+        let unsubscribe_link = self.unsubscribe_link(task.subscriber_id, issue.list_id);
+        let context = newsletter_context(
+            &task.subscriber_name,
+            recipient.as_ref(),
+            &issue.title,
+            &issue_url(&self.base_url, issue.slug.as_deref()),
+            &unsubscribe_link,
+            &self.preferences_link(task.subscriber_id),
+        );
+        let email = if issue.tracking_enabled {
+            let tracker = IssueTracker::new(
+                &self.base_url,
+                &self.token_signer,
+                task.newsletter_issue_id,
+                task.subscriber_id,
+            );
+            templates.render_tracked_newsletter(
+                &issue.html_content,
+                &issue.text_content,
+                &context,
+                |html| tracker.track(html),
+            )?
+        } else {
+            templates.render_newsletter(&issue.html_content, &issue.text_content, &context)?
+        };
+        let headers = vec![
+            EmailHeader::new("List-Unsubscribe", format!("<{}>", unsubscribe_link)),
+            EmailHeader::new("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+        ];
+
+        Ok(EmailMessage {
+            recipient,
+            subject: issue.title.clone(),
+            text_content: email.text,
+            html_content: email.html,
+            headers,
+        })
+    }
+
+    /// Gathers the issues of the given tasks in a single email, each rendered
+    /// as it would be on its own. A digest can span several lists: its
+    /// unsubscribe link is about the whole subscription.
+    fn personalise_digest(
+        &self,
+        templates: &EmailTemplates,
+        issues: &HashMap<Uuid, NewsletterIssue>,
+        recipient: SubscriberEmail,
+        digest: &Digest,
+        tasks: &[DeliveryTask],
+    ) -> Result<EmailMessage, minijinja::Error> {
+        let unsubscribe_link = self.unsubscribe_link(digest.subscriber_id, None);
+        let preferences_link = self.preferences_link(digest.subscriber_id);
+        let mut contents = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let issue = &issues[&task.newsletter_issue_id];
+            let issue_url = issue_url(&self.base_url, issue.slug.as_deref());
+            let context = newsletter_context(
+                &digest.subscriber_name,
+                recipient.as_ref(),
+                &issue.title,
+                &issue_url,
+                &unsubscribe_link,
+                &preferences_link,
+            );
+            let content = if issue.tracking_enabled {
+                let tracker = IssueTracker::new(
+                    &self.base_url,
+                    &self.token_signer,
+                    task.newsletter_issue_id,
+                    task.subscriber_id,
+                );
+                templates.render_tracked_issue(
+                    &issue.html_content,
+                    &issue.text_content,
+                    &context,
+                    |html| tracker.track(html),
+                )?
+            } else {
+                templates.render_tracked_issue(
+                    &issue.html_content,
+                    &issue.text_content,
+                    &context,
+                    str::to_owned,
+                )?
+            };
+            contents.push(digest_issue(&issue.title, &issue_url, content));
+        }
+        let subject = format!("Your {} digest", digest.digest_frequency);
+        let context = digest_context(
+            &digest.subscriber_name,
+            recipient.as_ref(),
+            &subject,
+            contents,
+            &unsubscribe_link,
+            &preferences_link,
+        );
+        let email = templates.render_email(DIGEST_EMAIL, &context)?;
+        let headers = vec![
+            EmailHeader::new("List-Unsubscribe", format!("<{}>", unsubscribe_link)),
+            EmailHeader::new("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+        ];
+
+        Ok(EmailMessage {
+            recipient,
+            subject,
+            text_content: email.text,
+            html_content: email.html,
+            headers,
+        })
+    }
+
+    /// Links to leave the list the issue was sent to.
+    fn unsubscribe_link(&self, subscriber_id: Uuid, list_id: Option<Uuid>) -> Url {
+        let payload = match list_id {
+            Some(list_id) => format!("{} {}", subscriber_id, list_id),
+            None => subscriber_id.to_string(),
+        };
+        let token = self.token_signer.sign(TokenPurpose::Unsubscribe, &payload);
+        let mut link = self.base_url.join("/subscriptions/unsubscribe").unwrap();
+        link.query_pairs_mut().append_pair("token", &token);
+        link
+    }
+
+    /// Links to the preference center of the subscriber, for a limited time:
+    /// a forwarded issue should not hand over the subscription for good.
+    fn preferences_link(&self, subscriber_id: Uuid) -> Url {
+        let token = self.token_signer.sign_until(
+            TokenPurpose::Preferences,
+            &subscriber_id.to_string(),
+            Utc::now() + self.preferences_link_ttl,
+        );
+        let mut link = self.base_url.join("/preferences").unwrap();
+        link.query_pairs_mut().append_pair("token", &token);
+        link
+    }
+}
+
+/// Queues the delivery of an issue to every member of the list confirmed right now.
+#[tracing::instrument(skip(db_connection_pool, transaction))]
+pub async fn enqueue_issue_delivery(
+    db_connection_pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let subscriber_ids: Vec<_> = get_confirmed_subscribers(db_connection_pool, list_id)
+        .await
+        .context("Failed to retrieve confirmed subscribers")?
+        .into_iter()
+        .filter_map(|subscriber| match subscriber {
+            Ok(subscriber) => Some(subscriber.id),
+            Err(error) => {
+                tracing::warn!(error.cause_chain = ?error, "Skipping a confirmed subscriber. \
+                Their stored contact details are invalid");
+                None
+            }
+        })
+        .collect();
+
+    enqueue_delivery_tasks(transaction, newsletter_issue_id, &subscriber_ids)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    Ok(())
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(
+    db_connection_pool: &PgPool,
+    batch_size: i64,
+) -> Result<(PgTransaction, Vec<DeliveryTask>), anyhow::Error> {
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")?;
+
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"SELECT q.newsletter_issue_id, q.subscriber_id, s.email AS subscriber_email,
+            s.name AS subscriber_name, q.n_retries,
+            CASE WHEN s.status <> 'confirmed' THEN s.status
+                WHEN s.paused_until >= current_date THEN 'paused'
+                WHEN m.status = 'confirmed' THEN 'confirmed'
+                ELSE 'unsubscribed from the list' END AS "subscriber_status!"
+        FROM issue_delivery_queue q
+        JOIN subscriptions s ON s.id = q.subscriber_id
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        LEFT JOIN list_memberships m
+            ON m.list_id = i.list_id AND m.subscriber_id = q.subscriber_id
+        WHERE q.execute_after <= now() AND NOT q.digest
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT $1"#,
+        batch_size
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to dequeue delivery tasks")?;
+
+    Ok((transaction, tasks))
+}
+
+/// Locks the next digests due, leaving those locked by another worker alone.
+#[tracing::instrument(skip_all)]
+async fn dequeue_digests(
+    db_connection_pool: &PgPool,
+    batch_size: i64,
+) -> Result<(PgTransaction, Vec<Digest>), anyhow::Error> {
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")?;
+
+    let digests = sqlx::query_as!(
+        Digest,
+        r#"SELECT d.subscriber_id, s.email AS subscriber_email, s.name AS subscriber_name,
+            s.digest_frequency, d.n_retries
+        FROM digest_queue d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.execute_after <= now()
+        FOR UPDATE OF d
+        SKIP LOCKED
+        LIMIT $1"#,
+        batch_size
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to dequeue digests")?;
+
+    Ok((transaction, digests))
+}
+
+/// The issues waiting for the digests of the given subscribers, oldest first.
+/// Locking the digest is enough to own them: no other worker touches them.
+#[tracing::instrument(skip_all)]
+async fn dequeue_digest_tasks(
+    transaction: &mut PgTransaction,
+    subscriber_ids: &[Uuid],
+) -> Result<Vec<DeliveryTask>, anyhow::Error> {
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"SELECT q.newsletter_issue_id, q.subscriber_id, s.email AS subscriber_email,
+            s.name AS subscriber_name, q.n_retries,
+            CASE WHEN s.status <> 'confirmed' THEN s.status
+                WHEN s.paused_until >= current_date THEN 'paused'
+                WHEN m.status = 'confirmed' THEN 'confirmed'
+                ELSE 'unsubscribed from the list' END AS "subscriber_status!"
+        FROM issue_delivery_queue q
+        JOIN subscriptions s ON s.id = q.subscriber_id
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        LEFT JOIN list_memberships m
+            ON m.list_id = i.list_id AND m.subscriber_id = q.subscriber_id
+        WHERE q.subscriber_id = ANY($1) AND q.digest AND q.execute_after <= now()
+        ORDER BY i.published_at
+        FOR UPDATE OF q"#,
+        subscriber_ids
+    )
+    .fetch_all(transaction)
+    .await
+    .context("Failed to dequeue the issues of the digests")?;
+
+    Ok(tasks)
+}
+
+/// Settles every issue of a digest the same way, then moves on to the next
+/// digest of the subscriber.
+async fn settle_digest(
+    transaction: &mut PgTransaction,
+    digest: &Digest,
+    tasks: &[DeliveryTask],
+    status: DeliveryStatus,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    for task in tasks {
+        settle_task(transaction, task, status, error).await?;
+    }
+    advance_digest(transaction, digest.subscriber_id).await
+}
+
+/// Drops the digest of the subscriber, unless issues published since it was
+/// due are waiting: they make the next digest, due with the oldest of them.
+#[tracing::instrument(skip(transaction))]
+async fn advance_digest(
+    transaction: &mut PgTransaction,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE digest_queue
+        SET n_retries = 0, execute_after = next.execute_after
+        FROM (SELECT MIN(execute_after) AS execute_after FROM issue_delivery_queue
+            WHERE subscriber_id = $1 AND digest) next
+        WHERE subscriber_id = $1 AND next.execute_after IS NOT NULL"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to schedule the next digest")?;
+    sqlx::query!(
+        r#"DELETE FROM digest_queue WHERE subscriber_id = $1
+        AND NOT EXISTS (
+            SELECT 1 FROM issue_delivery_queue WHERE subscriber_id = $1 AND digest
+        )"#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to delete the digest")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_digest_later(
+    transaction: &mut PgTransaction,
+    digest: &Digest,
+    tasks: &[DeliveryTask],
+    delay: Duration,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE digest_queue
+        SET n_retries = n_retries + 1, execute_after = now() + make_interval(secs => $2)
+        WHERE subscriber_id = $1"#,
+        digest.subscriber_id,
+        delay.as_secs_f64()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to reschedule the digest")?;
+    for task in tasks {
+        record_delivery(transaction, task, DeliveryStatus::Queued, Some(error)).await?;
+    }
+    Ok(())
+}
+
+/// Removes a task from the queue, recording how its delivery ended.
+#[tracing::instrument(skip_all)]
+async fn settle_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    status: DeliveryStatus,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE newsletter_issue_id = $1 AND subscriber_id = $2"#,
+        task.newsletter_issue_id,
+        task.subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the delivery task")?;
+    record_delivery(transaction, task, status, error).await
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_task_later(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    delay: Duration,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1, execute_after = now() + make_interval(secs => $3)
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2"#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        delay.as_secs_f64()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to reschedule the delivery task")?;
+    record_delivery(transaction, task, DeliveryStatus::Queued, Some(error)).await
+}
+
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    status: DeliveryStatus,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_id, status, last_error)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (newsletter_issue_id, subscriber_id) DO UPDATE
+        SET status = EXCLUDED.status, last_error = EXCLUDED.last_error, updated_at = now()"#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        status.as_str(),
+        error
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to record the delivery status")?;
+    Ok(())
+}
+
+/// Remembers the id the provider gave the delivered issue, to match its
+/// webhooks with the delivery.
+async fn record_message_id(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    message_id: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE issue_deliveries SET message_id = $3
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2"#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        message_id
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to record the message id of the delivery")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"SELECT title, slug, text_content, html_content, tracking_enabled, list_id
+        FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_one(db_connection_pool)
+    .await
+    .context("Failed to retrieve the newsletter issue")?;
+    Ok(issue)
+}
@@ -1,7 +1,23 @@
+pub mod authentication;
+pub mod client_address;
 pub mod configuration;
 pub mod database_helper;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox;
+pub mod email_templates;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod newsletter_scheduler;
+pub mod random_token;
+pub mod rate_limiting;
+pub mod retention_worker;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
+pub mod signed_token;
 pub mod startup;
+pub mod subscription_events;
 pub mod telemetry;
+pub mod tracking;
+pub mod utils;
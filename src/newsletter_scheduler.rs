@@ -0,0 +1,73 @@
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    configuration::NewsletterSchedulerSettings, domain::IssueSlug,
+    issue_delivery_worker::enqueue_issue_delivery,
+};
+
+/// Periodically publishes the scheduled issues that are due.
+pub async fn run_scheduler_until_stopped(
+    db_connection_pool: PgPool,
+    settings: NewsletterSchedulerSettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        // Failures are logged by the instrumentation, we simply try again later.
+        let _ = dispatch_due_issues(&db_connection_pool).await;
+        tokio::time::sleep(settings.poll_interval()).await;
+    }
+}
+
+/// Enqueues the deliveries of every scheduled issue whose `send_at` has passed,
+/// returning how many issues were published.
+///
+/// Due issues are locked with `SKIP LOCKED`: when several replicas poll at once
+/// each issue is dispatched by exactly one of them.
+#[tracing::instrument(skip_all, fields(n_dispatched=tracing::field::Empty), err)]
+pub async fn dispatch_due_issues(db_connection_pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")?;
+
+    let due_issues = sqlx::query!(
+        r#"SELECT newsletter_issue_id, title,
+            COALESCE(list_id, (SELECT list_id FROM lists WHERE is_default)) AS "list_id!"
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= now()
+        FOR UPDATE SKIP LOCKED"#
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to retrieve due newsletter issues")?;
+
+    for issue in &due_issues {
+        enqueue_issue_delivery(
+            db_connection_pool,
+            &mut transaction,
+            issue.newsletter_issue_id,
+            issue.list_id,
+        )
+        .await?;
+        let slug = IssueSlug::new(&issue.title, issue.newsletter_issue_id);
+        sqlx::query!(
+            r#"UPDATE newsletter_issues
+            SET status = 'published', slug = $2, published_at = now(), updated_at = now()
+            WHERE newsletter_issue_id = $1"#,
+            issue.newsletter_issue_id,
+            slug.as_ref()
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to mark a due newsletter issue as published")?;
+    }
+    let n_dispatched = due_issues.len() as u64;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transaction")?;
+
+    tracing::Span::current().record("n_dispatched", n_dispatched);
+    Ok(n_dispatched)
+}
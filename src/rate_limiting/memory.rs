@@ -0,0 +1,107 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+
+use super::{BucketState, RateLimitDecision, RateLimitStore, TokenBucket};
+
+/// The most buckets kept at once. Past it, the least recently used bucket is
+/// dropped to make room, which resets its limits.
+const MAX_BUCKETS: usize = 100_000;
+
+/// Keeps the buckets in the memory of the process: every replica limits
+/// requests on its own.
+pub struct InMemoryRateLimitStore {
+    max_buckets: usize,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    states: HashMap<String, BucketState>,
+    /// Every key, from the least to the most recently used.
+    by_last_use: BTreeSet<(DateTime<Utc>, String)>,
+}
+
+impl InMemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self::with_max_buckets(MAX_BUCKETS)
+    }
+
+    pub fn with_max_buckets(max_buckets: usize) -> Self {
+        Self {
+            max_buckets,
+            buckets: Mutex::default(),
+        }
+    }
+}
+
+impl Default for InMemoryRateLimitStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn take(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+    ) -> Result<RateLimitDecision, anyhow::Error> {
+        let now = Utc::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets {
+            states,
+            by_last_use,
+        } = &mut *buckets;
+
+        let state = states.get(key).copied();
+        match state {
+            Some(state) => {
+                by_last_use.remove(&(state.updated_at, key.to_owned()));
+            }
+            None if states.len() >= self.max_buckets => {
+                if let Some((_, least_recently_used)) = by_last_use.pop_first() {
+                    states.remove(&least_recently_used);
+                }
+            }
+            None => {}
+        }
+
+        let (state, decision) = bucket.take(state, now);
+        states.insert(key.to_owned(), state);
+        by_last_use.insert((state.updated_at, key.to_owned()));
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::InMemoryRateLimitStore;
+    use crate::rate_limiting::{RateLimitDecision, RateLimitStore, TokenBucket};
+
+    const ONE_PER_HOUR: TokenBucket = TokenBucket {
+        capacity: 1,
+        refill_interval: Duration::from_secs(3600),
+    };
+
+    #[tokio::test]
+    async fn the_least_recently_used_bucket_makes_room_for_new_ones() {
+        let store = InMemoryRateLimitStore::with_max_buckets(2);
+        for key in ["a", "b", "c"] {
+            store.take(key, &ONE_PER_HOUR).await.unwrap();
+        }
+
+        assert_eq!(2, store.buckets.lock().unwrap().states.len());
+        // "a" was dropped: its bucket is full again.
+        let decision = store.take("a", &ONE_PER_HOUR).await.unwrap();
+        assert_eq!(RateLimitDecision::Allowed, decision);
+        let decision = store.take("c", &ONE_PER_HOUR).await.unwrap();
+        assert_ne!(RateLimitDecision::Allowed, decision);
+    }
+}
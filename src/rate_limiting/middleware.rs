@@ -0,0 +1,54 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::{InternalError, PayloadError},
+    http::header::RETRY_AFTER,
+    web, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use futures_util::{stream, Stream};
+use std::pin::Pin;
+
+use super::{retry_after_seconds, RateLimitDecision, RateLimiter};
+use crate::{client_address::client_address, utils::e500};
+
+type BoxedPayloadStream = Pin<Box<dyn Stream<Item = Result<web::Bytes, PayloadError>>>>;
+
+/// Rejects with a `429 Too Many Requests` the requests over the limits of
+/// their client address or of the `email` field of their form: every form
+/// that gets an email sent to an address of the client's choosing.
+pub async fn limit_email_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let rate_limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .cloned()
+        .ok_or_else(|| e500("The rate limiter is not configured"))?;
+
+    // The form has to be read to find the email: it is then put back for the
+    // handler.
+    let body = req.extract::<web::Bytes>().await?;
+    let email = url::form_urlencoded::parse(&body)
+        .find(|(name, _)| name == "email")
+        .map(|(_, value)| value.into_owned());
+    let payload: BoxedPayloadStream = Box::pin(stream::once(async move { Ok(body) }));
+    req.set_payload(Payload::from(payload));
+
+    let ip_address = client_address(req.request());
+    let decision = rate_limiter
+        .check(ip_address.as_deref(), email.as_deref())
+        .await
+        .map_err(e500)?;
+
+    match decision {
+        RateLimitDecision::Allowed => next.call(req).await,
+        RateLimitDecision::Limited { retry_after } => {
+            let response = HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, retry_after_seconds(retry_after).to_string()))
+                .finish();
+            let e = anyhow::anyhow!("The client is over its rate limit");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
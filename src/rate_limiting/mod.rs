@@ -0,0 +1,207 @@
+mod memory;
+mod middleware;
+mod postgres;
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+pub use memory::InMemoryRateLimitStore;
+pub use middleware::limit_email_requests;
+pub use postgres::{purge_full_buckets, PgRateLimitStore};
+
+/// Lets `capacity` requests through at once, then one more every
+/// `refill_interval`.
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucket {
+    pub capacity: u32,
+    pub refill_interval: Duration,
+}
+
+/// The tokens left in a bucket the last time it was used.
+#[derive(Clone, Copy, Debug)]
+pub struct BucketState {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateLimitDecision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+impl TokenBucket {
+    /// Takes a token from the bucket, refilled up to `now`. A missing state is
+    /// a full bucket.
+    pub fn take(
+        &self,
+        state: Option<BucketState>,
+        now: DateTime<Utc>,
+    ) -> (BucketState, RateLimitDecision) {
+        let capacity = self.capacity as f64;
+        let tokens = match state {
+            Some(state) => {
+                let elapsed = (now - state.updated_at).to_std().unwrap_or_default();
+                (state.tokens + self.tokens_refilled_in(elapsed)).min(capacity)
+            }
+            None => capacity,
+        };
+
+        if tokens >= 1.0 {
+            let state = BucketState {
+                tokens: tokens - 1.0,
+                updated_at: now,
+            };
+            (state, RateLimitDecision::Allowed)
+        } else {
+            let retry_after = self.refill_interval.mul_f64(1.0 - tokens);
+            let state = BucketState {
+                tokens,
+                updated_at: now,
+            };
+            (state, RateLimitDecision::Limited { retry_after })
+        }
+    }
+
+    /// When the bucket holds `capacity` tokens again.
+    pub fn full_at(&self, state: &BucketState) -> DateTime<Utc> {
+        let missing = (self.capacity as f64 - state.tokens).max(0.0);
+        chrono::Duration::from_std(self.refill_interval.mul_f64(missing))
+            .ok()
+            .and_then(|delay| state.updated_at.checked_add_signed(delay))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    fn tokens_refilled_in(&self, elapsed: Duration) -> f64 {
+        if self.refill_interval.is_zero() {
+            return f64::INFINITY;
+        }
+        elapsed.as_secs_f64() / self.refill_interval.as_secs_f64()
+    }
+}
+
+/// The value of a `Retry-After` header, in whole seconds: rounded up not to
+/// invite an early retry.
+pub fn retry_after_seconds(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
+/// Where the token buckets live.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes a token from the bucket stored under `key`.
+    async fn take(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+    ) -> Result<RateLimitDecision, anyhow::Error>;
+}
+
+/// Limits the requests of every client address and the emails sent to every
+/// target address.
+pub struct RateLimiter {
+    store: Box<dyn RateLimitStore>,
+    per_ip: TokenBucket,
+    per_email: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(
+        store: Box<dyn RateLimitStore>,
+        per_ip: TokenBucket,
+        per_email: TokenBucket,
+    ) -> Self {
+        Self {
+            store,
+            per_ip,
+            per_email,
+        }
+    }
+
+    /// A request is limited as soon as either of its buckets is empty.
+    pub async fn check(
+        &self,
+        ip_address: Option<&str>,
+        email: Option<&str>,
+    ) -> Result<RateLimitDecision, anyhow::Error> {
+        if let Some(ip_address) = ip_address {
+            let key = format!("ip:{}", ip_address);
+            let decision = self.store.take(&key, &self.per_ip).await?;
+            if decision != RateLimitDecision::Allowed {
+                return Ok(decision);
+            }
+        }
+        if let Some(email) = email {
+            let key = format!("email:{}", email.trim().to_lowercase());
+            return self.store.take(&key, &self.per_email).await;
+        }
+        Ok(RateLimitDecision::Allowed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::{RateLimitDecision, TokenBucket};
+
+    fn bucket() -> TokenBucket {
+        TokenBucket {
+            capacity: 2,
+            refill_interval: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn a_full_bucket_lets_capacity_requests_through() {
+        let now = Utc::now();
+        let (state, first) = bucket().take(None, now);
+        let (state, second) = bucket().take(Some(state), now);
+        let (_, third) = bucket().take(Some(state), now);
+
+        assert_eq!(RateLimitDecision::Allowed, first);
+        assert_eq!(RateLimitDecision::Allowed, second);
+        assert_eq!(
+            RateLimitDecision::Limited {
+                retry_after: Duration::from_secs(10)
+            },
+            third
+        );
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time() {
+        let now = Utc::now();
+        let (state, _) = bucket().take(None, now);
+        let (state, _) = bucket().take(Some(state), now);
+
+        let later = now + chrono::Duration::seconds(15);
+        let (state, decision) = bucket().take(Some(state), later);
+
+        assert_eq!(RateLimitDecision::Allowed, decision);
+        assert_eq!(
+            RateLimitDecision::Limited {
+                retry_after: Duration::from_secs(5)
+            },
+            bucket().take(Some(state), later).1
+        );
+    }
+
+    #[test]
+    fn buckets_never_hold_more_than_their_capacity() {
+        let now = Utc::now();
+        let (state, _) = bucket().take(None, now);
+
+        let much_later = now + chrono::Duration::days(1);
+        let (state, _) = bucket().take(Some(state), much_later);
+
+        assert_eq!(1.0, state.tokens);
+        assert_eq!(
+            much_later + chrono::Duration::seconds(10),
+            bucket().full_at(&state)
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+use super::{BucketState, RateLimitDecision, RateLimitStore, TokenBucket};
+
+/// Keeps the buckets in the `rate_limit_buckets` table, shared by every replica.
+pub struct PgRateLimitStore {
+    db_connection_pool: PgPool,
+}
+
+impl PgRateLimitStore {
+    pub fn new(db_connection_pool: PgPool) -> Self {
+        Self { db_connection_pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for PgRateLimitStore {
+    async fn take(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+    ) -> Result<RateLimitDecision, anyhow::Error> {
+        let mut transaction = self
+            .db_connection_pool
+            .begin()
+            .await
+            .context("Failed to begin the transaction")?;
+        let state = sqlx::query!(
+            r#"SELECT tokens, updated_at FROM rate_limit_buckets WHERE key = $1 FOR UPDATE"#,
+            key
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to retrieve the rate limit bucket")?
+        .map(|r| BucketState {
+            tokens: r.tokens,
+            updated_at: r.updated_at,
+        });
+
+        let (state, decision) = bucket.take(state, Utc::now());
+        // Concurrent first requests race on the insert: the loser simply
+        // overwrites the bucket, which lets one extra request through at most.
+        sqlx::query!(
+            r#"INSERT INTO rate_limit_buckets (key, tokens, updated_at, full_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (key) DO UPDATE
+            SET tokens = EXCLUDED.tokens,
+                updated_at = EXCLUDED.updated_at,
+                full_at = EXCLUDED.full_at"#,
+            key,
+            state.tokens,
+            state.updated_at,
+            bucket.full_at(&state)
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to save the rate limit bucket")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the transaction")?;
+        Ok(decision)
+    }
+}
+
+/// Deletes the buckets that are full again, returning how many were deleted.
+#[tracing::instrument(skip_all, fields(n_purged=tracing::field::Empty), err)]
+pub async fn purge_full_buckets(db_connection_pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_purged = sqlx::query!(r#"DELETE FROM rate_limit_buckets WHERE full_at <= now()"#)
+        .execute(db_connection_pool)
+        .await
+        .context("Failed to delete the full rate limit buckets")?
+        .rows_affected();
+    tracing::Span::current().record("n_purged", n_purged);
+    Ok(n_purged)
+}
@@ -0,0 +1,103 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{purge_expired_password_reset_tokens, purge_old_login_attempts},
+    configuration::{LoginProtectionSettings, RetentionSettings, SubscriptionSettings},
+    idempotency::purge_expired_idempotency_keys,
+    rate_limiting::purge_full_buckets,
+};
+
+/// Periodically deletes the data we no longer need to keep: the pending
+/// subscriptions nobody confirmed in time, the expired data request and password
+/// reset links, the rate limit buckets that are full again, the expired idempotency keys and the
+/// login attempts past their retention.
+pub async fn run_retention_worker_until_stopped(
+    db_connection_pool: PgPool,
+    settings: RetentionSettings,
+    subscriptions: SubscriptionSettings,
+    login_protection: LoginProtectionSettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        // Failures are logged by the instrumentation, we simply try again later.
+        let _ = purge_stale_pending_subscriptions(&db_connection_pool, &subscriptions).await;
+        let _ = purge_expired_data_request_tokens(&db_connection_pool).await;
+        let _ = purge_expired_password_reset_tokens(&db_connection_pool).await;
+        let _ = purge_full_buckets(&db_connection_pool).await;
+        let _ = purge_expired_idempotency_keys(&db_connection_pool, &settings).await;
+        let _ = purge_old_login_attempts(&db_connection_pool, &login_protection).await;
+        tokio::time::sleep(settings.purge_interval()).await;
+    }
+}
+
+/// Removes pending subscriptions whose confirmation tokens have all expired,
+/// returning how many subscriptions were deleted.
+#[tracing::instrument(skip_all, fields(n_purged=tracing::field::Empty), err)]
+pub async fn purge_stale_pending_subscriptions(
+    db_connection_pool: &PgPool,
+    settings: &SubscriptionSettings,
+) -> Result<u64, anyhow::Error> {
+    let expired_before = Utc::now() - settings.confirmation_token_ttl();
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")?;
+
+    let stale_subscriber_ids: Vec<_> = sqlx::query!(
+        r#"SELECT id FROM subscriptions s
+        WHERE s.status = 'pending confirmation'
+        AND NOT EXISTS (
+            SELECT 1 FROM subscription_tokens t
+            WHERE t.subscriber_id = s.id AND t.issued_at > $1
+        )
+        FOR UPDATE SKIP LOCKED"#,
+        expired_before
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to retrieve stale pending subscriptions")?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)"#,
+        &stale_subscriber_ids
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the tokens of stale pending subscriptions")?;
+    let n_purged = sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = ANY($1)"#,
+        &stale_subscriber_ids
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete stale pending subscriptions")?
+    .rows_affected();
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transaction")?;
+
+    tracing::Span::current().record("n_purged", n_purged);
+    Ok(n_purged)
+}
+
+/// Removes the data request tokens past their expiry, returning how many were
+/// deleted.
+#[tracing::instrument(skip_all, fields(n_purged=tracing::field::Empty), err)]
+pub async fn purge_expired_data_request_tokens(
+    db_connection_pool: &PgPool,
+) -> Result<u64, anyhow::Error> {
+    let n_purged = sqlx::query!(r#"DELETE FROM data_request_tokens WHERE expires_at <= now()"#)
+        .execute(db_connection_pool)
+        .await
+        .context("Failed to delete expired data request tokens")?
+        .rows_affected();
+
+    tracing::Span::current().record("n_purged", n_purged);
+    Ok(n_purged)
+}
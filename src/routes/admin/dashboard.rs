@@ -0,0 +1,52 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{authentication::UserId, utils::e500};
+
+pub async fn admin_dashboard(
+    user_id: web::ReqData<UserId>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let username = get_username(*user_id.into_inner(), &db_connection_pool)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Admin dashboard</title>
+</head>
+<body>
+    <p>Welcome {username}!</p>
+    <p>Available actions:</p>
+    <ol>
+        <li><a href="/admin/password">Change password</a></li>
+        <li><a href="/admin/email">Change email</a></li>
+        <li>
+            <form name="logoutForm" action="/admin/logout" method="post">
+                <input type="submit" value="Logout">
+            </form>
+        </li>
+    </ol>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Get username", skip(db_connection_pool))]
+pub async fn get_username(
+    user_id: Uuid,
+    db_connection_pool: &PgPool,
+) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT username FROM users WHERE user_id = $1"#, user_id,)
+        .fetch_one(db_connection_pool)
+        .await
+        .context("Failed to perform a query to retrieve a username.")?;
+    Ok(row.username)
+}
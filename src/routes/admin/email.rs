@@ -0,0 +1,137 @@
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::Secret;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials, UserId},
+    client_address::client_address,
+    configuration::LoginProtectionSettings,
+    database_helper::{get_user_contact, set_user_email},
+    domain::SubscriberEmail,
+    routes::admin::dashboard::get_username,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct ChangeEmailFormData {
+    email: String,
+    current_password: Secret<String>,
+}
+
+pub async fn change_email_form(
+    flash_messages: IncomingFlashMessages,
+    user_id: web::ReqData<UserId>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let (_, email) = get_user_contact(&db_connection_pool, *user_id.into_inner())
+        .await
+        .map_err(e500)?;
+    let email = email.unwrap_or_default();
+    let email = htmlescape::encode_attribute(&email);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Email</title>
+</head>
+<body>
+    {msg_html}
+    <p>Password reset links are sent to this address.</p>
+    <form action="/admin/email" method="post">
+        <label>Email
+            <input
+                type="email"
+                placeholder="Enter your email"
+                name="email"
+                value="{email}"
+            >
+        </label>
+        <br>
+        <label>Current password
+            <input
+                type="password"
+                placeholder="Enter current password"
+                name="current_password"
+            >
+        </label>
+        <br>
+        <button type="submit">Change email</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+/// Password reset links go to this address: changing it takes the current
+/// password, like changing the password itself.
+pub async fn change_email(
+    request: HttpRequest,
+    form_data: web::Form<ChangeEmailFormData>,
+    db_connection_pool: web::Data<PgPool>,
+    login_protection: web::Data<LoginProtectionSettings>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let ChangeEmailFormData {
+        email,
+        current_password,
+    } = form_data.0;
+
+    let username = get_username(*user_id, &db_connection_pool)
+        .await
+        .map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: current_password,
+    };
+    let ip_address = client_address(&request);
+    if let Err(e) = validate_credentials(
+        credentials,
+        ip_address.as_deref(),
+        &login_protection,
+        &db_connection_pool,
+    )
+    .await
+    {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/email"))
+            }
+            AuthError::TooManyAttempts { .. } => {
+                FlashMessage::error(e.to_string()).send();
+                Ok(see_other("/admin/email"))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    let email = match SubscriberEmail::parse(email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/email"));
+        }
+    };
+
+    if set_user_email(&db_connection_pool, *user_id, &email)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("Your email has been changed.").send();
+    } else {
+        FlashMessage::error("This email is already used by another account.").send();
+    }
+    Ok(see_other("/admin/email"))
+}
@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    database_helper::{
+        cancel_scheduled_issue, count_issue_deliveries, delete_draft_issue, get_issue_engagement,
+        get_newsletter_issue, get_newsletter_issue_status, get_user_contact, insert_draft_issue,
+        list_issue_deliveries, list_newsletter_issues, reschedule_issue, update_draft_issue,
+        IssueDelivery,
+    },
+    domain::{issue_url, SubscriberEmail},
+    email_client::EmailClient,
+    email_templates::{newsletter_context, EmailTemplates},
+    issue_delivery_worker::DeliveryStatus,
+    utils::{e400, e500},
+};
+
+#[derive(serde::Deserialize)]
+pub struct DraftBody {
+    title: String,
+    content: Content,
+    /// Counts opens and clicks. Off unless asked for, out of respect for readers' privacy.
+    #[serde(default)]
+    tracking_enabled: bool,
+}
+
+/// Both bodies are templates, rendered for every subscriber (e.g. `{{ subscriber.name }}`).
+#[derive(serde::Deserialize)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleBody {
+    send_at: DateTime<Utc>,
+}
+
+pub async fn list_issues(
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = list_newsletter_issues(&db_connection_pool)
+        .await
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+pub async fn get_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match get_newsletter_issue(&db_connection_pool, *newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(issue) => Ok(HttpResponse::Ok().json(issue)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[tracing::instrument(name = "Creating a draft issue", skip(body, db_connection_pool))]
+pub async fn create_draft(
+    body: web::Json<DraftBody>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    validate_draft(&db_connection_pool, &body).await?;
+    let newsletter_issue_id = insert_draft_issue(
+        &db_connection_pool,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+        body.tracking_enabled,
+    )
+    .await
+    .context("Failed to store the draft issue")
+    .map_err(e500)?;
+    Ok(HttpResponse::Created()
+        .json(serde_json::json!({ "newsletter_issue_id": newsletter_issue_id })))
+}
+
+#[tracing::instrument(name = "Updating a draft issue", skip(body, db_connection_pool))]
+pub async fn update_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<DraftBody>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    validate_draft(&db_connection_pool, &body).await?;
+    if update_draft_issue(
+        &db_connection_pool,
+        newsletter_issue_id,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+        body.tracking_enabled,
+    )
+    .await
+    .map_err(e500)?
+    {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    wrong_status(&db_connection_pool, newsletter_issue_id, "draft").await
+}
+
+#[tracing::instrument(name = "Deleting a draft issue", skip(db_connection_pool))]
+pub async fn delete_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    if delete_draft_issue(&db_connection_pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    wrong_status(&db_connection_pool, newsletter_issue_id, "draft").await
+}
+
+/// Renders an issue the way subscribers will receive it, with placeholder
+/// subscriber details.
+pub async fn preview_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_newsletter_issue(&db_connection_pool, *newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let templates = EmailTemplates::load(&db_connection_pool)
+        .await
+        .map_err(e500)?;
+    let preview = templates
+        .preview_newsletter(&issue.title, &issue.html_content, &issue.text_content)
+        .map_err(e400)?;
+    Ok(HttpResponse::Ok().json(preview))
+}
+
+/// Emails an issue to the logged-in user only.
+#[tracing::instrument(
+    name = "Sending a test issue",
+    skip(db_connection_pool, email_client, base_url)
+)]
+pub async fn send_test_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    db_connection_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<Url>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_newsletter_issue(&db_connection_pool, *newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let (username, email) = get_user_contact(&db_connection_pool, *user_id.into_inner())
+        .await
+        .context("Failed to retrieve the user's contact details")
+        .map_err(e500)?;
+    let email = email
+        .ok_or_else(|| e400("Your account has no email address, set one at /admin/email"))
+        .and_then(|email| SubscriberEmail::parse(email).map_err(e400))?;
+
+    let templates = EmailTemplates::load(&db_connection_pool)
+        .await
+        .map_err(e500)?;
+    // There is no subscription to manage: the links only show where they would go.
+    let unsubscribe_link = base_url.join("/subscriptions/unsubscribe").unwrap();
+    let preferences_link = base_url.join("/preferences").unwrap();
+    let context = newsletter_context(
+        &username,
+        email.as_ref(),
+        &issue.title,
+        &issue_url(&base_url, issue.slug.as_deref()),
+        &unsubscribe_link,
+        &preferences_link,
+    );
+    let content = templates
+        .render_newsletter(&issue.html_content, &issue.text_content, &context)
+        .map_err(e400)?;
+    email_client
+        .send_email(
+            &email,
+            &format!("[TEST] {}", issue.title),
+            &content.text,
+            &content.html,
+        )
+        .await
+        .context("Failed to send the test issue")
+        .map_err(e500)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+const DELIVERIES_PER_PAGE: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct DeliveriesParameters {
+    status: Option<String>,
+    page: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveriesReport {
+    counts: BTreeMap<&'static str, i64>,
+    deliveries: Vec<IssueDelivery>,
+}
+
+/// How many subscribers got an issue, plus the deliveries themselves,
+/// optionally filtered by status (`?status=failed`).
+#[tracing::instrument(
+    name = "Listing the deliveries of an issue",
+    skip(parameters, db_connection_pool)
+)]
+pub async fn issue_deliveries(
+    newsletter_issue_id: web::Path<Uuid>,
+    parameters: web::Query<DeliveriesParameters>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let status = parameters
+        .status
+        .as_deref()
+        .map(DeliveryStatus::try_from)
+        .transpose()
+        .map_err(e400)?;
+    let page = parameters.page.unwrap_or(1);
+    if page < 1 {
+        return Err(e400("Pages are numbered from 1"));
+    }
+    if get_newsletter_issue_status(&db_connection_pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let mut counts: BTreeMap<_, _> = [
+        DeliveryStatus::Queued,
+        DeliveryStatus::Sent,
+        DeliveryStatus::Failed,
+        DeliveryStatus::Bounced,
+    ]
+    .into_iter()
+    .map(|status| (status.as_str(), 0))
+    .collect();
+    for (status, count) in count_issue_deliveries(&db_connection_pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        if let Ok(status) = DeliveryStatus::try_from(status.as_str()) {
+            counts.insert(status.as_str(), count);
+        }
+    }
+    let deliveries = list_issue_deliveries(
+        &db_connection_pool,
+        newsletter_issue_id,
+        status.map(|status| status.as_str()),
+        DELIVERIES_PER_PAGE,
+        (page - 1) * DELIVERIES_PER_PAGE,
+    )
+    .await
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(DeliveriesReport { counts, deliveries }))
+}
+
+/// Unique opens and clicks of an issue, next to the number of deliveries.
+/// Both stay at 0 unless tracking was enabled on the issue.
+pub async fn issue_stats(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match get_issue_engagement(&db_connection_pool, *newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(engagement) => Ok(HttpResponse::Ok().json(engagement)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Stops a scheduled issue from being published.
+#[tracing::instrument(name = "Cancelling a newsletter issue", skip(db_connection_pool))]
+pub async fn cancel_newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    if cancel_scheduled_issue(&db_connection_pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    wrong_status(&db_connection_pool, newsletter_issue_id, "scheduled").await
+}
+
+/// Moves a scheduled issue to a new `send_at`. Past dates publish it on the
+/// scheduler's next run.
+#[tracing::instrument(
+    name = "Rescheduling a newsletter issue",
+    skip(body, db_connection_pool)
+)]
+pub async fn reschedule_newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<RescheduleBody>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    if reschedule_issue(&db_connection_pool, newsletter_issue_id, body.send_at)
+        .await
+        .map_err(e500)?
+    {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    wrong_status(&db_connection_pool, newsletter_issue_id, "scheduled").await
+}
+
+/// Explains why an issue could not be changed: either it doesn't exist or it
+/// is no longer in the `expected` status.
+async fn wrong_status(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    expected: &str,
+) -> Result<HttpResponse, actix_web::Error> {
+    match get_newsletter_issue_status(db_connection_pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(status) => Ok(HttpResponse::Conflict().body(format!(
+            "The newsletter issue is {}, not {}",
+            status, expected
+        ))),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Rejects issues whose content doesn't render.
+async fn validate_draft(
+    db_connection_pool: &PgPool,
+    body: &DraftBody,
+) -> Result<(), actix_web::Error> {
+    let templates = EmailTemplates::load(db_connection_pool)
+        .await
+        .map_err(e500)?;
+    templates
+        .validate_newsletter(&body.title, &body.content.html, &body.content.text)
+        .map_err(e400)
+}
@@ -0,0 +1,45 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    database_helper::{insert_mailing_list, list_mailing_lists},
+    utils::{e400, e500},
+};
+
+#[derive(serde::Deserialize)]
+pub struct ListBody {
+    name: String,
+}
+
+pub async fn list_lists(
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = list_mailing_lists(&db_connection_pool)
+        .await
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(lists))
+}
+
+/// Creates a list readers can subscribe to and issues can be sent to.
+#[tracing::instrument(name = "Creating a mailing list", skip(body, db_connection_pool))]
+pub async fn create_list(
+    body: web::Json<ListBody>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(e400("List names must be between 1 and 100 characters long"));
+    }
+
+    match insert_mailing_list(&db_connection_pool, name)
+        .await
+        .context("Failed to store the mailing list")
+        .map_err(e500)?
+    {
+        Some(list_id) => {
+            Ok(HttpResponse::Created().json(serde_json::json!({ "list_id": list_id })))
+        }
+        None => Ok(HttpResponse::Conflict().body(format!("The list {} already exists", name))),
+    }
+}
@@ -0,0 +1,35 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::list_failed_login_attempts,
+    utils::{e400, e500},
+};
+
+const ATTEMPTS_PER_PAGE: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct LoginAttemptsParameters {
+    page: Option<i64>,
+}
+
+/// The audit log of failed logins, most recent first.
+#[tracing::instrument(name = "Listing failed logins", skip(parameters, db_connection_pool))]
+pub async fn failed_logins(
+    parameters: web::Query<LoginAttemptsParameters>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = parameters.page.unwrap_or(1);
+    if page < 1 {
+        return Err(e400("Pages are numbered from 1"));
+    }
+
+    let attempts = list_failed_login_attempts(
+        &db_connection_pool,
+        ATTEMPTS_PER_PAGE,
+        (page - 1) * ATTEMPTS_PER_PAGE,
+    )
+    .await
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(attempts))
+}
@@ -0,0 +1,19 @@
+mod dashboard;
+mod email;
+mod issues;
+mod lists;
+mod login_attempts;
+mod logout;
+mod password;
+mod subscribers;
+mod templates;
+
+pub use dashboard::*;
+pub use email::*;
+pub use issues::*;
+pub use lists::*;
+pub use login_attempts::*;
+pub use logout::*;
+pub use password::*;
+pub use subscribers::*;
+pub use templates::*;
@@ -0,0 +1,138 @@
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{self, validate_credentials, AuthError, Credentials, UserId},
+    client_address::client_address,
+    configuration::LoginProtectionSettings,
+    domain::NewPassword,
+    routes::admin::dashboard::get_username,
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct ChangePasswordFormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+pub async fn change_password_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/password" method="post">
+        <label>Current password
+            <input
+                type="password"
+                placeholder="Enter current password"
+                name="current_password"
+            >
+        </label>
+        <br>
+        <label>New password
+            <input
+                type="password"
+                placeholder="Enter new password"
+                name="new_password"
+            >
+        </label>
+        <br>
+        <label>Confirm new password
+            <input
+                type="password"
+                placeholder="Type the new password again"
+                name="new_password_check"
+            >
+        </label>
+        <br>
+        <button type="submit">Change password</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+pub async fn change_password(
+    request: HttpRequest,
+    form_data: web::Form<ChangePasswordFormData>,
+    db_connection_pool: web::Data<PgPool>,
+    login_protection: web::Data<LoginProtectionSettings>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    if form_data.new_password.expose_secret() != form_data.new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    let username = get_username(*user_id, &db_connection_pool)
+        .await
+        .map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form_data.0.current_password,
+    };
+    let ip_address = client_address(&request);
+    if let Err(e) = validate_credentials(
+        credentials,
+        ip_address.as_deref(),
+        &login_protection,
+        &db_connection_pool,
+    )
+    .await
+    {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/password"))
+            }
+            AuthError::TooManyAttempts { .. } => {
+                FlashMessage::error(e.to_string()).send();
+                Ok(see_other("/admin/password"))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    let new_password = match NewPassword::parse(form_data.0.new_password) {
+        Ok(new_password) => new_password,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/password"));
+        }
+    };
+
+    authentication::change_password(*user_id, new_password, &db_connection_pool)
+        .await
+        .map_err(e500)?;
+    // Every session of the user is gone, this one carries on under a new key.
+    session.renew();
+    FlashMessage::info("Your password has been changed.").send();
+    Ok(see_other("/admin/password"))
+}
@@ -0,0 +1,489 @@
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use futures_util::stream;
+use reqwest::Url;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::borrow::Cow;
+use uuid::Uuid;
+
+use crate::{
+    database_helper::{
+        confirm_subscriber, delete_subscriber, get_subscriber, import_subscriber, list_subscribers,
+        list_subscribers_after, list_subscription_events, record_subscription_event,
+        resolve_list_id, store_token, unsubscribe_subscriber, ImportOutcome, SubscriberRecord,
+    },
+    domain::{Subscriber, SubscriberEmail, SubscriberName},
+    email_templates::EmailTemplates,
+    routes::{enqueue_confirmation_email, generate_subscription_token},
+    subscription_events::{EventSource, SubscriptionEventKind},
+    utils::{e400, e500},
+};
+
+const SUBSCRIBERS_PER_PAGE: i64 = 50;
+
+const SUBSCRIBER_STATUSES: [&str; 5] = [
+    "pending confirmation",
+    "confirmed",
+    "unsubscribed",
+    "bounced",
+    "complained",
+];
+
+#[derive(serde::Deserialize)]
+pub struct SubscribersParameters {
+    status: Option<String>,
+    email: Option<String>,
+    page: Option<i64>,
+}
+
+/// Subscribers ordered by email, filtered by `?status=` and by a part of
+/// their email (`?email=`).
+#[tracing::instrument(name = "Listing subscribers", skip(parameters, db_connection_pool))]
+pub async fn browse_subscribers(
+    parameters: web::Query<SubscribersParameters>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    validate_status(parameters.status.as_deref())?;
+    let page = parameters.page.unwrap_or(1);
+    if page < 1 {
+        return Err(e400("Pages are numbered from 1"));
+    }
+
+    let subscribers = list_subscribers(
+        &db_connection_pool,
+        parameters.status.as_deref(),
+        parameters.email.as_deref(),
+        SUBSCRIBERS_PER_PAGE,
+        (page - 1) * SUBSCRIBERS_PER_PAGE,
+    )
+    .await
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(subscribers))
+}
+
+pub async fn view_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match get_subscriber(&db_connection_pool, *subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber) => Ok(HttpResponse::Ok().json(subscriber)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Confirms a subscriber on their behalf, e.g. when the confirmation email
+/// never reached them.
+#[tracing::instrument(
+    name = "Manually confirming a subscriber",
+    skip(request, db_connection_pool)
+)]
+pub async fn confirm_subscription(
+    request: HttpRequest,
+    subscriber_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = db_connection_pool.begin().await.map_err(e500)?;
+    let found = confirm_subscriber(&mut transaction, *subscriber_id, None)
+        .await
+        .map_err(e500)?;
+    if found {
+        record_admin_action(
+            transaction,
+            &request,
+            *subscriber_id,
+            SubscriptionEventKind::Confirmed,
+        )
+        .await?;
+    }
+    unchanged_unless(found, &db_connection_pool, *subscriber_id).await
+}
+
+#[tracing::instrument(
+    name = "Manually unsubscribing a subscriber",
+    skip(request, db_connection_pool)
+)]
+pub async fn cancel_subscription(
+    request: HttpRequest,
+    subscriber_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = db_connection_pool.begin().await.map_err(e500)?;
+    let found = unsubscribe_subscriber(&mut transaction, *subscriber_id)
+        .await
+        .map_err(e500)?;
+    if found {
+        record_admin_action(
+            transaction,
+            &request,
+            *subscriber_id,
+            SubscriptionEventKind::Unsubscribed,
+        )
+        .await?;
+    }
+    unchanged_unless(found, &db_connection_pool, *subscriber_id).await
+}
+
+/// Records the action in the audit trail, committing it together with the
+/// change it records.
+async fn record_admin_action(
+    mut transaction: Transaction<'_, Postgres>,
+    request: &HttpRequest,
+    subscriber_id: Uuid,
+    kind: SubscriptionEventKind,
+) -> Result<(), actix_web::Error> {
+    record_subscription_event(
+        &mut transaction,
+        subscriber_id,
+        kind,
+        &EventSource::from_request(request),
+        Some("By an administrator"),
+    )
+    .await
+    .map_err(e500)?;
+    transaction.commit().await.map_err(e500)
+}
+
+/// The consent audit trail of a subscriber, to answer compliance requests.
+#[tracing::instrument(name = "Exporting consent history", skip(db_connection_pool))]
+pub async fn export_consent_history(
+    subscriber_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(subscriber) = get_subscriber(&db_connection_pool, *subscriber_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let events = list_subscription_events(&db_connection_pool, *subscriber_id)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "consent-history-{}.json",
+                subscriber.id
+            ))],
+        })
+        .json(serde_json::json!({ "subscriber": subscriber, "events": events })))
+}
+
+/// Erases every trace of a subscriber, as required by GDPR erasure requests.
+#[tracing::instrument(name = "Deleting a subscriber", skip(db_connection_pool))]
+pub async fn erase_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let found = delete_subscriber(&db_connection_pool, *subscriber_id)
+        .await
+        .map_err(e500)?;
+    Ok(no_content_if(found))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ImportParameters {
+    /// Adds the subscribers as confirmed instead of emailing them a confirmation link.
+    #[serde(default)]
+    confirmed: bool,
+    /// The list to import into, the default one if missing.
+    list_id: Option<Uuid>,
+}
+
+#[derive(serde::Deserialize)]
+struct ImportRow {
+    name: String,
+    email: String,
+}
+
+#[derive(serde::Serialize, Default)]
+struct ImportReport {
+    /// New subscribers.
+    imported: usize,
+    /// Known subscribers who joined the list.
+    added_to_list: usize,
+    already_on_list: usize,
+    /// Known subscribers whose address bounced or complained.
+    undeliverable: usize,
+    errors: Vec<RowError>,
+}
+
+#[derive(serde::Serialize)]
+struct RowError {
+    line: u64,
+    error: String,
+}
+
+/// Adds the subscribers of a CSV file with `name` and `email` columns.
+///
+/// Valid rows are imported even when others are rejected, and emails already
+/// on the list are skipped: a fixed file can be imported again. Known
+/// subscribers are added to the list, keeping their details.
+#[tracing::instrument(
+    name = "Importing subscribers",
+    skip(request, body, db_connection_pool, base_url)
+)]
+pub async fn import_subscribers(
+    request: HttpRequest,
+    parameters: web::Query<ImportParameters>,
+    body: web::Bytes,
+    db_connection_pool: web::Data<PgPool>,
+    base_url: web::Data<Url>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body.as_ref());
+    let headers = reader.headers().map_err(e400)?.clone();
+    if !headers.iter().any(|h| h == "name") || !headers.iter().any(|h| h == "email") {
+        return Err(e400("The CSV file must have a name and an email column"));
+    }
+
+    let list_id = resolve_list_id(&db_connection_pool, parameters.list_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e400("The list does not exist"))?;
+
+    let mut report = ImportReport::default();
+    let mut subscribers = Vec::new();
+    for record in reader.records() {
+        let (line, parsed) = match record {
+            Ok(record) => (
+                record.position().map_or(0, |p| p.line()),
+                parse_row(&record, &headers),
+            ),
+            Err(error) => (
+                error.position().map_or(0, |p| p.line()),
+                Err(error.to_string()),
+            ),
+        };
+        match parsed {
+            Ok(subscriber) => subscribers.push(subscriber),
+            Err(error) => report.errors.push(RowError { line, error }),
+        }
+    }
+
+    let status = if parameters.confirmed {
+        "confirmed"
+    } else {
+        "pending confirmation"
+    };
+    let templates = EmailTemplates::load(&db_connection_pool)
+        .await
+        .map_err(e500)?;
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")
+        .map_err(e500)?;
+    let source = EventSource::from_request(&request);
+    let detail = format!("Imported into list {}", list_id);
+    for subscriber in subscribers {
+        let subscriber_id = match import_subscriber(&subscriber, status, list_id, &mut transaction)
+            .await
+            .context("Failed to import a subscriber")
+            .map_err(e500)?
+        {
+            ImportOutcome::Created(subscriber_id) => {
+                report.imported += 1;
+                subscriber_id
+            }
+            ImportOutcome::AddedToList(subscriber_id) => {
+                report.added_to_list += 1;
+                subscriber_id
+            }
+            ImportOutcome::AlreadyOnList => {
+                report.already_on_list += 1;
+                continue;
+            }
+            ImportOutcome::Undeliverable => {
+                report.undeliverable += 1;
+                continue;
+            }
+        };
+        // Confirmation emails go through the outbox: they are only sent if the
+        // import is committed, and the import never waits for the provider.
+        let events: &[_] = if parameters.confirmed {
+            &[
+                SubscriptionEventKind::Subscribed,
+                SubscriptionEventKind::Confirmed,
+            ]
+        } else {
+            let token = generate_subscription_token();
+            store_token(&mut transaction, subscriber_id, list_id, &token)
+                .await
+                .map_err(e500)?;
+            enqueue_confirmation_email(
+                &mut transaction,
+                &templates,
+                &subscriber,
+                &base_url,
+                &token,
+            )
+            .await
+            .map_err(e500)?;
+            &[
+                SubscriptionEventKind::Subscribed,
+                SubscriptionEventKind::ConfirmationSent,
+            ]
+        };
+        for kind in events {
+            record_subscription_event(
+                &mut transaction,
+                subscriber_id,
+                *kind,
+                &source,
+                Some(&detail),
+            )
+            .await
+            .map_err(e500)?;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transaction")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+fn parse_row(
+    record: &csv::StringRecord,
+    headers: &csv::StringRecord,
+) -> Result<Subscriber, String> {
+    let row: ImportRow = record
+        .deserialize(Some(headers))
+        .map_err(|e| e.to_string())?;
+    Ok(Subscriber {
+        name: SubscriberName::parse(row.name)?,
+        email: SubscriberEmail::parse(row.email)?,
+    })
+}
+
+const EXPORT_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize)]
+pub struct ExportParameters {
+    status: Option<String>,
+}
+
+/// Streams the list, optionally filtered by `?status=`, as a CSV file.
+/// Subscribers are read a page at a time so large lists are never held in memory.
+#[tracing::instrument(name = "Exporting subscribers", skip(parameters, db_connection_pool))]
+pub async fn export_subscribers(
+    parameters: web::Query<ExportParameters>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    validate_status(parameters.status.as_deref())?;
+    let status = parameters.into_inner().status;
+
+    // Each step of the stream carries the email to resume after (empty at
+    // first, every email sorts after it), or `None` once the last page is sent.
+    let pages = stream::try_unfold(
+        (Some(String::new()), true),
+        move |(after_email, is_first_page)| {
+            let db_connection_pool = db_connection_pool.clone();
+            let status = status.clone();
+            async move {
+                let Some(after_email) = after_email else {
+                    return Ok(None);
+                };
+                let subscribers = list_subscribers_after(
+                    &db_connection_pool,
+                    status.as_deref(),
+                    Some(after_email.as_str()),
+                    EXPORT_PAGE_SIZE,
+                )
+                .await
+                .map_err(e500)?;
+                let next = (subscribers.len() as i64 == EXPORT_PAGE_SIZE)
+                    .then(|| subscribers.last().map(|s| s.email.clone()))
+                    .flatten();
+                let chunk = to_csv(&subscribers, is_first_page).map_err(e500)?;
+                Ok::<_, actix_web::Error>(Some((web::Bytes::from(chunk), (next, false))))
+            }
+        },
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscribers.csv".into())],
+        })
+        .streaming(pages))
+}
+
+fn to_csv(subscribers: &[SubscriberRecord], with_headers: bool) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    if with_headers {
+        writer.write_record(["email", "name", "status", "subscribed_at"])?;
+    }
+    for subscriber in subscribers {
+        writer.write_record([
+            &neutralise_formula(&subscriber.email),
+            &neutralise_formula(&subscriber.name),
+            subscriber.status.as_str(),
+            &subscriber.subscribed_at.to_rfc3339(),
+        ])?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// Spreadsheets evaluate cells starting with `=`, `+`, `-`, `@`, a tab or a
+/// carriage return as formulas: a leading quote keeps subscriber-supplied
+/// values as plain text.
+fn neutralise_formula(cell: &str) -> Cow<'_, str> {
+    if cell.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", cell))
+    } else {
+        Cow::Borrowed(cell)
+    }
+}
+
+fn validate_status(status: Option<&str>) -> Result<(), actix_web::Error> {
+    match status {
+        Some(status) if !SUBSCRIBER_STATUSES.contains(&status) => {
+            Err(e400(format!("{} is not a subscriber status", status)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// 404 if there is no such subscriber, 409 if they exist but the change was
+/// refused because their address is undeliverable.
+async fn unchanged_unless(
+    changed: bool,
+    db_connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<HttpResponse, actix_web::Error> {
+    if changed {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    let exists = get_subscriber(db_connection_pool, subscriber_id)
+        .await
+        .map_err(e500)?
+        .is_some();
+    Ok(if exists {
+        HttpResponse::Conflict().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    })
+}
+
+fn no_content_if(found: bool) -> HttpResponse {
+    if found {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
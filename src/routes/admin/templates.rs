@@ -0,0 +1,74 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    email_templates::{get_template, list_templates, save_template, EmailTemplates},
+    utils::{e400, e500},
+};
+
+#[derive(serde::Deserialize)]
+pub struct TemplateBody {
+    source: String,
+}
+
+pub async fn list_email_templates(
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let templates = list_templates(&db_connection_pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok().json(templates))
+}
+
+pub async fn get_email_template(
+    name: web::Path<String>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match get_template(&db_connection_pool, &name)
+        .await
+        .map_err(e500)?
+    {
+        Some(template) => Ok(HttpResponse::Ok().json(template)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Creates or replaces a template, rejecting it if it doesn't compile or if it
+/// breaks any of the emails sent by the application.
+#[tracing::instrument(name = "Saving an email template", skip(body, db_connection_pool))]
+pub async fn put_email_template(
+    name: web::Path<String>,
+    body: web::Json<TemplateBody>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = name.into_inner();
+    validate_template_name(&name).map_err(e400)?;
+
+    let mut templates: Vec<_> = list_templates(&db_connection_pool)
+        .await
+        .map_err(e500)?
+        .into_iter()
+        .filter(|t| t.name != name)
+        .map(|t| (t.name, t.source))
+        .collect();
+    templates.push((name.clone(), body.source.clone()));
+    EmailTemplates::new(templates)
+        .and_then(|templates| templates.validate())
+        .map_err(e400)?;
+
+    save_template(&db_connection_pool, &name, &body.source)
+        .await
+        .context("Failed to save the email template")
+        .map_err(e500)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn validate_template_name(name: &str) -> Result<(), String> {
+    let is_valid_char = |c: char| c.is_ascii_alphanumeric() || "_-./".contains(c);
+    if name.len() > 100 || !name.chars().all(is_valid_char) || name.contains("..") {
+        return Err(format!("{} is not a valid template name", name));
+    }
+    if !name.ends_with(".html") && !name.ends_with(".txt") {
+        return Err("Template names must end with .html or .txt".into());
+    }
+    Ok(())
+}
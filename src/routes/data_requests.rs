@@ -0,0 +1,273 @@
+use actix_web::{
+    http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use reqwest::Url;
+use sqlx::{PgExecutor, PgPool};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    database_helper::{
+        delete_data_request_token, delete_subscriber, export_subscriber_data,
+        get_subscriber_id_from_data_request_token, get_subscriber_id_from_email,
+        issue_data_request_token,
+    },
+    domain::SubscriberEmail,
+    email_outbox::enqueue_email,
+    utils::{e500, see_other},
+};
+
+/// How long the link sent to a subscriber asking about their data stays valid.
+const DATA_REQUEST_TOKEN_TTL_HOURS: i64 = 24;
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestParameters {
+    token: String,
+}
+
+pub async fn data_request_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    {msg_html}
+    <p>Enter the email you subscribed with: we will send you a link to download or erase the data we hold about you.</p>
+    <form action="/data-requests" method="post">
+        <label>Email
+            <input
+                type="email"
+                placeholder="Enter your email"
+                name="email"
+            >
+        </label>
+        <button type="submit">Send link</button>
+    </form>
+</body>
+</html>"#,
+        ))
+}
+
+#[tracing::instrument(
+    name = "Requesting subscriber data",
+    skip(form_data, db_connection_pool, base_url)
+)]
+pub async fn request_data(
+    form_data: web::Form<DataRequestFormData>,
+    db_connection_pool: web::Data<PgPool>,
+    base_url: web::Data<Url>,
+) -> HttpResponse {
+    // The answer is the same whether the email is subscribed or not, and even
+    // if something went wrong, to avoid leaking who our subscribers are.
+    FlashMessage::info("If we hold data about that email, you will receive a link shortly.").send();
+
+    if let Ok(recipient) = SubscriberEmail::parse(form_data.0.email) {
+        if let Err(error) = enqueue_data_request(&db_connection_pool, &recipient, &base_url).await {
+            tracing::error!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Failed to handle a data request"
+            );
+        }
+    }
+    see_other("/data-requests")
+}
+
+/// Queues the email with the data request link, if the email belongs to a
+/// subscriber. The email goes through the outbox: the request never waits
+/// for the email provider.
+async fn enqueue_data_request(
+    db_connection_pool: &PgPool,
+    recipient: &SubscriberEmail,
+    base_url: &Url,
+) -> Result<(), anyhow::Error> {
+    let subscriber_id = match get_subscriber_id_from_email(db_connection_pool, recipient.as_ref())
+        .await
+        .context("Failed to get the subscriber from the email")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => return Ok(()),
+    };
+
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")?;
+    let token = issue_data_request_token(
+        &mut transaction,
+        subscriber_id,
+        chrono::Duration::hours(DATA_REQUEST_TOKEN_TTL_HOURS),
+    )
+    .await
+    .context("Failed to issue the data request token")?;
+    enqueue_data_request_email(&mut transaction, recipient, base_url, &token).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transaction")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Enqueuing data request email", skip(executor, base_url, token))]
+async fn enqueue_data_request_email(
+    executor: impl PgExecutor<'_>,
+    recipient: &SubscriberEmail,
+    base_url: &Url,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let mut link = base_url.join("/data-requests/manage").unwrap();
+    link.query_pairs_mut().append_pair("token", token);
+
+    let plain_body = format!(
+        "Someone asked for the data we hold about this email address.\n\
+        Visit {} to download or erase it. If it wasn't you, you can ignore this email.",
+        link
+    );
+    let html_body = format!(
+        "Someone asked for the data we hold about this email address.<br />\
+        Click <a href=\"{}\">here</a> to download or erase it. \
+        If it wasn't you, you can ignore this email.",
+        link
+    );
+
+    enqueue_email(executor, recipient, "Your data", &plain_body, &html_body).await
+}
+
+/// Checks the token of a data request link. Invalid or expired links send
+/// the subscriber back to the request form.
+async fn verify_token(
+    db_connection_pool: &PgPool,
+    token: &str,
+) -> Result<Result<Uuid, HttpResponse>, actix_web::Error> {
+    match get_subscriber_id_from_data_request_token(db_connection_pool, token)
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber_id) => Ok(Ok(subscriber_id)),
+        None => {
+            FlashMessage::error("The link is invalid or has expired.").send();
+            Ok(Err(see_other("/data-requests")))
+        }
+    }
+}
+
+fn with_token(path: &str, token: &str) -> String {
+    format!(
+        "{}?{}",
+        path,
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("token", token)
+            .finish()
+    )
+}
+
+pub async fn manage_data(
+    parameters: web::Query<DataRequestParameters>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(response) = verify_token(&db_connection_pool, &parameters.token).await? {
+        return Ok(response);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    <p>Downloading your data uses up this link: ask for a new one to erase it afterwards.</p>
+    <p><a href="{export}">Download a copy of your data</a></p>
+    <form action="{erase}" method="post">
+        <p>Erasing your data unsubscribes you from every list. It can't be undone.</p>
+        <button type="submit">Erase my data</button>
+    </form>
+</body>
+</html>"#,
+            export =
+                htmlescape::encode_minimal(&with_token("/data-requests/export", &parameters.token)),
+            erase =
+                htmlescape::encode_minimal(&with_token("/data-requests/erase", &parameters.token)),
+        )))
+}
+
+/// Every row we hold about the subscriber, as a JSON download. The link is
+/// used up: erasing the data afterwards takes a new one.
+#[tracing::instrument(
+    name = "Exporting subscriber data",
+    skip(parameters, db_connection_pool)
+)]
+pub async fn export_data(
+    parameters: web::Query<DataRequestParameters>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = match verify_token(&db_connection_pool, &parameters.token).await? {
+        Ok(subscriber_id) => subscriber_id,
+        Err(response) => return Ok(response),
+    };
+    let Some(data) = export_subscriber_data(&db_connection_pool, subscriber_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    delete_data_request_token(&db_connection_pool, &parameters.token)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("my-data.json".into())],
+        })
+        .json(data))
+}
+
+/// Deletes the subscriber and every row about them, the data request tokens
+/// included, in a single transaction.
+#[tracing::instrument(name = "Erasing subscriber data", skip(parameters, db_connection_pool))]
+pub async fn erase_data(
+    parameters: web::Query<DataRequestParameters>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = match verify_token(&db_connection_pool, &parameters.token).await? {
+        Ok(subscriber_id) => subscriber_id,
+        Err(response) => return Ok(response),
+    };
+    delete_subscriber(&db_connection_pool, subscriber_id)
+        .await
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Data erased</title>
+</head>
+<body>
+    <p>Your data has been erased. You will not hear from us again.</p>
+</body>
+</html>"#,
+    ))
+}
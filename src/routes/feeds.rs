@@ -0,0 +1,243 @@
+use actix_web::{
+    http::header::{
+        ContentType, ETag, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+        IF_NONE_MATCH,
+    },
+    web, HttpRequest, HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::fmt::Write;
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::{
+    database_helper::list_published_issues,
+    domain::issue_url,
+    email_templates::{newsletter_context, EmailTemplates},
+    utils::e500,
+};
+
+const FEED_TITLE: &str = "Newsletter";
+const FEED_LENGTH: i64 = 20;
+
+struct FeedEntry {
+    id: Uuid,
+    title: String,
+    url: Url,
+    html: String,
+    published_at: DateTime<Utc>,
+}
+
+/// RSS 2.0 feed of the latest published issues.
+#[tracing::instrument(name = "Serving the RSS feed", skip_all)]
+pub async fn rss_feed(
+    request: HttpRequest,
+    db_connection_pool: web::Data<PgPool>,
+    base_url: web::Data<Url>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let entries = feed_entries(&db_connection_pool, &base_url).await?;
+    let archive_url = base_url.join("/issues").unwrap();
+
+    let mut items = String::new();
+    for entry in &entries {
+        write!(
+            items,
+            r#"
+    <item>
+      <title>{title}</title>
+      <link>{url}</link>
+      <guid isPermaLink="true">{url}</guid>
+      <pubDate>{published_at}</pubDate>
+      <description>{html}</description>
+    </item>"#,
+            title = xml_escape(&entry.title),
+            url = xml_escape(entry.url.as_str()),
+            published_at = entry.published_at.to_rfc2822(),
+            html = xml_escape(&entry.html),
+        )
+        .unwrap();
+    }
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+  <channel>
+    <title>{title}</title>
+    <link>{archive_url}</link>
+    <description>Published issues of the {title}</description>
+    <atom:link href="{feed_url}" rel="self" type="application/rss+xml" />{items}
+  </channel>
+</rss>
+"#,
+        title = xml_escape(FEED_TITLE),
+        archive_url = xml_escape(archive_url.as_str()),
+        feed_url = xml_escape(base_url.join("/feed.rss").unwrap().as_str()),
+    );
+
+    Ok(conditional_response(
+        &request,
+        body,
+        "application/rss+xml; charset=utf-8",
+        &entries,
+    ))
+}
+
+/// Atom feed of the latest published issues.
+#[tracing::instrument(name = "Serving the Atom feed", skip_all)]
+pub async fn atom_feed(
+    request: HttpRequest,
+    db_connection_pool: web::Data<PgPool>,
+    base_url: web::Data<Url>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let entries = feed_entries(&db_connection_pool, &base_url).await?;
+    let archive_url = base_url.join("/issues").unwrap();
+    let updated = entries
+        .first()
+        .map(|entry| entry.published_at)
+        .unwrap_or(DateTime::<Utc>::UNIX_EPOCH);
+
+    let mut items = String::new();
+    for entry in &entries {
+        write!(
+            items,
+            r#"
+  <entry>
+    <title>{title}</title>
+    <link rel="alternate" href="{url}" />
+    <id>urn:uuid:{id}</id>
+    <updated>{published_at}</updated>
+    <published>{published_at}</published>
+    <content type="html">{html}</content>
+  </entry>"#,
+            title = xml_escape(&entry.title),
+            url = xml_escape(entry.url.as_str()),
+            id = entry.id,
+            published_at = entry.published_at.to_rfc3339(),
+            html = xml_escape(&entry.html),
+        )
+        .unwrap();
+    }
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>{title}</title>
+  <id>{archive_url}</id>
+  <link rel="alternate" href="{archive_url}" />
+  <link rel="self" href="{feed_url}" />
+  <updated>{updated}</updated>
+  <author><name>{title}</name></author>{items}
+</feed>
+"#,
+        title = xml_escape(FEED_TITLE),
+        archive_url = xml_escape(archive_url.as_str()),
+        feed_url = xml_escape(base_url.join("/feed.atom").unwrap().as_str()),
+        updated = updated.to_rfc3339(),
+    );
+
+    Ok(conditional_response(
+        &request,
+        body,
+        "application/atom+xml; charset=utf-8",
+        &entries,
+    ))
+}
+
+/// The latest published issues, rendered the way the web version shows them.
+async fn feed_entries(
+    db_connection_pool: &PgPool,
+    base_url: &Url,
+) -> Result<Vec<FeedEntry>, actix_web::Error> {
+    let issues = list_published_issues(db_connection_pool, FEED_LENGTH, 0)
+        .await
+        .map_err(e500)?;
+    let templates = EmailTemplates::load(db_connection_pool)
+        .await
+        .map_err(e500)?;
+    let unsubscribe_url = base_url.join("/subscriptions/unsubscribe").unwrap();
+    let preferences_url = base_url.join("/preferences").unwrap();
+
+    let mut entries = Vec::with_capacity(issues.len());
+    for issue in issues {
+        let url = issue_url(base_url, Some(&issue.slug));
+        let context = newsletter_context(
+            "reader",
+            "",
+            &issue.title,
+            &url,
+            &unsubscribe_url,
+            &preferences_url,
+        );
+        let html = templates
+            .render_issue_html(&issue.html_content, &context)
+            .map_err(e500)?;
+        entries.push(FeedEntry {
+            id: issue.newsletter_issue_id,
+            title: issue.title,
+            url,
+            html,
+            published_at: issue.published_at,
+        });
+    }
+    Ok(entries)
+}
+
+/// Answers `304 Not Modified` when the reader already has this version of the
+/// feed. `If-None-Match` takes precedence over `If-Modified-Since`.
+fn conditional_response(
+    request: &HttpRequest,
+    body: String,
+    content_type: &str,
+    entries: &[FeedEntry],
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(format!("{:x}", Sha256::digest(body.as_bytes()))[..32].into());
+    // HTTP dates have a one second resolution.
+    let last_modified = entries.first().map(|entry| {
+        HttpDate::from(UNIX_EPOCH + Duration::from_secs(entry.published_at.timestamp() as u64))
+    });
+
+    let not_modified = if request.headers().contains_key(IF_NONE_MATCH) {
+        match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        }
+    } else {
+        match (IfModifiedSince::parse(request), last_modified) {
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => last_modified <= since,
+            _ => false,
+        }
+    };
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.insert_header(ETag(etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if not_modified {
+        return response.finish();
+    }
+    response
+        .content_type(ContentType(content_type.parse().unwrap()))
+        .body(body)
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,144 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use reqwest::Url;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    database_helper::{get_published_issue, list_published_issues},
+    domain::issue_url,
+    email_templates::{newsletter_context, EmailTemplates},
+    utils::{e400, e500},
+};
+
+const ISSUES_PER_PAGE: i64 = 20;
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveParameters {
+    page: Option<i64>,
+}
+
+/// The public archive of published issues, most recent first.
+#[tracing::instrument(
+    name = "Showing the issue archive",
+    skip(parameters, db_connection_pool)
+)]
+pub async fn issues_archive(
+    parameters: web::Query<ArchiveParameters>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = parameters.page.unwrap_or(1);
+    if page < 1 {
+        return Err(e400("Pages are numbered from 1"));
+    }
+
+    // One more issue than needed tells us whether there is a next page.
+    let mut issues = list_published_issues(
+        &db_connection_pool,
+        ISSUES_PER_PAGE + 1,
+        (page - 1) * ISSUES_PER_PAGE,
+    )
+    .await
+    .map_err(e500)?;
+    let has_next_page = issues.len() as i64 > ISSUES_PER_PAGE;
+    issues.truncate(ISSUES_PER_PAGE as usize);
+
+    let mut items = String::new();
+    for issue in &issues {
+        writeln!(
+            items,
+            r#"        <li><a href="/issues/{}">{}</a> <time>{}</time></li>"#,
+            // Slugs only contain lowercase letters, digits and dashes.
+            issue.slug,
+            htmlescape::encode_minimal(&issue.title),
+            issue.published_at.format("%Y-%m-%d")
+        )
+        .unwrap();
+    }
+    let mut navigation = String::new();
+    if page > 1 {
+        write!(
+            navigation,
+            r#"<a href="/issues?page={}">Newer issues</a> "#,
+            page - 1
+        )
+        .unwrap();
+    }
+    if has_next_page {
+        write!(
+            navigation,
+            r#"<a href="/issues?page={}">Older issues</a>"#,
+            page + 1
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter archive</title>
+</head>
+<body>
+    <h1>Newsletter archive</h1>
+    <ol>
+{items}    </ol>
+    <nav>{navigation}</nav>
+</body>
+</html>"#,
+        )))
+}
+
+/// The web version of a published issue, linked from every email.
+#[tracing::instrument(name = "Showing an issue", skip(db_connection_pool, base_url))]
+pub async fn issue_page(
+    slug: web::Path<String>,
+    db_connection_pool: web::Data<PgPool>,
+    base_url: web::Data<Url>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_published_issue(&db_connection_pool, &slug)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let templates = EmailTemplates::load(&db_connection_pool)
+        .await
+        .map_err(e500)?;
+    // Readers of the web version are anonymous: personalised bits get a
+    // generic value instead.
+    let context = newsletter_context(
+        "reader",
+        "",
+        &issue.title,
+        &issue_url(&base_url, Some(&slug)),
+        &base_url.join("/subscriptions/unsubscribe").unwrap(),
+        &base_url.join("/preferences").unwrap(),
+    );
+    let content = templates
+        .render_issue_html(&issue.html_content, &context)
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <article>
+{content}
+    </article>
+    <p><a href="/issues">All issues</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&issue.title),
+        )))
+}
@@ -0,0 +1,136 @@
+use actix_web::{
+    error::InternalError, http::header::ContentType, web, HttpRequest, HttpResponse, ResponseError,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use reqwest::StatusCode;
+use secrecy::Secret;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    client_address::client_address,
+    configuration::LoginProtectionSettings,
+    session_state::TypedSession,
+    telemetry::error_chain_fmt,
+    utils::see_other,
+};
+
+#[derive(serde::Deserialize)]
+pub struct LoginFormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error(transparent)]
+    TooManyAttempts(AuthError),
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for LoginError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SEE_OTHER
+    }
+}
+
+pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut error_html = String::new();
+    for message in flash_messages.iter() {
+        writeln!(error_html, "<p><i>{}</i></p>", message.content()).unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {error_html}
+    <form action="/login" method="post">
+        <label>Username
+            <input
+                type="text"
+                placeholder="Enter Username"
+                name="username"
+            >
+        </label>
+        <label>Password
+            <input
+                type="password"
+                placeholder="Enter Password"
+                name="password"
+            >
+        </label>
+        <button type="submit">Login</button>
+    </form>
+    <p><a href="/password/forgot">Forgot your password?</a></p>
+</body>
+</html>"#,
+        ))
+}
+
+#[tracing::instrument(
+    name = "Logging in",
+    skip(request, form_data, db_connection_pool, login_protection, session),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn login(
+    request: HttpRequest,
+    form_data: web::Form<LoginFormData>,
+    db_connection_pool: web::Data<PgPool>,
+    login_protection: web::Data<LoginProtectionSettings>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let credentials = Credentials {
+        username: form_data.0.username,
+        password: form_data.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    let ip_address = client_address(&request);
+    match validate_credentials(
+        credentials,
+        ip_address.as_deref(),
+        &login_protection,
+        &db_connection_pool,
+    )
+    .await
+    {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            session.renew();
+            session
+                .insert_user_id(user_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(e) => {
+            let e = match e {
+                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::TooManyAttempts { .. } => LoginError::TooManyAttempts(e),
+                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+            };
+            Err(login_redirect(e))
+        }
+    }
+}
+
+fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    InternalError::from_response(e, see_other("/login"))
+}
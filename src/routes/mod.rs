@@ -1,9 +1,29 @@
+mod admin;
+mod data_requests;
+mod feeds;
 mod health_check;
+mod issues;
+mod login;
 mod newsletters;
+mod password_reset;
+mod preferences;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
 
+pub use admin::*;
+pub use data_requests::*;
+pub use feeds::*;
 pub use health_check::*;
+pub use issues::*;
+pub use login::*;
 pub use newsletters::*;
+pub use password_reset::*;
+pub use preferences::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
+pub use webhooks::*;
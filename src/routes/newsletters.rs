@@ -1,32 +1,45 @@
-use actix_web::{http::header::HeaderMap, web, HttpRequest, HttpResponse, ResponseError};
-use anyhow::{anyhow, Context};
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use actix_web::{
+    http::header::{HeaderMap, RETRY_AFTER},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
 use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
 use reqwest::{header::HeaderValue, StatusCode};
-use secrecy::{ExposeSecret, Secret};
+use secrecy::Secret;
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
 
 use crate::{
-    database_helper::get_confirmed_subscribers,
-    email_client::EmailClient,
-    telemetry::{error_chain_fmt, spawn_blocking_with_tracing},
+    authentication::{validate_credentials, AuthError, Credentials},
+    client_address::client_address,
+    configuration::LoginProtectionSettings,
+    database_helper::{
+        get_newsletter_issue, publish_draft_issue, resolve_list_id, schedule_draft_issue,
+    },
+    domain::IssueSlug,
+    email_templates::EmailTemplates,
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    issue_delivery_worker::enqueue_issue_delivery,
+    session_state::TypedSession,
+    telemetry::error_chain_fmt,
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct BodyData {
-    title: String,
-    content: Content,
-}
-
-#[derive(serde::Deserialize)]
-pub struct Content {
-    html: String,
-    text: String,
+    newsletter_issue_id: Uuid,
+    /// Issues due in the future are kept until the scheduler dispatches them.
+    send_at: Option<DateTime<Utc>>,
+    /// Issues go to the default list unless another one is given.
+    list_id: Option<Uuid>,
 }
 
-pub struct Credentials {
-    username: String,
-    password: Secret<String>,
+#[derive(serde::Serialize)]
+struct PublishResponse {
+    newsletter_issue_id: Uuid,
+    status: &'static str,
 }
 
 #[derive(thiserror::Error)]
@@ -35,6 +48,16 @@ pub enum PublishError {
     UnexpectedError(#[from] anyhow::Error),
     #[error("Authentication Failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed authentication attempts")]
+    TooManyAttempts(Duration),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The newsletter issue does not exist")]
+    IssueNotFound,
+    #[error("The newsletter issue is {0}, not a draft")]
+    NotADraft(String),
+    #[error("The idempotency key was already used for another request")]
+    IdempotencyKeyReused,
 }
 
 impl std::fmt::Debug for PublishError {
@@ -43,12 +66,33 @@ impl std::fmt::Debug for PublishError {
     }
 }
 
+impl From<AuthError> for PublishError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::TooManyAttempts { retry_after } => {
+                PublishError::TooManyAttempts(retry_after)
+            }
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        }
+    }
+}
+
 impl ResponseError for PublishError {
     fn error_response(&self) -> HttpResponse {
         match self {
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
+            PublishError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            PublishError::IssueNotFound => HttpResponse::new(StatusCode::NOT_FOUND),
+            PublishError::NotADraft(_) => HttpResponse::new(StatusCode::CONFLICT),
+            PublishError::IdempotencyKeyReused => {
+                HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY)
+            }
+            PublishError::TooManyAttempts(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, retry_after.as_secs().to_string()))
+                .finish(),
             PublishError::AuthError(_) => {
                 let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
                 let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
@@ -94,109 +138,150 @@ fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Erro
     })
 }
 
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, PublishError> {
+    headers
+        .get("Idempotency-Key")
+        .map(|value| {
+            let value = value.to_str().map_err(|_| {
+                PublishError::ValidationError(
+                    "The 'Idempotency-Key' header was not a valid UTF8 string.".into(),
+                )
+            })?;
+            IdempotencyKey::try_from(value.to_owned()).map_err(PublishError::ValidationError)
+        })
+        .transpose()
+}
+
+/// Identifies the publisher: editors logged in through `/login` are recognised
+/// by their session, API clients have to send 'Basic' credentials on every call.
+async fn authenticate(
+    session: &TypedSession,
+    request: &HttpRequest,
+    login_protection: &LoginProtectionSettings,
+    db_connection_pool: &PgPool,
+) -> Result<Uuid, PublishError> {
+    if let Some(user_id) = session
+        .get_user_id()
+        .context("Failed to read the user id from the session")?
+    {
+        return Ok(user_id);
+    }
+
+    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let ip_address = client_address(request);
+    Ok(validate_credentials(
+        credentials,
+        ip_address.as_deref(),
+        login_protection,
+        db_connection_pool,
+    )
+    .await?)
+}
+
 #[tracing::instrument(
     name = "Publishing a newsletter",
-    skip(body, db_connection_pool, email_client, request),
+    skip(body, db_connection_pool, login_protection, request, session),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     db_connection_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    login_protection: web::Data<LoginProtectionSettings>,
     request: HttpRequest,
+    session: TypedSession,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    let user_id = authenticate(&session, &request, &login_protection, &db_connection_pool).await?;
 
-    let user_id = validate_credentials(credentials, &db_connection_pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
 
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    let idempotency_key = idempotency_key(request.headers())?;
 
-    let confirmed_subscribers = get_confirmed_subscribers(&db_connection_pool)
-        .await
-        .context("Failed to retrieve confirmed subscribers")?;
-
-    for subscriber in confirmed_subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.text,
-                        &body.content.html,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(error.cause_chain = ?error, "Skipping a confirmed subscriber. \
-                Their stored contact details are invalid")
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            let request_hash = Sha256::digest(
+                serde_json::to_vec(&body.0).context("Failed to serialize the request body")?,
+            );
+            match try_processing(&db_connection_pool, idempotency_key, user_id, &request_hash)
+                .await?
+            {
+                NextAction::StartProcessing(transaction) => transaction,
+                NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+                NextAction::RejectReusedKey => return Err(PublishError::IdempotencyKeyReused),
             }
         }
-    }
-
-    Ok(HttpResponse::Ok().finish())
-}
-
-#[tracing::instrument(name = "Validating credentials", skip(db_connection_pool, credentials))]
-async fn validate_credentials(
-    credentials: Credentials,
-    db_connection_pool: &PgPool,
-) -> Result<uuid::Uuid, PublishError> {
-    let (user_id, expected_password) =
-        get_stored_credentials(&credentials.username, db_connection_pool)
+        None => db_connection_pool
+            .begin()
             .await
-            .map_err(PublishError::UnexpectedError)?
-            .ok_or_else(|| PublishError::AuthError(anyhow!("Unknown username.")))?;
+            .context("Failed to get the connection pool while beginning the transaction")?,
+    };
 
-    spawn_blocking_with_tracing(move || {
-        validate_password_hash(expected_password, credentials.password)
-    })
-    .await
-    .context("Failed to spawn blocking task")
-    .map_err(PublishError::UnexpectedError)??;
+    let newsletter_issue_id = body.newsletter_issue_id;
+    let issue = get_newsletter_issue(&db_connection_pool, newsletter_issue_id)
+        .await
+        .context("Failed to retrieve the newsletter issue")?
+        .ok_or(PublishError::IssueNotFound)?;
 
-    Ok(user_id)
-}
+    let list_id = resolve_list_id(&db_connection_pool, body.list_id)
+        .await
+        .context("Failed to retrieve the mailing list")?
+        .ok_or_else(|| PublishError::ValidationError("The list does not exist".into()))?;
 
-#[tracing::instrument(
-    name = "Validating password hash",
-    skip(expected_password, password_candidate)
-)]
-fn validate_password_hash(
-    expected_password: Secret<String>,
-    password_candidate: Secret<String>,
-) -> Result<(), PublishError> {
-    let expected_password_hash = PasswordHash::new(&expected_password.expose_secret())
-        .context("Failed to parse hash in PHC string format")
-        .map_err(PublishError::AuthError)?;
-
-    Argon2::default()
-        .verify_password(
-            password_candidate.expose_secret().as_bytes(),
-            &expected_password_hash,
-        )
-        .context("Invalid password")
-        .map_err(PublishError::AuthError)
-}
+    // Stored templates may have changed since the draft was saved.
+    let templates = EmailTemplates::load(&db_connection_pool).await?;
+    templates
+        .validate_newsletter(&issue.title, &issue.html_content, &issue.text_content)
+        .map_err(|e| {
+            PublishError::ValidationError(format!(
+                "The newsletter content is not a valid template: {}",
+                e
+            ))
+        })?;
 
-#[tracing::instrument(name = "Get stored credentials", skip(db_connection_pool, username))]
-async fn get_stored_credentials(
-    username: &str,
-    db_connection_pool: &PgPool,
-) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
-    let row = sqlx::query!(
-        r#"SELECT user_id, password FROM users WHERE username = $1"#,
-        username
-    )
-    .fetch_optional(db_connection_pool)
-    .await
-    .context("Failed to retrieve stored credentials")?
-    .map(|row| (row.user_id, Secret::new(row.password)));
+    let status = match body.send_at {
+        Some(send_at) if send_at > Utc::now() => {
+            if !schedule_draft_issue(&mut transaction, newsletter_issue_id, send_at, list_id)
+                .await
+                .context("Failed to schedule the newsletter issue")?
+            {
+                return Err(PublishError::NotADraft(issue.status));
+            }
+            "scheduled"
+        }
+        _ => {
+            let slug = IssueSlug::new(&issue.title, newsletter_issue_id);
+            if !publish_draft_issue(&mut transaction, newsletter_issue_id, &slug, list_id)
+                .await
+                .context("Failed to publish the newsletter issue")?
+            {
+                return Err(PublishError::NotADraft(issue.status));
+            }
+            enqueue_issue_delivery(
+                &db_connection_pool,
+                &mut transaction,
+                newsletter_issue_id,
+                list_id,
+            )
+            .await?;
+            "published"
+        }
+    };
 
-    Ok(row)
+    let response = HttpResponse::Accepted().json(PublishResponse {
+        newsletter_issue_id,
+        status,
+    });
+    match idempotency_key {
+        Some(idempotency_key) => {
+            let response = save_response(transaction, &idempotency_key, user_id, response).await?;
+            Ok(response)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit the transaction")?;
+            Ok(response)
+        }
+    }
 }
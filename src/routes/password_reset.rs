@@ -0,0 +1,249 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgExecutor, PgPool};
+use std::fmt::Write;
+
+use crate::{
+    authentication::{
+        change_password, get_user_by_email, get_user_id_from_reset_token,
+        issue_password_reset_token,
+    },
+    domain::{NewPassword, SubscriberEmail},
+    email_outbox::enqueue_email,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct ForgotPasswordFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordFormData {
+    token: String,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+fn flash_messages_html(flash_messages: &IncomingFlashMessages) -> String {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    msg_html
+}
+
+pub async fn forgot_password_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let msg_html = flash_messages_html(&flash_messages);
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forgot password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/password/forgot" method="post">
+        <label>Email
+            <input
+                type="email"
+                placeholder="Enter the email of your account"
+                name="email"
+            >
+        </label>
+        <button type="submit">Send reset link</button>
+    </form>
+    <p><a href="/login">&lt;- Back to login</a></p>
+</body>
+</html>"#,
+        ))
+}
+
+#[tracing::instrument(
+    name = "Requesting a password reset",
+    skip(form_data, db_connection_pool, base_url)
+)]
+pub async fn forgot_password(
+    form_data: web::Form<ForgotPasswordFormData>,
+    db_connection_pool: web::Data<PgPool>,
+    base_url: web::Data<Url>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // The answer is the same whether the account exists or not, to avoid
+    // leaking which emails are registered. The email is only enqueued, so that
+    // neither the response time nor a provider outage tells the two apart.
+    FlashMessage::info(
+        "If an account with that email exists, you will receive a reset link shortly.",
+    )
+    .send();
+
+    let user = match get_user_by_email(&form_data.email, &db_connection_pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(user) => user,
+        None => return Ok(see_other("/password/forgot")),
+    };
+    let recipient = match SubscriberEmail::parse(user.email) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "The stored user email is invalid");
+            return Ok(see_other("/password/forgot"));
+        }
+    };
+
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")
+        .map_err(e500)?;
+    let token = issue_password_reset_token(&mut transaction, user.user_id)
+        .await
+        .map_err(e500)?;
+    enqueue_password_reset_email(&mut transaction, &recipient, &base_url, &token)
+        .await
+        .context("Failed to enqueue the password reset email")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transaction")
+        .map_err(e500)?;
+
+    Ok(see_other("/password/forgot"))
+}
+
+#[tracing::instrument(
+    name = "Enqueuing password reset email",
+    skip(executor, base_url, reset_token)
+)]
+async fn enqueue_password_reset_email(
+    executor: impl PgExecutor<'_>,
+    recipient: &SubscriberEmail,
+    base_url: &Url,
+    reset_token: &str,
+) -> Result<(), anyhow::Error> {
+    let mut reset_link = base_url.join("/password/reset").unwrap();
+    reset_link
+        .query_pairs_mut()
+        .append_pair("token", reset_token);
+
+    let plain_body = format!(
+        "Someone asked to reset the password of your account.\n\
+        Visit {} to choose a new one. If it wasn't you, you can ignore this email.",
+        reset_link
+    );
+    let html_body = format!(
+        "Someone asked to reset the password of your account.<br />\
+        Click <a href=\"{}\">here</a> to choose a new one. \
+        If it wasn't you, you can ignore this email.",
+        reset_link
+    );
+
+    enqueue_email(
+        executor,
+        recipient,
+        "Reset your password",
+        &plain_body,
+        &html_body,
+    )
+    .await
+}
+
+pub async fn reset_password_form(
+    parameters: web::Query<ResetPasswordParameters>,
+    flash_messages: IncomingFlashMessages,
+) -> HttpResponse {
+    let msg_html = flash_messages_html(&flash_messages);
+    let token = htmlescape::encode_attribute(&parameters.token);
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/password/reset" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <label>New password
+            <input
+                type="password"
+                placeholder="Enter new password"
+                name="new_password"
+            >
+        </label>
+        <br>
+        <label>Confirm new password
+            <input
+                type="password"
+                placeholder="Type the new password again"
+                name="new_password_check"
+            >
+        </label>
+        <br>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>"#,
+        ))
+}
+
+#[tracing::instrument(name = "Resetting a password", skip(form_data, db_connection_pool))]
+pub async fn reset_password(
+    form_data: web::Form<ResetPasswordFormData>,
+    db_connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let retry_location = format!(
+        "/password/reset?{}",
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("token", &form_data.token)
+            .finish()
+    );
+
+    let user_id = match get_user_id_from_reset_token(&form_data.token, &db_connection_pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error("The reset link is invalid or has expired.").send();
+            return Ok(see_other("/password/forgot"));
+        }
+    };
+
+    if form_data.new_password.expose_secret() != form_data.new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&retry_location));
+    }
+    let new_password = match NewPassword::parse(form_data.0.new_password) {
+        Ok(new_password) => new_password,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&retry_location));
+        }
+    };
+
+    change_password(user_id, new_password, &db_connection_pool)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("Your password has been reset, you can now log in.").send();
+    Ok(see_other("/login"))
+}
@@ -0,0 +1,430 @@
+use actix_web::{
+    http::header::{ContentType, RETRY_AFTER},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::NaiveDate;
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::fmt::Write;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::{
+    database_helper::{
+        get_subscriber_id_from_email, get_subscriber_preferences, record_subscription_event,
+        store_email_change_token, update_subscriber_preferences, SubscriberPreferences,
+    },
+    domain::{DigestFrequency, Subscriber, SubscriberEmail, SubscriberName},
+    email_templates::EmailTemplates,
+    rate_limiting::{retry_after_seconds, RateLimitDecision, RateLimiter},
+    routes::{enqueue_confirmation_email, generate_subscription_token},
+    signed_token::{TokenPurpose, TokenSigner},
+    subscription_events::{EventSource, SubscriptionEventKind},
+    telemetry::error_chain_fmt,
+    utils::see_other,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+/// The preferences of a subscriber, as sent to the JSON API.
+#[derive(Deserialize)]
+pub struct PreferencesUpdate {
+    name: String,
+    /// A new address only replaces the current one once it is confirmed.
+    email: String,
+    digest_frequency: String,
+    /// Deliveries resume the day after. `None` resumes them right away.
+    paused_until: Option<NaiveDate>,
+}
+
+#[derive(Deserialize)]
+pub struct PreferencesFormData {
+    name: String,
+    email: String,
+    digest_frequency: String,
+    /// Empty when deliveries are not paused.
+    paused_until: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("This preferences link is invalid or has expired.")]
+    InvalidToken,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("This email address is already used by another subscriber.")]
+    EmailTaken,
+    #[error(
+        "Too many requests, try again in {} seconds.",
+        retry_after_seconds(*retry_after)
+    )]
+    TooManyRequests { retry_after: Duration },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::InvalidToken | PreferencesError::ValidationError(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            PreferencesError::EmailTaken => StatusCode::CONFLICT,
+            PreferencesError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let PreferencesError::TooManyRequests { retry_after } = self {
+            response.insert_header((RETRY_AFTER, retry_after_seconds(*retry_after).to_string()));
+        }
+        response
+            .content_type(ContentType::plaintext())
+            .body(self.to_string())
+    }
+}
+
+impl TryFrom<PreferencesFormData> for PreferencesUpdate {
+    type Error = String;
+
+    fn try_from(form: PreferencesFormData) -> Result<Self, Self::Error> {
+        let paused_until = match form.paused_until.trim() {
+            "" => None,
+            date => Some(
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| format!("{} is not a valid date.", date))?,
+            ),
+        };
+        Ok(Self {
+            name: form.name,
+            email: form.email,
+            digest_frequency: form.digest_frequency,
+            paused_until,
+        })
+    }
+}
+
+fn subscriber_from_token(
+    token_signer: &TokenSigner,
+    token: &str,
+) -> Result<Uuid, PreferencesError> {
+    token_signer
+        .verify_unexpired(TokenPurpose::Preferences, token)
+        .and_then(|payload| Uuid::parse_str(&payload).ok())
+        .ok_or(PreferencesError::InvalidToken)
+}
+
+/// Links keep working after the subscriber is erased: they just lead nowhere.
+async fn load_preferences(
+    db_connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<SubscriberPreferences, PreferencesError> {
+    get_subscriber_preferences(db_connection_pool, subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber preferences")?
+        .ok_or(PreferencesError::InvalidToken)
+}
+
+/// Saves the new preferences. A new email address is sent a confirmation
+/// link first, within the rate limits: returns `true` if that happened.
+async fn apply_preferences(
+    db_connection_pool: &PgPool,
+    base_url: &Url,
+    rate_limiter: &RateLimiter,
+    subscriber_id: Uuid,
+    update: PreferencesUpdate,
+    source: &EventSource,
+) -> Result<bool, PreferencesError> {
+    let name = SubscriberName::parse(update.name).map_err(PreferencesError::ValidationError)?;
+    let digest_frequency = DigestFrequency::parse(&update.digest_frequency)
+        .map_err(PreferencesError::ValidationError)?;
+    let current = load_preferences(db_connection_pool, subscriber_id).await?;
+    let new_email = if update.email.trim() == current.email {
+        None
+    } else {
+        let email =
+            SubscriberEmail::parse(update.email).map_err(PreferencesError::ValidationError)?;
+        let owner = get_subscriber_id_from_email(db_connection_pool, email.as_ref())
+            .await
+            .context("Failed to get the subscriber from the new email")?;
+        if owner.is_some() {
+            return Err(PreferencesError::EmailTaken);
+        }
+        let decision = rate_limiter
+            .check(source.ip_address.as_deref(), Some(email.as_ref()))
+            .await
+            .context("Failed to check the rate limits")?;
+        if let RateLimitDecision::Limited { retry_after } = decision {
+            return Err(PreferencesError::TooManyRequests { retry_after });
+        }
+        Some(email)
+    };
+
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")?;
+    update_subscriber_preferences(
+        &mut transaction,
+        subscriber_id,
+        &name,
+        digest_frequency,
+        update.paused_until,
+    )
+    .await
+    .context("Failed to update the subscriber preferences")?;
+    let confirmation_sent = match new_email {
+        // The confirmation goes through the outbox: it is sent if and only if
+        // the new preferences are saved.
+        Some(email) => {
+            let token = generate_subscription_token();
+            store_email_change_token(&mut transaction, subscriber_id, &email, &token)
+                .await
+                .context("Failed to store the email change token")?;
+            let detail = format!("To the new email {}", email.as_ref());
+            let templates = EmailTemplates::load(db_connection_pool).await?;
+            enqueue_confirmation_email(
+                &mut transaction,
+                &templates,
+                &Subscriber { name, email },
+                base_url,
+                &token,
+            )
+            .await
+            .context("Failed to enqueue the confirmation email to the new address")?;
+            record_subscription_event(
+                &mut transaction,
+                subscriber_id,
+                SubscriptionEventKind::ConfirmationSent,
+                source,
+                Some(&detail),
+            )
+            .await
+            .context("Failed to record the confirmation email")?;
+            true
+        }
+        None => false,
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transaction")?;
+    Ok(confirmation_sent)
+}
+
+#[tracing::instrument(
+    name = "Retrieving subscriber preferences",
+    skip(parameters, db_connection_pool, token_signer)
+)]
+pub async fn get_preferences(
+    parameters: web::Query<PreferencesParameters>,
+    db_connection_pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = subscriber_from_token(&token_signer, &parameters.token)?;
+    let preferences = load_preferences(&db_connection_pool, subscriber_id).await?;
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+#[tracing::instrument(
+    name = "Updating subscriber preferences",
+    skip(
+        request,
+        parameters,
+        body,
+        db_connection_pool,
+        base_url,
+        rate_limiter,
+        token_signer
+    )
+)]
+pub async fn put_preferences(
+    request: HttpRequest,
+    parameters: web::Query<PreferencesParameters>,
+    body: web::Json<PreferencesUpdate>,
+    db_connection_pool: web::Data<PgPool>,
+    base_url: web::Data<Url>,
+    rate_limiter: web::Data<RateLimiter>,
+    token_signer: web::Data<TokenSigner>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = subscriber_from_token(&token_signer, &parameters.token)?;
+    apply_preferences(
+        &db_connection_pool,
+        &base_url,
+        &rate_limiter,
+        subscriber_id,
+        body.0,
+        &EventSource::from_request(&request),
+    )
+    .await?;
+    let preferences = load_preferences(&db_connection_pool, subscriber_id).await?;
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+fn preferences_path(token: &str) -> String {
+    format!(
+        "/preferences?{}",
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("token", token)
+            .finish()
+    )
+}
+
+#[tracing::instrument(
+    name = "Showing preferences page",
+    skip(parameters, db_connection_pool, token_signer, flash_messages)
+)]
+pub async fn preferences_form(
+    parameters: web::Query<PreferencesParameters>,
+    db_connection_pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = subscriber_from_token(&token_signer, &parameters.token)?;
+    let preferences = load_preferences(&db_connection_pool, subscriber_id).await?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    if let Some(pending_email) = &preferences.pending_email {
+        writeln!(
+            msg_html,
+            "<p>We sent a confirmation link to {}: your email changes once you click it.</p>",
+            htmlescape::encode_minimal(pending_email)
+        )
+        .unwrap();
+    }
+    let mut frequency_options = String::new();
+    for (frequency, label) in [
+        (DigestFrequency::Immediate, "as soon as they are published"),
+        (DigestFrequency::Daily, "in a daily digest"),
+        (DigestFrequency::Weekly, "in a weekly digest"),
+    ] {
+        let value = frequency.as_str();
+        let selected = if value == preferences.digest_frequency {
+            " selected"
+        } else {
+            ""
+        };
+        writeln!(
+            frequency_options,
+            r#"<option value="{value}"{selected}>{label}</option>"#
+        )
+        .unwrap();
+    }
+    let paused_until = preferences
+        .paused_until
+        .map(|date| date.to_string())
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    {msg_html}
+    <form action="{action}" method="post">
+        <label>Name
+            <input type="text" name="name" value="{name}">
+        </label>
+        <br>
+        <label>Email
+            <input type="email" name="email" value="{email}">
+        </label>
+        <br>
+        <label>Send me issues
+            <select name="digest_frequency">
+                {frequency_options}
+            </select>
+        </label>
+        <br>
+        <label>Pause deliveries until
+            <input type="date" name="paused_until" value="{paused_until}">
+        </label>
+        <br>
+        <button type="submit">Save preferences</button>
+    </form>
+</body>
+</html>"#,
+            action = htmlescape::encode_attribute(&preferences_path(&parameters.token)),
+            name = htmlescape::encode_minimal(&preferences.name),
+            email = htmlescape::encode_minimal(&preferences.email),
+        )))
+}
+
+#[tracing::instrument(
+    name = "Saving subscriber preferences",
+    skip(
+        request,
+        parameters,
+        form_data,
+        db_connection_pool,
+        base_url,
+        rate_limiter,
+        token_signer
+    )
+)]
+pub async fn save_preferences(
+    request: HttpRequest,
+    parameters: web::Query<PreferencesParameters>,
+    form_data: web::Form<PreferencesFormData>,
+    db_connection_pool: web::Data<PgPool>,
+    base_url: web::Data<Url>,
+    rate_limiter: web::Data<RateLimiter>,
+    token_signer: web::Data<TokenSigner>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = subscriber_from_token(&token_signer, &parameters.token)?;
+    let location = preferences_path(&parameters.token);
+
+    let update = match form_data.0.try_into() {
+        Ok(update) => update,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&location));
+        }
+    };
+    match apply_preferences(
+        &db_connection_pool,
+        &base_url,
+        &rate_limiter,
+        subscriber_id,
+        update,
+        &EventSource::from_request(&request),
+    )
+    .await
+    {
+        Ok(false) => FlashMessage::info("Your preferences have been saved.").send(),
+        Ok(true) => FlashMessage::info(
+            "Your preferences have been saved. \
+            Check your new inbox to confirm your email address.",
+        )
+        .send(),
+        Err(
+            e @ (PreferencesError::ValidationError(_)
+            | PreferencesError::EmailTaken
+            | PreferencesError::TooManyRequests { .. }),
+        ) => FlashMessage::error(e.to_string()).send(),
+        Err(e) => return Err(e),
+    }
+    Ok(see_other(&location))
+}
@@ -1,24 +1,30 @@
 use crate::{
     database_helper::{
-        get_subscriber_id_from_email, get_subscription_token_from_id, insert_subscriber,
-        store_token,
+        delete_subscription_tokens, get_subscriber_id_from_email, insert_subscriber,
+        is_undeliverable, join_list, record_subscription_event, resolve_list_id, store_token,
     },
     domain::{Subscriber, SubscriberEmail, SubscriberName},
     email_client::EmailClient,
+    email_outbox::enqueue_email,
+    email_templates::{confirmation_context, EmailTemplates, RenderedEmail, CONFIRMATION_EMAIL},
+    subscription_events::{EventSource, SubscriptionEventKind},
     telemetry::error_chain_fmt,
 };
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 
 use anyhow::Context;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use reqwest::{StatusCode, Url};
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    /// Subscribers join the default list unless they pick another one.
+    list_id: Option<Uuid>,
 }
 
 #[derive(thiserror::Error)]
@@ -46,81 +52,161 @@ impl ResponseError for SubscribeError {
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form_data, db_connection_pool, email_client, base_url),
+    skip(request, form_data, db_connection_pool, email_client, base_url),
     fields(
         subscriber_email = %form_data.email,
         subscriber_name = %form_data.name
     )
 )]
 pub async fn subscribe(
+    request: HttpRequest,
     form_data: web::Form<FormData>,
     db_connection_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<Url>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let list_id = resolve_list_id(&db_connection_pool, form_data.list_id)
+        .await
+        .context("Failed to retrieve the mailing list")?
+        .ok_or_else(|| SubscribeError::ValidationError("The list does not exist".into()))?;
     let subscriber: Subscriber = match form_data.0.try_into() {
         Ok(subscriber) => subscriber,
         Err(_) => return Ok(HttpResponse::BadRequest().finish()),
     };
-
-    let confirmation_email_error_message = "Failed to send confirmation email";
-
-    if let Some(subscriber_id) =
-        get_subscriber_id_from_email(&db_connection_pool, &subscriber.email.as_ref())
-            .await
-            .context("Failed to get the subscriber from input email")?
+    // The address bounced or its owner reported us as spam: emailing it again
+    // hurts our sender reputation. The response does not tell them apart.
+    if is_undeliverable(&db_connection_pool, subscriber.email.as_ref())
+        .await
+        .context("Failed to check if the email is deliverable")?
     {
-        let subscription_token =
-            match get_subscription_token_from_id(&db_connection_pool, subscriber_id)
-                .await
-                .context("Failed to get the subcription token from subscriber id")?
-            {
-                Some(token) => token,
-                None => return Ok(HttpResponse::InternalServerError().finish()),
-            };
-
-        send_confirmation_email(&email_client, subscriber, &base_url, &subscription_token)
-            .await
-            .context(confirmation_email_error_message)?;
-
+        tracing::info!("Skipping the confirmation email of an undeliverable address");
         return Ok(HttpResponse::Ok().finish());
-    };
+    }
 
     let mut transaction = db_connection_pool
         .begin()
         .await
         .context("Failed to get the connection pool while beginning the transaction")?;
 
-    let subscriber_id = insert_subscriber(&subscriber, &mut transaction)
+    let subscriber_id =
+        match get_subscriber_id_from_email(&db_connection_pool, subscriber.email.as_ref())
+            .await
+            .context("Failed to get the subscriber from input email")?
+        {
+            // Subscribing again rotates the token: older confirmation links for the
+            // list stop working and the new one gets a fresh time-to-live.
+            Some(subscriber_id) => {
+                delete_subscription_tokens(&mut transaction, subscriber_id, list_id)
+                    .await
+                    .context("Failed to delete the previous subscription tokens")?;
+                subscriber_id
+            }
+            None => insert_subscriber(&subscriber, &mut transaction)
+                .await
+                .context("Failed to insert the subcriber into the database")?,
+        };
+
+    join_list(&mut transaction, subscriber_id, list_id)
         .await
-        .context("Failed to insert the subcriber into the database")?;
+        .context("Failed to add the subscriber to the list")?;
+    let source = EventSource::from_request(&request);
+    let detail = format!("List {}", list_id);
+    record_subscription_event(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionEventKind::Subscribed,
+        &source,
+        Some(&detail),
+    )
+    .await
+    .context("Failed to record the subscription")?;
+
     let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
-        .await
-        .context("Failed to store the subscription token")?;
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list_id,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to store the subscription token")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit the transaction")?;
 
-    send_confirmation_email(&email_client, subscriber, &base_url, &subscription_token)
-        .await
-        .context(confirmation_email_error_message)?;
+    let templates = EmailTemplates::load(&db_connection_pool).await?;
+    send_confirmation_email(
+        &email_client,
+        &templates,
+        subscriber,
+        &base_url,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send confirmation email")?;
+    record_subscription_event(
+        db_connection_pool.as_ref(),
+        subscriber_id,
+        SubscriptionEventKind::ConfirmationSent,
+        &source,
+        Some(&detail),
+    )
+    .await
+    .context("Failed to record the confirmation email")?;
 
     Ok(HttpResponse::Ok().finish())
 }
 
 #[tracing::instrument(
     name = "Sending confirmation email",
-    skip(email_client, subscriber, base_url)
+    skip(email_client, templates, subscriber, base_url)
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
+    templates: &EmailTemplates,
     subscriber: Subscriber,
     base_url: &Url,
     confirmation_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
+    let email = render_confirmation_email(templates, &subscriber, base_url, confirmation_token)?;
+    email_client
+        .send_email(&subscriber.email, "Welcome", &email.text, &email.html)
+        .await?;
+    Ok(())
+}
+
+/// Queues the confirmation email in the outbox, to go out once the
+/// transaction of `executor` is committed.
+#[tracing::instrument(
+    name = "Enqueuing confirmation email",
+    skip(executor, templates, subscriber, base_url)
+)]
+pub async fn enqueue_confirmation_email(
+    executor: impl PgExecutor<'_>,
+    templates: &EmailTemplates,
+    subscriber: &Subscriber,
+    base_url: &Url,
+    confirmation_token: &str,
+) -> Result<(), anyhow::Error> {
+    let email = render_confirmation_email(templates, subscriber, base_url, confirmation_token)?;
+    enqueue_email(
+        executor,
+        &subscriber.email,
+        "Welcome",
+        &email.text,
+        &email.html,
+    )
+    .await
+}
+
+fn render_confirmation_email(
+    templates: &EmailTemplates,
+    subscriber: &Subscriber,
+    base_url: &Url,
+    confirmation_token: &str,
+) -> Result<RenderedEmail, anyhow::Error> {
     let confirmation_link = base_url
         .join(
             format!(
@@ -131,20 +217,12 @@ pub async fn send_confirmation_email(
         )
         .unwrap();
 
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm subscription",
-        confirmation_link
-    );
-
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-    Click <a href=\"{}\"> here </a> to confirm your subscription.",
-        confirmation_link
-    );
-
-    email_client
-        .send_email(&subscriber.email, "Welcome", &plain_body, &html_body)
-        .await
+    templates
+        .render_email(
+            CONFIRMATION_EMAIL,
+            &confirmation_context(subscriber.name.as_ref(), &confirmation_link),
+        )
+        .context("Failed to render the confirmation email")
 }
 
 impl TryFrom<FormData> for Subscriber {
@@ -158,7 +236,7 @@ impl TryFrom<FormData> for Subscriber {
     }
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -1,7 +1,16 @@
-use crate::database_helper::{confirm_subscriber, get_subscriber_id_from_token};
-use actix_web::{web, HttpResponse};
+use crate::{
+    configuration::SubscriptionSettings,
+    database_helper::{
+        change_subscriber_email, confirm_subscriber, get_subscriber_id_from_token,
+        record_subscription_event, StoredSubscriptionToken,
+    },
+    subscription_events::{EventSource, SubscriptionEventKind},
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 use validator::Validate;
 
 #[derive(Deserialize, Debug, Validate)]
@@ -10,28 +19,92 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirming a pending subscription", skip(parameters))]
+#[tracing::instrument(
+    name = "Confirming a pending subscription",
+    skip(request, parameters, db_connection_pool, subscription_settings)
+)]
 pub async fn confirm(
+    request: HttpRequest,
     parameters: web::Query<Parameters>,
     db_connection_pool: web::Data<PgPool>,
+    subscription_settings: web::Data<SubscriptionSettings>,
 ) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&db_connection_pool, &parameters.subscription_token)
-        .await
-    {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
+    let token =
+        match get_subscriber_id_from_token(&db_connection_pool, &parameters.subscription_token)
+            .await
+        {
+            Ok(id) => id,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
 
-    match id {
+    match token {
         None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&db_connection_pool, subscriber_id)
-                .await
-                .is_err()
-            {
-                return HttpResponse::InternalServerError().finish();
+        Some(token)
+            if token.issued_at + subscription_settings.confirmation_token_ttl() < Utc::now() =>
+        {
+            HttpResponse::Gone().body(
+                "This confirmation link has expired. \
+                Please subscribe again to receive a new one.",
+            )
+        }
+        Some(StoredSubscriptionToken {
+            subscriber_id,
+            new_email: Some(new_email),
+            ..
+        }) => {
+            let mut transaction = match db_connection_pool.begin().await {
+                Ok(transaction) => transaction,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+            match change_subscriber_email(&mut transaction, subscriber_id, &new_email).await {
+                Ok(true) => {
+                    record_confirmation(transaction, &request, subscriber_id, "New email").await
+                }
+                Ok(false) => HttpResponse::Conflict()
+                    .body("This email address is already used by another subscriber."),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            }
+        }
+        Some(token) => {
+            let mut transaction = match db_connection_pool.begin().await {
+                Ok(transaction) => transaction,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+            match confirm_subscriber(&mut transaction, token.subscriber_id, token.list_id).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return HttpResponse::Conflict()
+                        .body("This email address no longer accepts our emails.")
+                }
+                Err(_) => return HttpResponse::InternalServerError().finish(),
             }
-            HttpResponse::Ok().finish()
+            let detail = match token.list_id {
+                Some(list_id) => format!("List {}", list_id),
+                None => "All pending lists".into(),
+            };
+            record_confirmation(transaction, &request, token.subscriber_id, &detail).await
         }
     }
 }
+
+/// Records the confirmation in the audit trail, committing it together with
+/// the change it confirms.
+async fn record_confirmation(
+    mut transaction: Transaction<'_, Postgres>,
+    request: &HttpRequest,
+    subscriber_id: Uuid,
+    detail: &str,
+) -> HttpResponse {
+    let recorded = record_subscription_event(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionEventKind::Confirmed,
+        &EventSource::from_request(request),
+        Some(detail),
+    )
+    .await;
+    match recorded {
+        Ok(()) if transaction.commit().await.is_ok() => HttpResponse::Ok().finish(),
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -0,0 +1,131 @@
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    database_helper::{leave_list, record_subscription_event, unsubscribe_subscriber},
+    signed_token::{TokenPurpose, TokenSigner},
+    subscription_events::{EventSource, SubscriptionEventKind},
+};
+
+#[derive(Deserialize, Debug)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+/// Returns the subscriber and the list they want to leave. Links sent before
+/// lists existed carry no list: they unsubscribe from everything.
+fn subscription_from_token(
+    token_signer: &TokenSigner,
+    token: &str,
+) -> Option<(Uuid, Option<Uuid>)> {
+    let payload = token_signer.verify(TokenPurpose::Unsubscribe, token)?;
+    match payload.split_once(' ') {
+        Some((subscriber_id, list_id)) => Some((
+            Uuid::parse_str(subscriber_id).ok()?,
+            Some(Uuid::parse_str(list_id).ok()?),
+        )),
+        None => Some((Uuid::parse_str(&payload).ok()?, None)),
+    }
+}
+
+/// Asks for an explicit confirmation: link scanners and prefetchers issue GET
+/// requests, so a GET must never unsubscribe anyone on its own.
+#[tracing::instrument(name = "Showing unsubscribe page", skip(parameters, token_signer))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    token_signer: web::Data<TokenSigner>,
+) -> HttpResponse {
+    if subscription_from_token(&token_signer, &parameters.token).is_none() {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    let action = format!(
+        "/subscriptions/unsubscribe?{}",
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("token", &parameters.token)
+            .finish()
+    );
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="{}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(&action)
+        ))
+}
+
+/// Handles both the confirmation form and RFC 8058 one-click requests
+/// (`List-Unsubscribe=One-Click` in the body), which carry the token in the URL.
+#[tracing::instrument(
+    name = "Unsubscribing a subscriber",
+    skip(request, parameters, db_connection_pool, token_signer)
+)]
+pub async fn unsubscribe(
+    request: HttpRequest,
+    parameters: web::Query<UnsubscribeParameters>,
+    db_connection_pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+) -> HttpResponse {
+    let (subscriber_id, list_id) = match subscription_from_token(&token_signer, &parameters.token) {
+        Some(subscription) => subscription,
+        None => return HttpResponse::BadRequest().finish(),
+    };
+
+    let mut transaction = match db_connection_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let (outcome, detail) = match list_id {
+        Some(list_id) => (
+            leave_list(&mut transaction, subscriber_id, list_id).await,
+            format!("List {}", list_id),
+        ),
+        None => (
+            unsubscribe_subscriber(&mut transaction, subscriber_id).await,
+            "All lists".into(),
+        ),
+    };
+    match outcome {
+        Ok(true) => {
+            let recorded = record_subscription_event(
+                &mut transaction,
+                subscriber_id,
+                SubscriptionEventKind::Unsubscribed,
+                &EventSource::from_request(&request),
+                Some(&detail),
+            )
+            .await;
+            if recorded.is_err() || transaction.commit().await.is_err() {
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+        Ok(false) => {}
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed. You will not receive any further issues from this list.</p>
+</body>
+</html>"#,
+    )
+}
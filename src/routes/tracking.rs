@@ -0,0 +1,115 @@
+use actix_web::{
+    http::header::{CacheControl, CacheDirective, ContentType},
+    web, HttpResponse,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    database_helper::record_issue_event,
+    signed_token::TokenSigner,
+    tracking::{verify_click_token, verify_open_token, EventKind},
+    utils::see_other,
+};
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize)]
+pub struct TrackingParameters {
+    token: String,
+}
+
+/// Serves the open-tracking pixel of a tracked issue.
+#[tracing::instrument(
+    name = "Tracking an issue open",
+    skip(parameters, db_connection_pool, token_signer)
+)]
+pub async fn track_open(
+    parameters: web::Query<TrackingParameters>,
+    db_connection_pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+) -> HttpResponse {
+    let Some((newsletter_issue_id, subscriber_id)) =
+        verify_open_token(&token_signer, &parameters.token)
+    else {
+        return HttpResponse::BadRequest().finish();
+    };
+    record_event(
+        &db_connection_pool,
+        newsletter_issue_id,
+        subscriber_id,
+        EventKind::Open,
+        None,
+    )
+    .await;
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        // Every open should reach us, not a cached copy of the pixel.
+        .insert_header(CacheControl(vec![
+            CacheDirective::NoCache,
+            CacheDirective::NoStore,
+        ]))
+        .body(PIXEL)
+}
+
+/// Records a click on a link of a tracked issue and redirects to it.
+#[tracing::instrument(
+    name = "Tracking an issue click",
+    skip(parameters, db_connection_pool, token_signer)
+)]
+pub async fn track_click(
+    parameters: web::Query<TrackingParameters>,
+    db_connection_pool: web::Data<PgPool>,
+    token_signer: web::Data<TokenSigner>,
+) -> HttpResponse {
+    let Some((newsletter_issue_id, subscriber_id, destination)) =
+        verify_click_token(&token_signer, &parameters.token)
+    else {
+        return HttpResponse::BadRequest()
+            .content_type(ContentType::plaintext())
+            .body("This link is invalid");
+    };
+    record_event(
+        &db_connection_pool,
+        newsletter_issue_id,
+        subscriber_id,
+        EventKind::Click,
+        Some(destination.as_str()),
+    )
+    .await;
+
+    see_other(destination.as_str())
+}
+
+/// Tracking is best-effort: readers get their pixel or their page even when
+/// the event can't be stored.
+async fn record_event(
+    db_connection_pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    kind: EventKind,
+    url: Option<&str>,
+) {
+    if let Err(error) = record_issue_event(
+        db_connection_pool,
+        newsletter_issue_id,
+        subscriber_id,
+        kind.as_str(),
+        url,
+    )
+    .await
+    {
+        tracing::error!(
+            error.cause_chain = ?error,
+            error.message = %error,
+            "Failed to record an issue {} event",
+            kind.as_str()
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use reqwest::StatusCode;
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::{
+    configuration::WebhookSettings,
+    database_helper::{flag_undeliverable_subscriber, record_subscription_event},
+    subscription_events::{EventSource, SubscriptionEventKind},
+    telemetry::error_chain_fmt,
+};
+
+/// Bounce types telling us the address will never accept our emails.
+const PERMANENT_BOUNCE_TYPES: [&str; 2] = ["HardBounce", "BadEmailAddress"];
+
+/// The subset of Postmark's webhook payloads we act upon.
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "RecordType", rename_all_fields = "PascalCase")]
+pub enum PostmarkEvent {
+    Bounce {
+        #[serde(rename = "Type")]
+        bounce_type: String,
+        email: String,
+        #[serde(rename = "MessageID")]
+        message_id: Option<String>,
+    },
+    SpamComplaint {
+        email: String,
+        #[serde(rename = "MessageID")]
+        message_id: Option<String>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("The webhook secret is missing or invalid")]
+    AuthError,
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::AuthError => StatusCode::UNAUTHORIZED,
+            WebhookError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Receives Postmark's Bounce and SpamComplaint webhooks: hard-bounced and
+/// complaining addresses stop receiving issues. Other events are acknowledged
+/// and ignored.
+#[tracing::instrument(
+    name = "Handling a Postmark webhook",
+    skip(request, body, db_connection_pool, settings)
+)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    db_connection_pool: web::Data<PgPool>,
+    settings: web::Data<WebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    let secret = request
+        .headers()
+        .get("X-Webhook-Secret")
+        .ok_or(WebhookError::AuthError)?;
+    // Comparing digests keeps the comparison time independent of the secret.
+    if Sha256::digest(secret.as_bytes())
+        != Sha256::digest(settings.postmark_secret.expose_secret().as_bytes())
+    {
+        return Err(WebhookError::AuthError);
+    }
+
+    let event: PostmarkEvent = serde_json::from_slice(&body)
+        .map_err(|e| WebhookError::ValidationError(format!("Invalid Postmark payload: {}", e)))?;
+
+    let (email, message_id, kind, detail) = match &event {
+        PostmarkEvent::Bounce {
+            bounce_type,
+            email,
+            message_id,
+        } if PERMANENT_BOUNCE_TYPES.contains(&bounce_type.as_str()) => (
+            email,
+            message_id,
+            SubscriptionEventKind::Bounced,
+            bounce_type.as_str(),
+        ),
+        PostmarkEvent::SpamComplaint { email, message_id } => (
+            email,
+            message_id,
+            SubscriptionEventKind::Complained,
+            "SpamComplaint",
+        ),
+        PostmarkEvent::Bounce { .. } | PostmarkEvent::Other => {
+            return Ok(HttpResponse::Ok().finish())
+        }
+    };
+
+    let mut transaction = db_connection_pool
+        .begin()
+        .await
+        .context("Failed to begin the transaction")?;
+    match flag_undeliverable_subscriber(
+        &mut transaction,
+        email,
+        message_id.as_deref(),
+        kind.as_str(),
+        kind == SubscriptionEventKind::Bounced,
+    )
+    .await
+    .context("Failed to flag an undeliverable subscriber")?
+    {
+        Some(subscriber_id) => record_subscription_event(
+            &mut transaction,
+            subscriber_id,
+            kind,
+            &EventSource::email_provider(),
+            Some(detail),
+        )
+        .await
+        .context("Failed to record the event")?,
+        None => tracing::info!("The webhook refers to an unknown subscriber"),
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transaction")?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::session_state::TypedSession;
+
+type SessionState = HashMap<String, String>;
+
+/// Server-side session storage backed by the `sessions` table.
+///
+/// Only the session key travels in the (signed) cookie, the state itself never
+/// leaves Postgres.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    db_connection_pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(db_connection_pool: PgPool) -> Self {
+        Self { db_connection_pool }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"SELECT state FROM sessions WHERE session_key = $1 AND expires_at > now()"#,
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.db_connection_pool)
+        .await
+        .context("Failed to load the session state")
+        .map_err(LoadError::Other)?;
+
+        row.map(|row| serde_json::from_value(row.state))
+            .transpose()
+            .context("Failed to deserialize the session state")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_value(session_state)
+            .context("Failed to serialize the session state")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+
+        sqlx::query!(
+            r#"INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, now() + make_interval(secs => $3))"#,
+            session_key.as_ref(),
+            state,
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.db_connection_pool)
+        .await
+        .context("Failed to save the session state")
+        .map_err(SaveError::Other)?;
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_value(&session_state)
+            .context("Failed to serialize the session state")
+            .map_err(UpdateError::Serialization)?;
+
+        let n_updated_rows = sqlx::query!(
+            r#"UPDATE sessions
+            SET state = $2, expires_at = now() + make_interval(secs => $3)
+            WHERE session_key = $1"#,
+            session_key.as_ref(),
+            state,
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.db_connection_pool)
+        .await
+        .context("Failed to update the session state")
+        .map_err(UpdateError::Other)?
+        .rows_affected();
+
+        if n_updated_rows == 0 {
+            // The session expired and was purged in the meantime: start a new one.
+            self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            })
+        } else {
+            Ok(session_key)
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"UPDATE sessions SET expires_at = now() + make_interval(secs => $2) WHERE session_key = $1"#,
+            session_key.as_ref(),
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.db_connection_pool)
+        .await
+        .context("Failed to update the session expiration")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM sessions WHERE session_key = $1 OR expires_at <= now()"#,
+            session_key.as_ref()
+        )
+        .execute(&self.db_connection_pool)
+        .await
+        .context("Failed to delete the session state")?;
+        Ok(())
+    }
+}
+
+/// Logs the user out everywhere.
+#[tracing::instrument(name = "Delete user sessions", skip(executor))]
+pub async fn delete_user_sessions(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    // Session values are stored serialized to JSON.
+    let user_id = serde_json::to_string(&user_id).context("Failed to serialize the user id")?;
+    sqlx::query!(
+        r#"DELETE FROM sessions WHERE state ->> $1 = $2"#,
+        TypedSession::USER_ID_KEY,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to delete the sessions of the user")?;
+    Ok(())
+}
+
+fn generate_session_key() -> SessionKey {
+    let mut rng = thread_rng();
+    let value: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(64)
+        .collect();
+
+    value
+        .try_into()
+        .expect("A 64 characters long alphanumeric string is a valid session key")
+}
@@ -0,0 +1,155 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+/// What a signed token grants access to.
+///
+/// The purpose is part of the signed message, so a token issued for one
+/// action can't be replayed against another.
+#[derive(Clone, Copy, Debug)]
+pub enum TokenPurpose {
+    Unsubscribe,
+    TrackOpen,
+    TrackClick,
+    Preferences,
+}
+
+impl TokenPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::Unsubscribe => "unsubscribe",
+            TokenPurpose::TrackOpen => "track-open",
+            TokenPurpose::TrackClick => "track-click",
+            TokenPurpose::Preferences => "preferences",
+        }
+    }
+}
+
+/// Issues and verifies unguessable, stateless tokens for links we send by email.
+///
+/// A token is `base64(payload).base64(hmac_sha256(purpose, payload))`.
+#[derive(Clone)]
+pub struct TokenSigner {
+    secret: Secret<String>,
+}
+
+impl TokenSigner {
+    pub fn new(secret: Secret<String>) -> Self {
+        Self { secret }
+    }
+
+    pub fn sign(&self, purpose: TokenPurpose, payload: &str) -> String {
+        let signature = self
+            .mac(purpose, payload.as_bytes())
+            .finalize()
+            .into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    /// Returns the signed payload if the token is authentic.
+    pub fn verify(&self, purpose: TokenPurpose, token: &str) -> Option<String> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+        self.mac(purpose, &payload).verify_slice(&signature).ok()?;
+        String::from_utf8(payload).ok()
+    }
+
+    /// Signs a payload that stops being verified after `expires_at`.
+    pub fn sign_until(
+        &self,
+        purpose: TokenPurpose,
+        payload: &str,
+        expires_at: DateTime<Utc>,
+    ) -> String {
+        self.sign(purpose, &format!("{} {}", expires_at.timestamp(), payload))
+    }
+
+    /// Returns the payload signed with [`TokenSigner::sign_until`] if the token
+    /// is authentic and has not expired yet.
+    pub fn verify_unexpired(&self, purpose: TokenPurpose, token: &str) -> Option<String> {
+        let signed = self.verify(purpose, token)?;
+        let (expires_at, payload) = signed.split_once(' ')?;
+        let expires_at: i64 = expires_at.parse().ok()?;
+        (Utc::now().timestamp() < expires_at).then(|| payload.to_owned())
+    }
+
+    fn mac(&self, purpose: TokenPurpose, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(purpose.as_str().as_bytes());
+        mac.update(b"\0");
+        mac.update(payload);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TokenPurpose, TokenSigner};
+    use chrono::{Duration, Utc};
+    use claim::{assert_none, assert_some_eq};
+    use secrecy::Secret;
+
+    fn signer() -> TokenSigner {
+        TokenSigner::new(Secret::new("a-secret".into()))
+    }
+
+    #[test]
+    fn a_signed_payload_is_verified() {
+        let token = signer().sign(TokenPurpose::Unsubscribe, "payload");
+        assert_some_eq!(
+            signer().verify(TokenPurpose::Unsubscribe, &token),
+            "payload"
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let other_signer = TokenSigner::new(Secret::new("another-secret".into()));
+        let token = other_signer.sign(TokenPurpose::Unsubscribe, "payload");
+        assert_none!(signer().verify(TokenPurpose::Unsubscribe, &token));
+    }
+
+    #[test]
+    fn a_tampered_payload_is_rejected() {
+        let token = signer().sign(TokenPurpose::Unsubscribe, "payload");
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", "b3RoZXI", signature);
+        assert_none!(signer().verify(TokenPurpose::Unsubscribe, &forged));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        assert_none!(signer().verify(TokenPurpose::Unsubscribe, ""));
+        assert_none!(signer().verify(TokenPurpose::Unsubscribe, "no-dot"));
+        assert_none!(signer().verify(TokenPurpose::Unsubscribe, "!!.!!"));
+    }
+
+    #[test]
+    fn an_expiring_token_is_verified_until_it_expires() {
+        let expires_at = Utc::now() + Duration::hours(1);
+        let token = signer().sign_until(TokenPurpose::Preferences, "payload", expires_at);
+        assert_some_eq!(
+            signer().verify_unexpired(TokenPurpose::Preferences, &token),
+            "payload"
+        );
+
+        let expires_at = Utc::now() - Duration::seconds(1);
+        let token = signer().sign_until(TokenPurpose::Preferences, "payload", expires_at);
+        assert_none!(signer().verify_unexpired(TokenPurpose::Preferences, &token));
+    }
+
+    #[test]
+    fn tokens_without_an_expiry_are_rejected_where_one_is_expected() {
+        let token = signer().sign(TokenPurpose::Preferences, "payload");
+        assert_none!(signer().verify_unexpired(TokenPurpose::Preferences, &token));
+    }
+}
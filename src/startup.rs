@@ -1,36 +1,85 @@
+use actix_session::config::CookieContentSecurity;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
-use actix_web::{web, App, HttpServer};
+use actix_web::{guard, web, App, HttpServer};
+use actix_web_flash_messages::storage::CookieMessageStore;
+use actix_web_flash_messages::FlashMessagesFramework;
+use actix_web_lab::middleware::from_fn;
 use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
+use std::fmt::{Debug, Display};
 use std::net::TcpListener;
+use tokio::task::{JoinError, JoinHandle};
 use tracing_actix_web::TracingLogger;
 
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::authentication::reject_anonymous_users;
+use crate::client_address::TrustedProxies;
+use crate::configuration::{
+    DatabaseSettings, LoginProtectionSettings, Settings, SubscriptionSettings, WebhookSettings,
+};
 use crate::email_client::EmailClient;
-use crate::routes::{confirm, health_check, publish_newsletter, subscribe};
+use crate::email_outbox::EmailOutboxWorker;
+use crate::issue_delivery_worker::IssueDeliveryWorker;
+use crate::newsletter_scheduler::run_scheduler_until_stopped;
+use crate::rate_limiting::{limit_email_requests, RateLimiter};
+use crate::retention_worker::run_retention_worker_until_stopped;
+use crate::routes::{
+    admin_dashboard, atom_feed, browse_subscribers, cancel_newsletter_issue, cancel_subscription,
+    change_email, change_email_form, change_password, change_password_form, confirm,
+    confirm_subscription, create_draft, create_list, data_request_form, delete_draft, erase_data,
+    erase_subscriber, export_consent_history, export_data, export_subscribers, failed_logins,
+    forgot_password, forgot_password_form, get_email_template, get_issue, get_preferences,
+    health_check, import_subscribers, issue_deliveries, issue_page, issue_stats, issues_archive,
+    list_email_templates, list_issues, list_lists, log_out, login, login_form, manage_data,
+    postmark_webhook, preferences_form, preview_issue, publish_newsletter, put_email_template,
+    put_preferences, request_data, reschedule_newsletter_issue, reset_password,
+    reset_password_form, rss_feed, save_preferences, send_test_issue, subscribe, track_click,
+    track_open, unsubscribe, unsubscribe_form, update_draft, view_subscriber,
+};
+use crate::session_store::PgSessionStore;
+use crate::signed_token::TokenSigner;
+
+/// Subscriber CSV files can be much larger than the default payload limit.
+const MAX_IMPORT_SIZE: usize = 10 * 1024 * 1024;
 
 pub struct Application {
     port: u16,
     server: Server,
+    issue_delivery_worker: JoinHandle<Result<(), anyhow::Error>>,
+    email_outbox_worker: JoinHandle<Result<(), anyhow::Error>>,
+    retention_worker: JoinHandle<Result<(), anyhow::Error>>,
+    newsletter_scheduler: JoinHandle<Result<(), anyhow::Error>>,
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid email address");
-        let timeout = configuration.email_client.timeout();
+        let email_client = configuration.email_client.clone().client();
 
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
+        let issue_delivery_worker = tokio::spawn(
+            IssueDeliveryWorker::build(configuration.clone(), connection_pool.clone())
+                .run_until_stopped(),
+        );
+        let email_outbox_worker = tokio::spawn(
+            EmailOutboxWorker::build(configuration.clone(), connection_pool.clone())
+                .run_until_stopped(),
         );
+        let retention_worker = tokio::spawn(run_retention_worker_until_stopped(
+            connection_pool.clone(),
+            configuration.retention.clone(),
+            configuration.subscriptions.clone(),
+            configuration.login_protection.clone(),
+        ));
+        let newsletter_scheduler = tokio::spawn(run_scheduler_until_stopped(
+            connection_pool.clone(),
+            configuration.newsletter_scheduler.clone(),
+        ));
+
+        let rate_limiter = configuration.rate_limiting.limiter(connection_pool.clone());
 
         let address = format!(
             "{}:{}",
@@ -43,9 +92,22 @@ impl Application {
             connection_pool,
             email_client,
             configuration.application.base_url,
+            configuration.application.hmac_secret,
+            TrustedProxies::new(configuration.application.trusted_proxies),
+            configuration.subscriptions,
+            configuration.webhooks,
+            rate_limiter,
+            configuration.login_protection,
         )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            issue_delivery_worker,
+            email_outbox_worker,
+            retention_worker,
+            newsletter_scheduler,
+        })
     }
 
     pub fn port(&self) -> u16 {
@@ -53,29 +115,185 @@ impl Application {
     }
 
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let server = tokio::spawn(self.server);
+        tokio::select! {
+            outcome = server => report_exit("API", outcome),
+            outcome = self.issue_delivery_worker => report_exit("Issue delivery worker", outcome),
+            outcome = self.email_outbox_worker => report_exit("Email outbox worker", outcome),
+            outcome = self.retention_worker => report_exit("Retention worker", outcome),
+            outcome = self.newsletter_scheduler => report_exit("Newsletter scheduler", outcome),
+        };
+        Ok(())
+    }
+}
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_connection_pool: PgPool,
     email_client: EmailClient,
     base_url: Url,
+    hmac_secret: Secret<String>,
+    trusted_proxies: TrustedProxies,
+    subscription_settings: SubscriptionSettings,
+    webhook_settings: WebhookSettings,
+    rate_limiter: RateLimiter,
+    login_protection: LoginProtectionSettings,
 ) -> Result<Server, std::io::Error> {
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
+    let session_store = PgSessionStore::new(db_connection_pool.clone());
     let db_connection_pool = web::Data::new(db_connection_pool);
     let email_client = web::Data::new(email_client);
     let base_url = web::Data::new(base_url);
+    let token_signer = web::Data::new(TokenSigner::new(hmac_secret));
+    let trusted_proxies = web::Data::new(trusted_proxies);
+    let subscription_settings = web::Data::new(subscription_settings);
+    let webhook_settings = web::Data::new(webhook_settings);
+    let rate_limiter = web::Data::new(rate_limiter);
+    let login_protection = web::Data::new(login_protection);
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(message_framework.clone())
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                    .cookie_content_security(CookieContentSecurity::Signed)
+                    .build(),
+            )
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .route("/password/forgot", web::get().to(forgot_password_form))
+            .service(
+                web::resource("/password/forgot")
+                    .guard(guard::Post())
+                    .wrap(from_fn(limit_email_requests))
+                    .to(forgot_password),
+            )
+            .route("/password/reset", web::get().to(reset_password_form))
+            .route("/password/reset", web::post().to(reset_password))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/email", web::get().to(change_email_form))
+                    .route("/email", web::post().to(change_email))
+                    .route("/logout", web::post().to(log_out))
+                    .route("/templates", web::get().to(list_email_templates))
+                    .route("/templates/{name:.*}", web::get().to(get_email_template))
+                    .route("/templates/{name:.*}", web::put().to(put_email_template))
+                    .route("/issues", web::get().to(list_issues))
+                    .route("/issues", web::post().to(create_draft))
+                    .route("/issues/{id}", web::get().to(get_issue))
+                    .route("/issues/{id}", web::put().to(update_draft))
+                    .route("/issues/{id}", web::delete().to(delete_draft))
+                    .route("/issues/{id}/preview", web::get().to(preview_issue))
+                    .route("/issues/{id}/test", web::post().to(send_test_issue))
+                    .route("/issues/{id}/deliveries", web::get().to(issue_deliveries))
+                    .route("/issues/{id}/stats", web::get().to(issue_stats))
+                    .route(
+                        "/issues/{id}/cancel",
+                        web::post().to(cancel_newsletter_issue),
+                    )
+                    .route(
+                        "/issues/{id}/reschedule",
+                        web::post().to(reschedule_newsletter_issue),
+                    )
+                    .route("/lists", web::get().to(list_lists))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/login-attempts", web::get().to(failed_logins))
+                    .route("/subscribers", web::get().to(browse_subscribers))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .service(
+                        web::resource("/subscribers/import")
+                            .app_data(web::PayloadConfig::new(MAX_IMPORT_SIZE))
+                            .route(web::post().to(import_subscribers)),
+                    )
+                    .route("/subscribers/{id}", web::get().to(view_subscriber))
+                    .route("/subscribers/{id}", web::delete().to(erase_subscriber))
+                    .route(
+                        "/subscribers/{id}/events",
+                        web::get().to(export_consent_history),
+                    )
+                    .route(
+                        "/subscribers/{id}/confirm",
+                        web::post().to(confirm_subscription),
+                    )
+                    .route(
+                        "/subscribers/{id}/unsubscribe",
+                        web::post().to(cancel_subscription),
+                    ),
+            )
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(from_fn(limit_email_requests))
+                    .route(web::post().to(subscribe)),
+            )
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/preferences", web::get().to(preferences_form))
+            .route("/preferences", web::post().to(save_preferences))
+            .route("/api/preferences", web::get().to(get_preferences))
+            .route("/api/preferences", web::put().to(put_preferences))
+            .route("/data-requests", web::get().to(data_request_form))
+            .service(
+                web::resource("/data-requests")
+                    .guard(guard::Post())
+                    .wrap(from_fn(limit_email_requests))
+                    .to(request_data),
+            )
+            .route("/data-requests/manage", web::get().to(manage_data))
+            .route("/data-requests/export", web::get().to(export_data))
+            .route("/data-requests/erase", web::post().to(erase_data))
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/issues", web::get().to(issues_archive))
+            .route("/issues/{slug}", web::get().to(issue_page))
+            .route("/feed.rss", web::get().to(rss_feed))
+            .route("/feed.atom", web::get().to(atom_feed))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
+            .route("/track/open", web::get().to(track_open))
+            .route("/track/click", web::get().to(track_click))
             .app_data(db_connection_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(token_signer.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(webhook_settings.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(login_protection.clone())
     })
     .listen(listener)?
     .run();
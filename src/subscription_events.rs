@@ -0,0 +1,54 @@
+use actix_web::HttpRequest;
+
+use crate::client_address::client_address;
+
+/// A step of a subscription, recorded in the consent audit trail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubscriptionEventKind {
+    Subscribed,
+    ConfirmationSent,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+}
+
+impl SubscriptionEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionEventKind::Subscribed => "subscribed",
+            SubscriptionEventKind::ConfirmationSent => "confirmation_sent",
+            SubscriptionEventKind::Confirmed => "confirmed",
+            SubscriptionEventKind::Unsubscribed => "unsubscribed",
+            SubscriptionEventKind::Bounced => "bounced",
+            SubscriptionEventKind::Complained => "complained",
+        }
+    }
+}
+
+/// Where the request behind an event came from.
+#[derive(Debug, Default)]
+pub struct EventSource {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl EventSource {
+    /// Events reported by the email provider: its webhook requests tell us
+    /// nothing about the subscriber, so nothing is recorded.
+    pub fn email_provider() -> Self {
+        Self::default()
+    }
+
+    /// The client address is forwarded by the trusted proxies, if any.
+    pub fn from_request(request: &HttpRequest) -> Self {
+        Self {
+            ip_address: client_address(request),
+            user_agent: request
+                .headers()
+                .get("User-Agent")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::signed_token::{TokenPurpose, TokenSigner};
+
+/// What a subscriber did with a tracked issue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Open,
+    Click,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Open => "open",
+            EventKind::Click => "click",
+        }
+    }
+}
+
+/// Instruments the HTML of an issue sent to one subscriber: links go through
+/// `/track/click` and a pixel served by `/track/open` is appended.
+///
+/// Tokens are signed, so the redirect can't be abused to send readers to
+/// arbitrary websites and events can't be forged for other subscribers.
+pub struct IssueTracker<'a> {
+    base_url: &'a Url,
+    token_signer: &'a TokenSigner,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+}
+
+impl<'a> IssueTracker<'a> {
+    pub fn new(
+        base_url: &'a Url,
+        token_signer: &'a TokenSigner,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+    ) -> Self {
+        Self {
+            base_url,
+            token_signer,
+            newsletter_issue_id,
+            subscriber_id,
+        }
+    }
+
+    pub fn track(&self, html: &str) -> String {
+        let mut html = rewrite_links(html, |href| {
+            let url = htmlescape::decode_html(href)
+                .ok()
+                .and_then(|href| Url::parse(&href).ok())
+                .filter(|url| url.scheme() == "http" || url.scheme() == "https")?;
+            Some(self.click_url(&url).to_string())
+        });
+        html.push_str(&format!(
+            r#"<img src="{}" width="1" height="1" alt="">"#,
+            self.open_url()
+        ));
+        html
+    }
+
+    fn open_url(&self) -> Url {
+        let payload = format!("{} {}", self.newsletter_issue_id, self.subscriber_id);
+        self.tracking_url("/track/open", TokenPurpose::TrackOpen, &payload)
+    }
+
+    fn click_url(&self, destination: &Url) -> Url {
+        let payload = format!(
+            "{} {} {}",
+            self.newsletter_issue_id, self.subscriber_id, destination
+        );
+        self.tracking_url("/track/click", TokenPurpose::TrackClick, &payload)
+    }
+
+    // Tokens are URL-safe base64: the URL can go in an attribute as it is.
+    fn tracking_url(&self, path: &str, purpose: TokenPurpose, payload: &str) -> Url {
+        let mut url = self.base_url.join(path).unwrap();
+        url.query_pairs_mut()
+            .append_pair("token", &self.token_signer.sign(purpose, payload));
+        url
+    }
+}
+
+/// Returns the issue and subscriber ids signed in an open-tracking token.
+pub fn verify_open_token(token_signer: &TokenSigner, token: &str) -> Option<(Uuid, Uuid)> {
+    let payload = token_signer.verify(TokenPurpose::TrackOpen, token)?;
+    let (newsletter_issue_id, subscriber_id) = payload.split_once(' ')?;
+    Some((
+        Uuid::parse_str(newsletter_issue_id).ok()?,
+        Uuid::parse_str(subscriber_id).ok()?,
+    ))
+}
+
+/// Returns the issue and subscriber ids, plus the destination, signed in a
+/// click-tracking token.
+pub fn verify_click_token(token_signer: &TokenSigner, token: &str) -> Option<(Uuid, Uuid, Url)> {
+    let payload = token_signer.verify(TokenPurpose::TrackClick, token)?;
+    let mut parts = payload.splitn(3, ' ');
+    Some((
+        Uuid::parse_str(parts.next()?).ok()?,
+        Uuid::parse_str(parts.next()?).ok()?,
+        Url::parse(parts.next()?).ok()?,
+    ))
+}
+
+/// Replaces the value of every `href` attribute for which `rewrite` returns
+/// a new one.
+fn rewrite_links(html: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(html.len());
+    // ASCII lowercasing keeps byte offsets, so matches index `html` as well.
+    let lowercase_html = html.to_ascii_lowercase();
+    let mut position = 0;
+    while let Some(found) = lowercase_html[position..].find("href=") {
+        let start = position + found;
+        let after_name = start + "href=".len();
+        let is_attribute = html[position..start].ends_with(|c: char| c.is_ascii_whitespace());
+        let quote = html[after_name..]
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'');
+        let value_end = quote.and_then(|quote| html[after_name + 1..].find(quote));
+        match (is_attribute, quote, value_end) {
+            (true, Some(quote), Some(value_end)) => {
+                let value = &html[after_name + 1..after_name + 1 + value_end];
+                output.push_str(&html[position..after_name]);
+                output.push(quote);
+                output.push_str(&rewrite(value).unwrap_or_else(|| value.to_owned()));
+                output.push(quote);
+                position = after_name + 1 + value_end + 1;
+            }
+            _ => {
+                output.push_str(&html[position..after_name]);
+                position = after_name;
+            }
+        }
+    }
+    output.push_str(&html[position..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_none, assert_some_eq};
+    use reqwest::Url;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::{rewrite_links, verify_click_token, verify_open_token, IssueTracker};
+    use crate::signed_token::TokenSigner;
+
+    fn signer() -> TokenSigner {
+        TokenSigner::new(Secret::new("a-secret".into()))
+    }
+
+    fn token(url: &str) -> String {
+        let url = Url::parse(url).unwrap();
+        url.query_pairs()
+            .find(|(name, _)| name == "token")
+            .unwrap()
+            .1
+            .into_owned()
+    }
+
+    #[test]
+    fn links_are_rewritten_in_href_attributes_only() {
+        let html = r#"<a class="x" href="a">a</a><A HREF='b'>b</A><p data-href="c">href="d"</p>"#;
+
+        let rewritten = rewrite_links(html, |href| Some(href.to_uppercase()));
+
+        assert_eq!(
+            r#"<a class="x" href="A">a</a><A HREF='B'>b</A><p data-href="c">href="d"</p>"#,
+            rewritten
+        );
+    }
+
+    #[test]
+    fn unterminated_attributes_are_left_untouched() {
+        let html = r#"<a href="oops>"#;
+        assert_eq!(html, rewrite_links(html, |_| Some("x".into())));
+    }
+
+    #[test]
+    fn web_links_point_to_the_click_tracker_and_keep_their_destination() {
+        let (signer, base_url) = (signer(), Url::parse("https://example.com").unwrap());
+        let (newsletter_issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let tracker = IssueTracker::new(&base_url, &signer, newsletter_issue_id, subscriber_id);
+
+        let html = tracker.track(r#"<a href="https://blog.com/?a=1&amp;b=2">x</a>"#);
+
+        let href = html.split('"').nth(1).unwrap();
+        assert!(href.starts_with("https://example.com/track/click?token="));
+        assert_some_eq!(
+            verify_click_token(&signer, &token(href)),
+            (
+                newsletter_issue_id,
+                subscriber_id,
+                Url::parse("https://blog.com/?a=1&b=2").unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn other_links_are_left_alone_and_an_open_pixel_is_appended() {
+        let (signer, base_url) = (signer(), Url::parse("https://example.com").unwrap());
+        let (newsletter_issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let tracker = IssueTracker::new(&base_url, &signer, newsletter_issue_id, subscriber_id);
+
+        let html = tracker.track(r#"<a href="mailto:jon@email.com">x</a>"#);
+
+        assert!(html.starts_with(r#"<a href="mailto:jon@email.com">x</a><img src=""#));
+        let pixel = html.split('"').nth(3).unwrap();
+        assert_some_eq!(
+            verify_open_token(&signer, &token(pixel)),
+            (newsletter_issue_id, subscriber_id)
+        );
+        assert_none!(verify_click_token(&signer, &token(pixel)));
+    }
+}
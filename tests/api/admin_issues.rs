@@ -0,0 +1,356 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::newsletter_scheduler::dispatch_due_issues;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+fn draft_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Hello {{ subscriber.name }}",
+            "html": "<p>Hello {{ subscriber.name }}</p>"
+        }
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_drafts() {
+    let test_app = spawn_app().await;
+
+    let response = test_app.post_admin_issue(&draft_body("Title")).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited_and_deleted() {
+    let test_app = spawn_app().await;
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body("First")).await;
+
+    let issue: serde_json::Value = test_app
+        .get_admin_issue(newsletter_issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!("draft", issue["status"]);
+    assert_eq!("First", issue["title"]);
+
+    let response = test_app
+        .put_admin_issue(newsletter_issue_id, &draft_body("Second"))
+        .await;
+    assert_eq!(204, response.status().as_u16());
+    let issue: serde_json::Value = test_app
+        .get_admin_issue(newsletter_issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!("Second", issue["title"]);
+
+    let response = test_app.delete_admin_issue(newsletter_issue_id).await;
+    assert_eq!(204, response.status().as_u16());
+    let response = test_app.get_admin_issue(newsletter_issue_id).await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn drafts_are_not_delivered() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(0).await;
+
+    test_app.create_draft_issue(&draft_body("Title")).await;
+
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_edited_or_deleted() {
+    let test_app = spawn_app().await;
+    let response = test_app.publish_issue(&draft_body("Title")).await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    let response = test_app
+        .put_admin_issue(newsletter_issue_id, &draft_body("Changed"))
+        .await;
+    assert_eq!(409, response.status().as_u16());
+    let response = test_app.delete_admin_issue(newsletter_issue_id).await;
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn drafts_with_invalid_data_are_rejected() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    let test_cases = [
+        (
+            serde_json::json!({"content": {"text": "Body", "html": "<p>Body</p>"}}),
+            "a missing title",
+        ),
+        (serde_json::json!({"title": "Title"}), "missing content"),
+        (
+            serde_json::json!({"title": "Title", "content": {"text": "Body", "html": "{% if %}"}}),
+            "a template syntax error",
+        ),
+        (
+            serde_json::json!({"title": "Title", "content": {"text": "{{ subscriber.age }}", "html": ""}}),
+            "an unknown variable",
+        ),
+        (
+            serde_json::json!({"title": "Title", "content": {"text": "", "html": "{% include \"partials/missing.html\" %}"}}),
+            "a missing partial",
+        ),
+    ];
+
+    for (invalid_body, description) in test_cases {
+        let response = test_app.post_admin_issue(&invalid_body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a draft with {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn previews_are_rendered_for_a_placeholder_subscriber() {
+    let test_app = spawn_app().await;
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body("Title")).await;
+
+    let response = test_app.get_issue_preview(newsletter_issue_id).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let preview: serde_json::Value = response.json().await.unwrap();
+    assert!(preview["html"]
+        .as_str()
+        .unwrap()
+        .contains("<p>Hello Subscriber</p>"));
+    assert!(preview["text"]
+        .as_str()
+        .unwrap()
+        .contains("Hello Subscriber"));
+}
+
+#[tokio::test]
+async fn test_issues_are_only_sent_to_the_logged_in_user() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(0).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body("Title")).await;
+
+    let response = test_app.post_test_issue(newsletter_issue_id).await;
+
+    assert_eq!(204, response.status().as_u16());
+    // The last email, after the subscriber's confirmation.
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(test_app.test_user.email, body["To"]);
+    assert_eq!("[TEST] Title", body["Subject"]);
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(&format!("Hello {}", test_app.test_user.username)));
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn test_issues_are_sent_to_the_email_set_by_the_admin() {
+    let test_app = spawn_app().await;
+    let new_email = format!("{}@example.com", Uuid::new_v4());
+    test_app.email_mock_200_response().await;
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body("Title")).await;
+
+    test_app.post_change_email(&new_email).await;
+    let response = test_app.post_test_issue(newsletter_issue_id).await;
+
+    assert_eq!(204, response.status().as_u16());
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(new_email, body["To"]);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_cancel_an_issue() {
+    let test_app = spawn_app().await;
+
+    let response = test_app.post_cancel_issue(Uuid::new_v4()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_delivered() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(0).await;
+    test_app.test_user.login(&test_app).await;
+    let newsletter_issue_id = test_app
+        .schedule_newsletter(Utc::now() + Duration::hours(1))
+        .await;
+
+    let response = test_app.post_cancel_issue(newsletter_issue_id).await;
+    assert_eq!(204, response.status().as_u16());
+
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = now() WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        0,
+        dispatch_due_issues(&test_app.db_connection_pool)
+            .await
+            .unwrap()
+    );
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn rescheduled_issues_are_delivered_at_the_new_time() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    test_app.test_user.login(&test_app).await;
+    let newsletter_issue_id = test_app
+        .schedule_newsletter(Utc::now() + Duration::days(7))
+        .await;
+
+    let response = test_app
+        .post_reschedule_issue(newsletter_issue_id, Utc::now() - Duration::seconds(1))
+        .await;
+    assert_eq!(204, response.status().as_u16());
+
+    dispatch_due_issues(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn only_scheduled_issues_can_be_cancelled_or_rescheduled() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    let newsletter_issue_id = test_app
+        .schedule_newsletter(Utc::now() + Duration::hours(1))
+        .await;
+    test_app.post_cancel_issue(newsletter_issue_id).await;
+
+    let response = test_app.post_cancel_issue(newsletter_issue_id).await;
+    assert_eq!(409, response.status().as_u16());
+    let response = test_app
+        .post_reschedule_issue(newsletter_issue_id, Utc::now() + Duration::hours(2))
+        .await;
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn unknown_issues_cannot_be_cancelled_or_rescheduled() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    let response = test_app.post_cancel_issue(Uuid::new_v4()).await;
+    assert_eq!(404, response.status().as_u16());
+    let response = test_app
+        .post_reschedule_issue(Uuid::new_v4(), Utc::now())
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn deliveries_are_tracked_per_recipient() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app
+        .insert_confirmed_subscriber("Jane Doe", "janedoe@email.com")
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 0, "Message": "OK"},
+            {"ErrorCode": 406, "Message": "Inactive recipient"},
+        ])))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    let response = test_app.publish_issue(&draft_body("Title")).await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    let report: serde_json::Value = test_app
+        .get_issue_deliveries(newsletter_issue_id, "")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(2, report["counts"]["queued"]);
+
+    test_app.dispatch_all_pending_emails().await;
+
+    let report: serde_json::Value = test_app
+        .get_issue_deliveries(newsletter_issue_id, "")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        serde_json::json!({"queued": 0, "sent": 1, "failed": 1, "bounced": 0}),
+        report["counts"]
+    );
+    assert_eq!(2, report["deliveries"].as_array().unwrap().len());
+
+    let report: serde_json::Value = test_app
+        .get_issue_deliveries(newsletter_issue_id, "status=failed")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let deliveries = report["deliveries"].as_array().unwrap();
+    assert_eq!(1, deliveries.len());
+    assert_eq!("failed", deliveries[0]["status"]);
+    assert!(deliveries[0]["last_error"].is_string());
+}
+
+#[tokio::test]
+async fn deliveries_can_only_be_filtered_by_known_statuses() {
+    let test_app = spawn_app().await;
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body("Title")).await;
+
+    let response = test_app
+        .get_issue_deliveries(newsletter_issue_id, "status=lost")
+        .await;
+    assert_eq!(400, response.status().as_u16());
+
+    let response = test_app.get_issue_deliveries(Uuid::new_v4(), "").await;
+    assert_eq!(404, response.status().as_u16());
+}
@@ -0,0 +1,443 @@
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn subscribe(test_app: &TestApp, name: &str, email: &str) -> Uuid {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("name", name)
+        .append_pair("email", email)
+        .finish();
+    test_app.post_subscriptions(body).await;
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .expect("Failed to fetch saved subscription")
+        .id
+}
+
+async fn list_emails(test_app: &TestApp, query: &str) -> Vec<String> {
+    let response = test_app.get_admin_subscribers(query).await;
+    assert_eq!(200, response.status().as_u16());
+    let subscribers: Vec<Value> = response.json().await.unwrap();
+    subscribers
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+async fn get_status(test_app: &TestApp, subscriber_id: Uuid) -> String {
+    let response = test_app.get_admin_subscriber(subscriber_id).await;
+    assert_eq!(200, response.status().as_u16());
+    let subscriber: Value = response.json().await.unwrap();
+    subscriber["status"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    let test_app = spawn_app().await;
+    let subscriber_id = Uuid::new_v4();
+
+    let responses = [
+        test_app.get_admin_subscribers("").await,
+        test_app.get_admin_subscriber(subscriber_id).await,
+        test_app
+            .post_admin_subscriber_action(subscriber_id, "confirm")
+            .await,
+        test_app.delete_admin_subscriber(subscriber_id).await,
+        test_app.get_consent_history(subscriber_id).await,
+    ];
+
+    for response in responses {
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_email() {
+    let test_app = spawn_app().await;
+    test_app.email_mock_200_response_with_times(2).await;
+    let jon = subscribe(&test_app, "Jon", "jon@email.com").await;
+    subscribe(&test_app, "Ursula", "ursula@other.org").await;
+    test_app.test_user.login(&test_app).await;
+    test_app
+        .post_admin_subscriber_action(jon, "confirm")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    assert_eq!(
+        vec!["jon@email.com", "ursula@other.org"],
+        list_emails(&test_app, "").await
+    );
+    assert_eq!(
+        vec!["jon@email.com"],
+        list_emails(&test_app, "status=confirmed").await
+    );
+    assert_eq!(
+        vec!["ursula@other.org"],
+        list_emails(&test_app, "email=OTHER.org").await
+    );
+    assert!(list_emails(&test_app, "status=confirmed&email=other")
+        .await
+        .is_empty());
+    assert!(list_emails(&test_app, "page=2").await.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    for query in ["status=happy", "page=0"] {
+        let response = test_app.get_admin_subscribers(query).await;
+        assert_eq!(400, response.status().as_u16(), "Accepted {}", query);
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_be_confirmed_and_unsubscribed_by_hand() {
+    let test_app = spawn_app().await;
+    test_app.email_mock_200_response().await;
+    let subscriber_id = subscribe(&test_app, "Jon", "jon@email.com").await;
+    test_app.test_user.login(&test_app).await;
+    assert_eq!(
+        "pending confirmation",
+        get_status(&test_app, subscriber_id).await
+    );
+
+    let response = test_app
+        .post_admin_subscriber_action(subscriber_id, "confirm")
+        .await;
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!("confirmed", get_status(&test_app, subscriber_id).await);
+
+    let response = test_app
+        .post_admin_subscriber_action(subscriber_id, "unsubscribe")
+        .await;
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!("unsubscribed", get_status(&test_app, subscriber_id).await);
+}
+
+#[tokio::test]
+async fn undeliverable_subscribers_cannot_be_confirmed_or_unsubscribed_by_hand() {
+    let test_app = spawn_app().await;
+    test_app.email_mock_200_response().await;
+    let subscriber_id = subscribe(&test_app, "Jon", "jon@email.com").await;
+    test_app.test_user.login(&test_app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'bounced'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+
+    for action in ["confirm", "unsubscribe"] {
+        let response = test_app
+            .post_admin_subscriber_action(subscriber_id, action)
+            .await;
+        assert_eq!(409, response.status().as_u16(), "Allowed to {}", action);
+    }
+    assert_eq!("bounced", get_status(&test_app, subscriber_id).await);
+
+    let response = test_app
+        .post_admin_subscriber_action(Uuid::new_v4(), "unsubscribe")
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_erases_their_tokens_and_deliveries() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    test_app
+        .publish_issue(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Body", "html": "<p>Body</p>"}
+        }))
+        .await;
+    test_app.dispatch_all_pending_emails().await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap()
+        .id;
+
+    let response = test_app.delete_admin_subscriber(subscriber_id).await;
+
+    assert_eq!(204, response.status().as_u16());
+    let response = test_app.get_admin_subscriber(subscriber_id).await;
+    assert_eq!(404, response.status().as_u16());
+    let leftovers = sqlx::query!(
+        r#"SELECT
+            (SELECT COUNT(*) FROM subscription_tokens WHERE subscriber_id = $1) AS "tokens!",
+            (SELECT COUNT(*) FROM issue_deliveries WHERE subscriber_id = $1) AS "deliveries!""#,
+        subscriber_id
+    )
+    .fetch_one(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(0, leftovers.tokens);
+    assert_eq!(0, leftovers.deliveries);
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_not_found() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    let subscriber_id = Uuid::new_v4();
+
+    let responses = [
+        test_app.get_admin_subscriber(subscriber_id).await,
+        test_app
+            .post_admin_subscriber_action(subscriber_id, "confirm")
+            .await,
+        test_app
+            .post_admin_subscriber_action(subscriber_id, "unsubscribe")
+            .await,
+        test_app.delete_admin_subscriber(subscriber_id).await,
+    ];
+
+    for response in responses {
+        assert_eq!(404, response.status().as_u16());
+    }
+}
+
+async fn import(test_app: &TestApp, query: &str, csv: &str) -> Value {
+    let response = test_app.post_subscribers_import(query, csv).await;
+    assert_eq!(200, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn imported_rows_are_validated_one_by_one() {
+    let test_app = spawn_app().await;
+    test_app.email_mock_200_response_with_times(0).await;
+    test_app.test_user.login(&test_app).await;
+    let csv = "name,email\n\
+        Jon,jon@email.com\n\
+        Ursula,not-an-email\n\
+        ,nameless@email.com\n\
+        Ursula,ursula@email.com\n";
+
+    let report = import(&test_app, "confirmed=true", csv).await;
+
+    assert_eq!(2, report["imported"]);
+    let errors = report["errors"].as_array().unwrap();
+    assert_eq!(
+        vec![3, 4],
+        errors
+            .iter()
+            .map(|e| e["line"].as_u64().unwrap())
+            .collect::<Vec<_>>()
+    );
+    assert!(errors[0]["error"]
+        .as_str()
+        .unwrap()
+        .contains("not-an-email"));
+    assert_eq!(
+        vec!["jon@email.com", "ursula@email.com"],
+        list_emails(&test_app, "status=confirmed").await
+    );
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_asked_to_confirm_by_default() {
+    let test_app = spawn_app().await;
+    test_app.email_mock_200_response_with_times(2).await;
+    test_app.test_user.login(&test_app).await;
+
+    let report = import(
+        &test_app,
+        "",
+        "email,name\njon@email.com,Jon\nursula@email.com,Ursula\n",
+    )
+    .await;
+
+    assert_eq!(2, report["imported"]);
+    assert_eq!(
+        2,
+        list_emails(&test_app, "status=pending%20confirmation")
+            .await
+            .len()
+    );
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(1, list_emails(&test_app, "status=confirmed").await.len());
+}
+
+#[tokio::test]
+async fn importing_a_file_again_skips_existing_subscribers() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    let csv = "name,email\nJon,jon@email.com\n";
+    import(&test_app, "confirmed=true", csv).await;
+
+    let report = import(
+        &test_app,
+        "confirmed=true",
+        "name,email\nJon,jon@email.com\nUrsula,ursula@email.com\n",
+    )
+    .await;
+
+    assert_eq!(1, report["imported"]);
+    assert_eq!(1, report["already_on_list"]);
+    assert_eq!(0, report["added_to_list"]);
+}
+
+#[tokio::test]
+async fn known_subscribers_are_added_to_the_list_they_are_imported_into() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    import(
+        &test_app,
+        "confirmed=true",
+        "name,email
+Jon,jon@email.com
+",
+    )
+    .await;
+    let list_id = test_app.create_list("Weekly").await;
+
+    let report = import(
+        &test_app,
+        &format!("confirmed=true&list_id={}", list_id),
+        "name,email
+Jonathan,jon@email.com
+",
+    )
+    .await;
+
+    assert_eq!(0, report["imported"]);
+    assert_eq!(1, report["added_to_list"]);
+    assert_eq!(0, report["already_on_list"]);
+    let membership = sqlx::query!(
+        "SELECT m.status, s.name FROM list_memberships m
+        JOIN subscriptions s ON s.id = m.subscriber_id
+        WHERE m.list_id = $1",
+        list_id
+    )
+    .fetch_one(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+    assert_eq!("confirmed", membership.status);
+    // The subscriber keeps their details.
+    assert_eq!("Jon", membership.name);
+
+    let report = import(
+        &test_app,
+        &format!("confirmed=true&list_id={}", list_id),
+        "name,email
+Jon,jon@email.com
+",
+    )
+    .await;
+    assert_eq!(1, report["already_on_list"]);
+}
+
+#[tokio::test]
+async fn undeliverable_subscribers_are_not_added_to_lists() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    import(
+        &test_app,
+        "confirmed=true",
+        "name,email
+Jon,jon@email.com
+",
+    )
+    .await;
+    sqlx::query!("UPDATE subscriptions SET status = 'bounced'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    let list_id = test_app.create_list("Weekly").await;
+
+    let report = import(
+        &test_app,
+        &format!("list_id={}", list_id),
+        "name,email
+Jon,jon@email.com
+",
+    )
+    .await;
+
+    assert_eq!(1, report["undeliverable"]);
+    let n_members = sqlx::query!(
+        r#"SELECT COUNT(*) AS "n!" FROM list_memberships WHERE list_id = $1"#,
+        list_id
+    )
+    .fetch_one(&test_app.db_connection_pool)
+    .await
+    .unwrap()
+    .n;
+    assert_eq!(0, n_members);
+}
+
+#[tokio::test]
+async fn imports_without_name_and_email_columns_are_rejected() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    let response = test_app
+        .post_subscribers_import("", "full_name,address\nJon,jon@email.com\n")
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_whole_list_is_exported_as_csv() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    // More than a page of the export, to cover the stream resuming.
+    let csv: String = std::iter::once("name,email\n".to_owned())
+        .chain((0..520).map(|i| format!("Reader {i},reader{i:03}@email.com\n")))
+        .collect();
+    import(&test_app, "confirmed=true", &csv).await;
+
+    let response = test_app.get_subscribers_export("").await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "text/csv; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(521, lines.len());
+    assert_eq!("email,name,status,subscribed_at", lines[0]);
+    assert!(lines[1].starts_with("reader000@email.com,Reader 0,confirmed,"));
+    assert!(lines[520].starts_with("reader519@email.com,Reader 519,confirmed,"));
+
+    let response = test_app.get_subscribers_export("status=unsubscribed").await;
+    assert_eq!(1, response.text().await.unwrap().lines().count());
+}
+
+#[tokio::test]
+async fn exported_cells_cannot_be_run_as_formulas() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    import(
+        &test_app,
+        "confirmed=true",
+        "name,email\n=1+2 Jon,jon@email.com\n@SUM Ursula,ursula@email.com\n",
+    )
+    .await;
+
+    let body = test_app
+        .get_subscribers_export("")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    let lines: Vec<_> = body.lines().collect();
+    assert!(lines[1].starts_with("jon@email.com,'=1+2 Jon,confirmed,"));
+    assert!(lines[2].starts_with("ursula@email.com,'@SUM Ursula,confirmed,"));
+}
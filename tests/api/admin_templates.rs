@@ -0,0 +1,88 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_templates() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .put_admin_template("emails/confirmation.txt", "Hello")
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn stored_templates_can_be_retrieved() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    let response = test_app.get_admin_template("layouts/base.html").await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["source"]
+        .as_str()
+        .unwrap()
+        .contains("{% block content %}"));
+
+    let response = test_app.get_admin_template("layouts/missing.html").await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn confirmation_emails_use_the_updated_template() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    test_app.email_mock_200_response().await;
+
+    let response = test_app
+        .put_admin_template(
+            "emails/confirmation.txt",
+            "Hey {{ subscriber.name }}, confirm here: {{ confirmation_link }}",
+        )
+        .await;
+    assert_eq!(204, response.status().as_u16());
+
+    test_app
+        .post_subscriptions("name=Jon%20Doe&email=jondoe%40email.com".into())
+        .await;
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hey Jon Doe, confirm here: "));
+    test_app.get_confirmation_links(email_request);
+}
+
+#[tokio::test]
+async fn templates_that_do_not_render_are_rejected() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    let test_cases = [
+        ("emails/confirmation.html", "{% if %}", "syntax error"),
+        (
+            "emails/confirmation.txt",
+            "{{ subscriber.age }}",
+            "unknown variable",
+        ),
+        (
+            "layouts/base.html",
+            "{% extends \"missing.html\" %}",
+            "missing layout",
+        ),
+        ("emails/new template.html", "Hello", "invalid name"),
+        ("emails/confirmation.md", "Hello", "unsupported extension"),
+    ];
+
+    for (name, source, description) in test_cases {
+        let response = test_app.put_admin_template(name, source).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a template with a {}",
+            description
+        );
+    }
+}
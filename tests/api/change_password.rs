@@ -0,0 +1,161 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_change_password_form() {
+    let test_app = spawn_app().await;
+
+    let response = test_app.get_change_password().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_your_password() {
+    let test_app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = test_app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn new_password_fields_must_match() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    let response = test_app
+        .post_change_password(&serde_json::json!({
+            "current_password": &test_app.test_user.password,
+            "new_password": "Correct-horse-battery",
+            "new_password_check": "Correct-horse-staple",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = test_app.get_change_password_html().await;
+    assert!(html_page.contains(
+        "<p><i>You entered two different new passwords - the field values must match.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn current_password_must_be_valid() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    let response = test_app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": "Correct-horse-battery",
+            "new_password_check": "Correct-horse-battery",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = test_app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+}
+
+#[tokio::test]
+async fn weak_new_passwords_are_rejected() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    let test_cases = vec![("short", "too short"), ("onlylowercaseletters", "too weak")];
+
+    for (new_password, error_message) in test_cases {
+        let response = test_app
+            .post_change_password(&serde_json::json!({
+                "current_password": &test_app.test_user.password,
+                "new_password": new_password,
+                "new_password_check": new_password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/password");
+
+        let html_page = test_app.get_change_password_html().await;
+        assert!(
+            html_page.contains("<p><i>The new password must"),
+            "The password was not rejected when it was {}",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn changing_password_works() {
+    let test_app = spawn_app().await;
+    let new_password = "Correct-horse-battery";
+
+    test_app.test_user.login(&test_app).await;
+
+    let response = test_app
+        .post_change_password(&serde_json::json!({
+            "current_password": &test_app.test_user.password,
+            "new_password": new_password,
+            "new_password_check": new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = test_app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+
+    let response = test_app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": &test_app.test_user.username,
+            "password": new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn changing_password_logs_out_every_other_session() {
+    let test_app = spawn_app().await;
+    let new_password = "Correct-horse-battery";
+    let other_device = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let dashboard = format!("{}/admin/dashboard", test_app.address);
+
+    test_app.test_user.login(&test_app).await;
+    other_device
+        .post(format!("{}/login", test_app.address))
+        .form(&serde_json::json!({
+            "username": &test_app.test_user.username,
+            "password": &test_app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+    let response = other_device.get(&dashboard).send().await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    test_app
+        .post_change_password(&serde_json::json!({
+            "current_password": &test_app.test_user.password,
+            "new_password": new_password,
+            "new_password_check": new_password,
+        }))
+        .await;
+
+    let response = other_device.get(&dashboard).send().await.unwrap();
+    assert_is_redirect_to(&response, "/login");
+    let response = test_app.get_admin_dashboard().await;
+    assert_eq!(200, response.status().as_u16());
+}
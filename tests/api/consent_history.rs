@@ -0,0 +1,157 @@
+use secrecy::ExposeSecret;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Subscribes Jon Doe from a browser and returns his id.
+async fn subscribe_from_browser(test_app: &TestApp) -> Uuid {
+    test_app.email_mock_200_response().await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", test_app.address))
+        .header("User-Agent", "Mozilla/5.0 (test)")
+        .form(&[("name", "Jon Doe"), ("email", "jondoe@email.com")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn get_events(test_app: &TestApp, subscriber_id: Uuid) -> Vec<Value> {
+    test_app.test_user.login(test_app).await;
+    let response = test_app.get_consent_history(subscriber_id).await;
+    assert_eq!(200, response.status().as_u16());
+    let history: Value = response.json().await.unwrap();
+    assert_eq!(subscriber_id.to_string(), history["subscriber"]["id"]);
+    history["events"].as_array().unwrap().clone()
+}
+
+fn kinds(events: &[Value]) -> Vec<&str> {
+    events
+        .iter()
+        .map(|event| event["kind"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn the_double_opt_in_is_recorded_with_its_source() {
+    let test_app = spawn_app().await;
+    let subscriber_id = subscribe_from_browser(&test_app).await;
+
+    test_app.call_confirmation_link().await;
+
+    let events = get_events(&test_app, subscriber_id).await;
+    assert_eq!(
+        vec!["subscribed", "confirmation_sent", "confirmed"],
+        kinds(&events)
+    );
+    let subscribed = &events[0];
+    assert_eq!("jondoe@email.com", subscribed["email"]);
+    assert_eq!("127.0.0.1", subscribed["ip_address"]);
+    assert_eq!("Mozilla/5.0 (test)", subscribed["user_agent"]);
+    assert!(subscribed["detail"].as_str().unwrap().starts_with("List "));
+    assert!(subscribed["occurred_at"].is_string());
+}
+
+#[tokio::test]
+async fn unsubscriptions_and_bounces_are_recorded() {
+    let test_app = spawn_app().await;
+    let subscriber_id = subscribe_from_browser(&test_app).await;
+    test_app.call_confirmation_link().await;
+    test_app.test_user.login(&test_app).await;
+    test_app
+        .post_admin_subscriber_action(subscriber_id, "unsubscribe")
+        .await;
+    let bounce = serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": "HardBounce",
+        "Email": "jondoe@email.com",
+    });
+    let secret = test_app.webhook_settings.postmark_secret.expose_secret();
+    test_app.post_postmark_webhook(&bounce, secret).await;
+
+    let events = get_events(&test_app, subscriber_id).await;
+
+    assert_eq!(
+        vec![
+            "subscribed",
+            "confirmation_sent",
+            "confirmed",
+            "unsubscribed",
+            "bounced"
+        ],
+        kinds(&events)
+    );
+    assert_eq!("By an administrator", events[3]["detail"]);
+    assert_eq!("HardBounce", events[4]["detail"]);
+    // The webhook request comes from the provider, not from the subscriber.
+    assert!(events[4]["ip_address"].is_null());
+}
+
+#[tokio::test]
+async fn spam_complaints_are_recorded_as_such() {
+    let test_app = spawn_app().await;
+    let subscriber_id = subscribe_from_browser(&test_app).await;
+    test_app.test_user.login(&test_app).await;
+    let complaint = serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "Email": "jondoe@email.com",
+    });
+    let secret = test_app.webhook_settings.postmark_secret.expose_secret();
+    test_app.post_postmark_webhook(&complaint, secret).await;
+
+    let events = get_events(&test_app, subscriber_id).await;
+
+    assert_eq!(
+        vec!["subscribed", "confirmation_sent", "complained"],
+        kinds(&events)
+    );
+    assert!(events[2]["ip_address"].is_null());
+}
+
+#[tokio::test]
+async fn the_history_of_an_unknown_subscriber_is_a_404() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+
+    let response = test_app.get_consent_history(Uuid::new_v4()).await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn recorded_events_cannot_be_altered() {
+    let test_app = spawn_app().await;
+    subscribe_from_browser(&test_app).await;
+
+    let result = sqlx::query!("UPDATE subscription_events SET kind = 'confirmed'")
+        .execute(&test_app.db_connection_pool)
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn recorded_events_are_only_deleted_with_their_subscriber() {
+    let test_app = spawn_app().await;
+    let subscriber_id = subscribe_from_browser(&test_app).await;
+
+    let result = sqlx::query!("DELETE FROM subscription_events")
+        .execute(&test_app.db_connection_pool)
+        .await;
+    assert!(result.is_err());
+
+    test_app.test_user.login(&test_app).await;
+    let response = test_app.delete_admin_subscriber(subscriber_id).await;
+    assert_eq!(204, response.status().as_u16());
+    let remaining = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_events"#)
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(0, remaining.count);
+}
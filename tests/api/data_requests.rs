@@ -0,0 +1,237 @@
+use reqwest::Url;
+use serde_json::Value;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::retention_worker::purge_expired_data_request_tokens;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn post_data_request(test_app: &TestApp, email: &str) -> reqwest::Response {
+    test_app
+        .api_client
+        .post(format!("{}/data-requests", test_app.address))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_html(test_app: &TestApp, url: &str) -> String {
+    test_app
+        .api_client
+        .get(url)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+/// Asks for the data of the confirmed subscriber and returns the link they
+/// receive by email.
+async fn data_request_link(test_app: &TestApp) -> Url {
+    test_app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = post_data_request(test_app, "jondoe@email.com").await;
+    assert_is_redirect_to(&response, "/data-requests");
+    test_app.dispatch_all_pending_emails().await;
+
+    let requests = test_app.email_server.received_requests().await.unwrap();
+    let link = test_app
+        .get_confirmation_links(requests.last().unwrap())
+        .html;
+    assert_eq!("/data-requests/manage", link.path());
+    link
+}
+
+fn with_path(link: &Url, path: &str) -> Url {
+    let mut link = link.clone();
+    link.set_path(path);
+    link
+}
+
+async fn count_rows(test_app: &TestApp, table: &str) -> i64 {
+    sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {}", table))
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn requests_for_unknown_emails_get_the_same_answer_and_no_email() {
+    let test_app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = post_data_request(&test_app, "nobody@email.com").await;
+
+    assert_is_redirect_to(&response, "/data-requests");
+    test_app.dispatch_all_pending_emails().await;
+    let html = get_html(&test_app, &format!("{}/data-requests", test_app.address)).await;
+    assert!(html.contains(
+        "<p><i>If we hold data about that email, you will receive a link shortly.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn requests_do_not_wait_for_the_email_provider() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = post_data_request(&test_app, "jondoe@email.com").await;
+
+    assert_is_redirect_to(&response, "/data-requests");
+}
+
+#[tokio::test]
+async fn the_emailed_link_offers_to_download_or_erase_the_data() {
+    let test_app = spawn_app().await;
+    let link = data_request_link(&test_app).await;
+
+    let html = get_html(&test_app, link.as_str()).await;
+
+    assert!(html.contains("/data-requests/export?token="));
+    assert!(html.contains("/data-requests/erase?token="));
+}
+
+/// Queues an email the outbox worker leaves alone for the rest of the test.
+async fn queue_email_to(test_app: &TestApp, recipient: &str) {
+    sqlx::query!(
+        "INSERT INTO email_outbox
+        (email_id, recipient, subject, text_content, html_content, execute_after)
+        VALUES (gen_random_uuid(), $1, 'Later', 'Hi Jon', '<p>Hi Jon</p>', now() + interval '1 day')",
+        recipient
+    )
+    .execute(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn the_export_contains_every_row_about_the_subscriber() {
+    let test_app = spawn_app().await;
+    let link = data_request_link(&test_app).await;
+    queue_email_to(&test_app, "jondoe@email.com").await;
+    queue_email_to(&test_app, "someone-else@email.com").await;
+
+    let response = test_app
+        .api_client
+        .get(with_path(&link, "/data-requests/export"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    let data: Value = response.json().await.unwrap();
+    assert_eq!("jondoe@email.com", data["subscription"]["email"]);
+    assert_eq!("Jon Doe", data["subscription"]["name"]);
+    assert_eq!("confirmed", data["list_memberships"][0]["status"]);
+    assert_eq!(1, data["subscription_tokens"].as_array().unwrap().len());
+    assert_eq!(1, data["data_request_tokens"].as_array().unwrap().len());
+    assert_eq!(3, data["subscription_events"].as_array().unwrap().len());
+    assert!(data["deliveries"].as_array().unwrap().is_empty());
+    assert_eq!(1, data["queued_emails"].as_array().unwrap().len());
+    assert_eq!("Hi Jon", data["queued_emails"][0]["text_content"]);
+    assert!(data["subscription_tokens"][0]
+        .get("subscription_token")
+        .is_none());
+}
+
+#[tokio::test]
+async fn a_link_is_used_up_by_the_export() {
+    let test_app = spawn_app().await;
+    let link = data_request_link(&test_app).await;
+    let export_link = with_path(&link, "/data-requests/export");
+
+    let response = test_app
+        .api_client
+        .get(export_link.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let response = test_app.api_client.get(export_link).send().await.unwrap();
+    assert_is_redirect_to(&response, "/data-requests");
+    assert_eq!(0, count_rows(&test_app, "data_request_tokens").await);
+}
+
+#[tokio::test]
+async fn expired_links_are_purged() {
+    let test_app = spawn_app().await;
+    data_request_link(&test_app).await;
+    sqlx::query!("UPDATE data_request_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+
+    let n_purged = purge_expired_data_request_tokens(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(1, n_purged);
+    assert_eq!(0, count_rows(&test_app, "data_request_tokens").await);
+}
+
+#[tokio::test]
+async fn erasure_deletes_every_row_about_the_subscriber() {
+    let test_app = spawn_app().await;
+    let link = data_request_link(&test_app).await;
+    queue_email_to(&test_app, "JonDoe@email.com").await;
+
+    let response = test_app
+        .api_client
+        .post(with_path(&link, "/data-requests/erase"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    for table in [
+        "subscriptions",
+        "subscription_tokens",
+        "data_request_tokens",
+        "list_memberships",
+        "subscription_events",
+        "email_outbox",
+    ] {
+        assert_eq!(0, count_rows(&test_app, table).await, "{}", table);
+    }
+    let response = test_app.api_client.get(link).send().await.unwrap();
+    assert_is_redirect_to(&response, "/data-requests");
+}
+
+#[tokio::test]
+async fn invalid_links_lead_back_to_the_request_form() {
+    let test_app = spawn_app().await;
+
+    for path in [
+        "/data-requests/manage",
+        "/data-requests/export",
+        "/data-requests/erase",
+    ] {
+        let url = format!("{}{}?token=forged", test_app.address, path);
+        let response = if path.ends_with("erase") {
+            test_app.api_client.post(&url).send().await.unwrap()
+        } else {
+            test_app.api_client.get(&url).send().await.unwrap()
+        };
+        assert_is_redirect_to(&response, "/data-requests");
+    }
+    let html = get_html(&test_app, &format!("{}/data-requests", test_app.address)).await;
+    assert!(html.contains("The link is invalid or has expired."));
+}
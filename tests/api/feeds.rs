@@ -0,0 +1,118 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish(test_app: &TestApp, title: &str) -> String {
+    test_app
+        .publish_issue(&serde_json::json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Hello {{ subscriber.name }}</p>"
+            }
+        }))
+        .await;
+    sqlx::query!("SELECT slug FROM newsletter_issues WHERE title = $1", title)
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap()
+        .slug
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_rss_feed_lists_published_issues_with_absolute_links() {
+    let test_app = spawn_app().await;
+    let slug = publish(&test_app, "Fish & Chips").await;
+
+    let response = test_app.feed_request("feed.rss").send().await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "application/rss+xml; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    let xml = response.text().await.unwrap();
+    assert!(xml.contains("<title>Fish &amp; Chips</title>"));
+    assert!(xml.contains("<link>http"));
+    assert!(xml.contains(&format!("/issues/{}</link>", slug)));
+    assert!(xml.contains("&lt;p&gt;Hello reader&lt;/p&gt;"));
+}
+
+#[tokio::test]
+async fn the_atom_feed_lists_published_issues_with_absolute_links() {
+    let test_app = spawn_app().await;
+    let slug = publish(&test_app, "Newsletter Title").await;
+    test_app
+        .create_draft_issue(&serde_json::json!({
+            "title": "Draft issue",
+            "content": {"text": "", "html": ""}
+        }))
+        .await;
+
+    let response = test_app.feed_request("feed.atom").send().await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "application/atom+xml; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    let xml = response.text().await.unwrap();
+    assert!(xml.contains("<title>Newsletter Title</title>"));
+    assert!(xml.contains(r#"<link rel="alternate" href="http"#));
+    assert!(xml.contains(&format!(r#"/issues/{}" />"#, slug)));
+    assert!(!xml.contains("Draft issue"));
+}
+
+#[tokio::test]
+async fn feeds_support_conditional_requests() {
+    let test_app = spawn_app().await;
+    publish(&test_app, "First issue").await;
+
+    for feed in ["feed.rss", "feed.atom"] {
+        let response = test_app.feed_request(feed).send().await.unwrap();
+        let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+        let last_modified = response.headers()["Last-Modified"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        let response = test_app
+            .feed_request(feed)
+            .header("If-None-Match", &etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(304, response.status().as_u16(), "{} ignored its ETag", feed);
+
+        let response = test_app
+            .feed_request(feed)
+            .header("If-Modified-Since", &last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            304,
+            response.status().as_u16(),
+            "{} ignored its Last-Modified date",
+            feed
+        );
+    }
+}
+
+#[tokio::test]
+async fn feeds_change_when_an_issue_is_published() {
+    let test_app = spawn_app().await;
+    publish(&test_app, "First issue").await;
+    let response = test_app.feed_request("feed.rss").send().await.unwrap();
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    publish(&test_app, "Second issue").await;
+    let response = test_app
+        .feed_request("feed.rss")
+        .header("If-None-Match", &etag)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("Second issue"));
+}
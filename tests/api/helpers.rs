@@ -1,5 +1,6 @@
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
+use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
 use reqwest::{Client, Response, Url};
 use serde_json::Value;
@@ -7,7 +8,12 @@ use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, MockServer, ResponseTemplate};
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, IssueDeliverySettings, LoginProtectionSettings,
+    RetentionSettings, Settings, SubscriptionSettings, WebhookSettings,
+};
+use zero2prod::email_outbox::EmailOutboxWorker;
+use zero2prod::issue_delivery_worker::{ExecutionOutcome, IssueDeliveryWorker};
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -17,6 +23,14 @@ pub struct TestApp {
     pub db_connection_pool: PgPool,
     pub email_server: MockServer,
     pub test_user: TestUser,
+    pub issue_delivery_worker: IssueDeliveryWorker,
+    pub email_outbox_worker: EmailOutboxWorker,
+    pub issue_delivery_settings: IssueDeliverySettings,
+    pub subscription_settings: SubscriptionSettings,
+    pub retention_settings: RetentionSettings,
+    pub webhook_settings: WebhookSettings,
+    pub login_protection_settings: LoginProtectionSettings,
+    pub api_client: Client,
 }
 
 pub struct ConfirmationLinks {
@@ -28,14 +42,25 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub email: String,
 }
 
 impl TestUser {
+    pub async fn login(&self, test_app: &TestApp) {
+        test_app
+            .post_login(&serde_json::json!({
+                "username": &self.username,
+                "password": &self.password
+            }))
+            .await;
+    }
+
     pub fn generate() -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", Uuid::new_v4()),
         }
     }
 
@@ -48,10 +73,11 @@ impl TestUser {
             .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password) VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password, email) VALUES ($1, $2, $3, $4)",
             self.user_id,
             self.username,
             password_hash,
+            self.email,
         )
         .execute(pool)
         .await
@@ -62,7 +88,7 @@ impl TestUser {
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -72,7 +98,7 @@ impl TestApp {
 
     pub async fn post_newsletters(&self, json_body: Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&json_body)
             .send()
@@ -80,6 +106,508 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        json_body: &Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(json_body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_change_email_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/email", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_change_email(&self, email: &str) -> reqwest::Response {
+        self.post_change_email_with_password(email, &self.test_user.password)
+            .await
+    }
+
+    pub async fn post_change_email_with_password(
+        &self,
+        email: &str,
+        current_password: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/email", &self.address))
+            .form(&serde_json::json!({
+                "email": email,
+                "current_password": current_password
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_template(&self, name: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/templates/{}", &self.address, name))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_admin_template(&self, name: &str, source: &str) -> reqwest::Response {
+        self.api_client
+            .put(format!("{}/admin/templates/{}", &self.address, name))
+            .json(&serde_json::json!({ "source": source }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_admin_issue(&self, body: &Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/issues", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_admin_issue(
+        &self,
+        newsletter_issue_id: Uuid,
+        body: &Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!(
+                "{}/admin/issues/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn delete_admin_issue(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .delete(format!(
+                "{}/admin/issues/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_issue(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/issues/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_issue_preview(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/issues/{}/preview",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_test_issue(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/issues/{}/test",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_issue_deliveries(
+        &self,
+        newsletter_issue_id: Uuid,
+        query: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/issues/{}/deliveries?{}",
+                &self.address, newsletter_issue_id, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_issue_stats(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/issues/{}/stats",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_lists(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_admin_list(&self, name: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .json(&serde_json::json!({ "name": name }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Logs the test user in and creates a mailing list, returning its id.
+    pub async fn create_list(&self, name: &str) -> Uuid {
+        self.test_user.login(self).await;
+        let response = self.post_admin_list(name).await;
+        assert_eq!(201, response.status().as_u16());
+        let body: Value = response.json().await.unwrap();
+        body["list_id"].as_str().unwrap().parse().unwrap()
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Posts to `/admin/subscribers/{id}/{action}`, e.g. `confirm`.
+    pub async fn post_admin_subscriber_action(
+        &self,
+        subscriber_id: Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                &self.address, subscriber_id, action
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscribers_import(&self, query: &str, csv: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/import?{}",
+                &self.address, query
+            ))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_failed_logins(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/login-attempts", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_consent_history(&self, subscriber_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}/events",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn delete_admin_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .delete(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Logs the test user in and saves a draft issue, returning its id.
+    pub async fn create_draft_issue(&self, body: &Value) -> Uuid {
+        self.test_user.login(self).await;
+        let response = self.post_admin_issue(body).await;
+        assert_eq!(201, response.status().as_u16());
+        let body: Value = response.json().await.unwrap();
+        body["newsletter_issue_id"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    /// Saves the issue as a draft, then publishes it through the API.
+    pub async fn publish_issue(&self, draft_body: &Value) -> reqwest::Response {
+        let newsletter_issue_id = self.create_draft_issue(draft_body).await;
+        self.post_newsletters(serde_json::json!({ "newsletter_issue_id": newsletter_issue_id }))
+            .await
+    }
+
+    /// Schedules a newsletter issue, returning its id.
+    pub async fn schedule_newsletter(&self, send_at: DateTime<Utc>) -> Uuid {
+        let newsletter_issue_id = self
+            .create_draft_issue(&serde_json::json!({
+                "title": "Newsletter Title",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>"
+                }
+            }))
+            .await;
+        let response = self
+            .post_newsletters(serde_json::json!({
+                "newsletter_issue_id": newsletter_issue_id,
+                "send_at": send_at
+            }))
+            .await;
+        assert_eq!(202, response.status().as_u16());
+        let body: Value = response.json().await.unwrap();
+        assert_eq!("scheduled", body["status"]);
+        newsletter_issue_id
+    }
+
+    pub async fn post_cancel_issue(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/issues/{}/cancel",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_reschedule_issue(
+        &self,
+        newsletter_issue_id: Uuid,
+        send_at: DateTime<Utc>,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/issues/{}/reschedule",
+                &self.address, newsletter_issue_id
+            ))
+            .json(&serde_json::json!({ "send_at": send_at }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_issues_archive(&self, page: u32) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/issues?page={}", &self.address, page))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_issue_page(&self, slug: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/issues/{}", &self.address, slug))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// A GET request for a feed, to which tests can add conditional headers.
+    pub fn feed_request(&self, name: &str) -> reqwest::RequestBuilder {
+        self.api_client.get(format!("{}/{}", &self.address, name))
+    }
+
+    pub async fn post_postmark_webhook(&self, body: &Value, secret: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .header("X-Webhook-Secret", secret)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_forgot_password(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password/forgot", &self.address))
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/password/reset", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Drains the delivery queue and the email outbox, waiting for tasks that
+    /// the application's own workers may be holding.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            let issues = self.issue_delivery_worker.try_execute_task().await.unwrap();
+            let emails = self.email_outbox_worker.try_execute_task().await.unwrap();
+            if let (ExecutionOutcome::EmptyQueue, ExecutionOutcome::EmptyQueue) = (issues, emails) {
+                // Delayed deliveries are not pending yet.
+                let pending = sqlx::query!(
+                    "SELECT (SELECT COUNT(*) FROM issue_delivery_queue
+                        WHERE execute_after <= now() AND NOT digest)
+                    + (SELECT COUNT(*) FROM digest_queue WHERE execute_after <= now())
+                    + (SELECT COUNT(*) FROM email_outbox WHERE execute_after <= now()) AS count"
+                )
+                .fetch_one(&self.db_connection_pool)
+                .await
+                .unwrap()
+                .count
+                .unwrap_or(0);
+                if pending == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+    }
+
+    /// Brings the pending digests forward, as if their day or week had come.
+    pub async fn make_digests_due(&self) {
+        sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now() WHERE digest")
+            .execute(&self.db_connection_pool)
+            .await
+            .unwrap();
+        sqlx::query!("UPDATE digest_queue SET execute_after = now()")
+            .execute(&self.db_connection_pool)
+            .await
+            .unwrap();
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let json_body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -96,8 +624,8 @@ impl TestApp {
             confirmation_link
         };
 
-        let html_link = get_link(&json_body["HtmlBody"].as_str().unwrap());
-        let text_link = get_link(&json_body["TextBody"].as_str().unwrap());
+        let html_link = get_link(json_body["HtmlBody"].as_str().unwrap());
+        let text_link = get_link(json_body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks {
             html: html_link,
@@ -105,6 +633,25 @@ impl TestApp {
         }
     }
 
+    /// Extracts the link advertised in the `List-Unsubscribe` header of a newsletter email.
+    pub fn get_unsubscribe_link(&self, email: &Value) -> Url {
+        let header = email["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .unwrap();
+        let raw_link = header["Value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        let mut unsubscribe_link = Url::parse(raw_link).unwrap();
+        assert_eq!("127.0.0.1", unsubscribe_link.host_str().unwrap());
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
     pub async fn call_confirmation_link(&self) -> Response {
         let email_request = &self.email_server.received_requests().await.unwrap()[0];
         let confirmation_links = self.get_confirmation_links(email_request);
@@ -119,6 +666,59 @@ impl TestApp {
             .unwrap()
     }
 
+    pub async fn create_unconfirmed_subscriber(&self) {
+        let body = "name=Jon%20Doe&email=jondoe%40email.com";
+
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+
+        self.post_subscriptions(body.into())
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    pub async fn create_confirmed_subscriber(&self) {
+        self.create_unconfirmed_subscriber().await;
+        self.call_confirmation_link().await;
+    }
+
+    /// Adds a confirmed member of the default list straight to the database.
+    pub async fn insert_confirmed_subscriber(&self, name: &str, email: &str) {
+        sqlx::query!(
+            r#"WITH subscriber AS (
+                INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+                VALUES ($1, $2, $3, now(), 'confirmed')
+                RETURNING id
+            )
+            INSERT INTO list_memberships (list_id, subscriber_id, status)
+            SELECT l.list_id, s.id, 'confirmed' FROM subscriber s, lists l WHERE l.is_default"#,
+            Uuid::new_v4(),
+            email,
+            name
+        )
+        .execute(&self.db_connection_pool)
+        .await
+        .unwrap();
+    }
+
+    /// Every newsletter email sent through Postmark's batch endpoint, in order.
+    pub async fn delivered_newsletters(&self) -> Vec<Value> {
+        self.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.url.path() == "/email/batch")
+            .flat_map(|request| serde_json::from_slice::<Vec<Value>>(&request.body).unwrap())
+            .collect()
+    }
+
     pub async fn email_mock_200_response(&self) {
         self.email_mock_200_response_with_times(1).await
     }
@@ -131,6 +731,32 @@ impl TestApp {
             .mount(&self.email_server)
             .await;
     }
+
+    pub async fn batch_email_mock_200_response_with_times(&self, times: u64) {
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(BatchEmailResponder)
+            .expect(times)
+            .mount(&self.email_server)
+            .await;
+    }
+}
+
+/// Answers a Postmark batch request with one successful result, and a fresh
+/// message id, per message.
+pub struct BatchEmailResponder;
+
+impl wiremock::Respond for BatchEmailResponder {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let messages: Vec<Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|_| {
+                serde_json::json!({"ErrorCode": 0, "Message": "OK", "MessageID": Uuid::new_v4()})
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
 }
 
 static TRACING: Lazy<()> = Lazy::new(|| {
@@ -147,6 +773,11 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 });
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawns the application after `customise` has changed its configuration.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -157,6 +788,13 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         c.email_client.base_url =
             Url::parse(email_server.uri().as_str()).expect("Failed to parse URL");
+        c.issue_delivery.base_retry_delay_milliseconds = 0;
+        // Retries are left to the delivery worker, keeping mock expectations exact.
+        c.email_client.retry.max_attempts = 1;
+        // Tests subscribe far more often than any real client.
+        c.rate_limiting.per_ip.capacity = 1000;
+        c.rate_limiting.per_email.capacity = 1000;
+        customise(&mut c);
         c
     };
 
@@ -168,7 +806,13 @@ pub async fn spawn_app() -> TestApp {
 
     let application_port = application.port();
     let address = format!("http://localhost:{}", application_port);
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
+
+    let api_client = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
 
     let test_app = TestApp {
         address,
@@ -176,6 +820,20 @@ pub async fn spawn_app() -> TestApp {
         db_connection_pool: get_connection_pool(&configuration.database),
         email_server,
         test_user: TestUser::generate(),
+        issue_delivery_worker: IssueDeliveryWorker::build(
+            configuration.clone(),
+            get_connection_pool(&configuration.database),
+        ),
+        email_outbox_worker: EmailOutboxWorker::build(
+            configuration.clone(),
+            get_connection_pool(&configuration.database),
+        ),
+        issue_delivery_settings: configuration.issue_delivery,
+        subscription_settings: configuration.subscriptions,
+        retention_settings: configuration.retention,
+        webhook_settings: configuration.webhooks,
+        login_protection_settings: configuration.login_protection,
+        api_client,
     };
     test_app.test_user.store(&test_app.db_connection_pool).await;
     test_app
@@ -202,3 +860,8 @@ pub async fn configure_database(configuration: &DatabaseSettings) -> PgPool {
 
     connection_pool
 }
+
+pub fn assert_is_redirect_to(response: &Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
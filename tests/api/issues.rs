@@ -0,0 +1,121 @@
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn draft_body(title: &str, html: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": html
+        }
+    })
+}
+
+async fn get_slug(test_app: &TestApp, title: &str) -> String {
+    sqlx::query!("SELECT slug FROM newsletter_issues WHERE title = $1", title)
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap()
+        .slug
+        .expect("The issue has no slug")
+}
+
+#[tokio::test]
+async fn only_published_issues_are_listed_in_the_archive() {
+    let test_app = spawn_app().await;
+    test_app
+        .publish_issue(&draft_body("Published issue", "<p>Hi</p>"))
+        .await;
+    test_app
+        .create_draft_issue(&draft_body("Draft issue", "<p>Hi</p>"))
+        .await;
+
+    let html = test_app.get_issues_archive(1).await.text().await.unwrap();
+
+    let slug = get_slug(&test_app, "Published issue").await;
+    assert!(html.contains(&format!(
+        r#"<a href="/issues/{}">Published issue</a>"#,
+        slug
+    )));
+    assert!(!html.contains("Draft issue"));
+}
+
+#[tokio::test]
+async fn the_archive_is_paginated() {
+    let test_app = spawn_app().await;
+    for i in 0..21 {
+        sqlx::query!(
+            r#"INSERT INTO newsletter_issues
+            (newsletter_issue_id, title, text_content, html_content, status, slug, published_at)
+            VALUES ($1, $2, '', '', 'published', $3, now() - make_interval(days => $4))"#,
+            Uuid::new_v4(),
+            format!("Issue {}", i),
+            format!("issue-{}", i),
+            i
+        )
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    }
+
+    let first_page = test_app.get_issues_archive(1).await.text().await.unwrap();
+    assert!(first_page.contains(">Issue 0<"));
+    assert!(first_page.contains(">Issue 19<"));
+    assert!(!first_page.contains(">Issue 20<"));
+    assert!(first_page.contains(r#"<a href="/issues?page=2">Older issues</a>"#));
+
+    let second_page = test_app.get_issues_archive(2).await.text().await.unwrap();
+    assert!(second_page.contains(">Issue 20<"));
+    assert!(!second_page.contains(">Issue 19<"));
+    assert!(second_page.contains(r#"<a href="/issues?page=1">Newer issues</a>"#));
+    assert!(!second_page.contains("Older issues"));
+
+    assert_eq!(400, test_app.get_issues_archive(0).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn issue_pages_render_the_html_content() {
+    let test_app = spawn_app().await;
+    test_app
+        .publish_issue(&draft_body(
+            "Title <3",
+            "<p>Hello {{ subscriber.name }}</p>",
+        ))
+        .await;
+    let slug = get_slug(&test_app, "Title <3").await;
+
+    let response = test_app.get_issue_page(&slug).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<h1>Title &lt;3</h1>"));
+    assert!(html.contains("<p>Hello reader</p>"));
+}
+
+#[tokio::test]
+async fn unknown_issues_have_no_page() {
+    let test_app = spawn_app().await;
+
+    let response = test_app.get_issue_page("missing-issue").await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletters_link_to_their_web_version() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+
+    test_app
+        .publish_issue(&draft_body("Newsletter Title", "<p>Hi</p>"))
+        .await;
+    test_app.dispatch_all_pending_emails().await;
+
+    let slug = get_slug(&test_app, "Newsletter Title").await;
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+    let issue_path = format!("/issues/{}", slug);
+    assert!(email["HtmlBody"].as_str().unwrap().contains(&issue_path));
+    assert!(email["TextBody"].as_str().unwrap().contains(&issue_path));
+}
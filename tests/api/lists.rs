@@ -0,0 +1,161 @@
+use serde_json::Value;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Subscribes Ursula to a list and follows her confirmation link.
+async fn subscribe_to_list(test_app: &TestApp, list_id: Uuid) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    let body = format!("name=Ursula&email=ursula%40email.com&list_id={}", list_id);
+    test_app
+        .post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = test_app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Publishes a new issue to the list and returns the recipients' addresses.
+async fn publish_to_list(test_app: &TestApp, list_id: Option<Uuid>) -> Vec<String> {
+    let already_delivered = test_app.delivered_newsletters().await.len();
+    let newsletter_issue_id = test_app
+        .create_draft_issue(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Body", "html": "<p>Body</p>"}
+        }))
+        .await;
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "list_id": list_id
+        }))
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    test_app.delivered_newsletters().await[already_delivered..]
+        .iter()
+        .map(|email| email["To"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    let test_app = spawn_app().await;
+
+    assert_is_redirect_to(&test_app.get_admin_lists().await, "/login");
+    assert_is_redirect_to(&test_app.post_admin_list("Rust").await, "/login");
+}
+
+#[tokio::test]
+async fn lists_are_created_next_to_the_default_one() {
+    let test_app = spawn_app().await;
+    let list_id = test_app.create_list("Rust").await;
+
+    let response = test_app.post_admin_list("Rust").await;
+    assert_eq!(409, response.status().as_u16());
+    let response = test_app.post_admin_list("  ").await;
+    assert_eq!(400, response.status().as_u16());
+
+    let lists: Vec<Value> = test_app.get_admin_lists().await.json().await.unwrap();
+    assert_eq!(2, lists.len());
+    assert_eq!("Newsletter", lists[0]["name"]);
+    assert_eq!(true, lists[0]["is_default"]);
+    assert_eq!(list_id.to_string(), lists[1]["list_id"]);
+    assert_eq!(false, lists[1]["is_default"]);
+}
+
+#[tokio::test]
+async fn issues_are_only_sent_to_the_members_of_their_list() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    let list_id = test_app.create_list("Rust").await;
+    subscribe_to_list(&test_app, list_id).await;
+    test_app.batch_email_mock_200_response_with_times(2).await;
+
+    assert_eq!(
+        vec!["ursula@email.com"],
+        publish_to_list(&test_app, Some(list_id)).await
+    );
+    assert_eq!(
+        vec!["jondoe@email.com"],
+        publish_to_list(&test_app, None).await
+    );
+}
+
+#[tokio::test]
+async fn unknown_lists_are_rejected() {
+    let test_app = spawn_app().await;
+    let unknown_list_id = Uuid::new_v4();
+
+    let response = test_app
+        .post_subscriptions(format!(
+            "name=Ursula&email=ursula%40email.com&list_id={}",
+            unknown_list_id
+        ))
+        .await;
+    assert_eq!(400, response.status().as_u16());
+
+    let newsletter_issue_id = test_app
+        .create_draft_issue(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Body", "html": "<p>Body</p>"}
+        }))
+        .await;
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "list_id": unknown_list_id
+        }))
+        .await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn unsubscribing_from_a_list_keeps_the_other_subscriptions() {
+    let test_app = spawn_app().await;
+    let list_id = test_app.create_list("Rust").await;
+    test_app
+        .insert_confirmed_subscriber("Ursula", "ursula@email.com")
+        .await;
+    subscribe_to_list(&test_app, list_id).await;
+    test_app.batch_email_mock_200_response_with_times(2).await;
+    publish_to_list(&test_app, Some(list_id)).await;
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+
+    reqwest::Client::new()
+        .post(test_app.get_unsubscribe_link(&email))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    assert!(publish_to_list(&test_app, Some(list_id)).await.is_empty());
+    assert_eq!(
+        vec!["ursula@email.com"],
+        publish_to_list(&test_app, None).await
+    );
+}
@@ -0,0 +1,275 @@
+use serde_json::Value;
+use uuid::Uuid;
+use zero2prod::authentication::purge_old_login_attempts;
+use zero2prod::configuration::Settings;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let test_app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = test_app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = test_app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+    // The flash message is gone once it has been displayed
+    let html_page = test_app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let test_app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": &test_app.test_user.username,
+        "password": &test_app.test_user.password
+    });
+    let response = test_app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = test_app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", test_app.test_user.username)));
+}
+
+fn wrong_password(username: &str) -> serde_json::Value {
+    serde_json::json!({
+        "username": username,
+        "password": "wrong-password"
+    })
+}
+
+fn lock_after(max_failed_attempts: i64) -> impl FnOnce(&mut Settings) {
+    move |c| c.login_protection.max_failed_attempts = max_failed_attempts
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_the_username_out() {
+    let test_app = spawn_app_with(lock_after(2)).await;
+    let username = test_app.test_user.username.clone();
+    for _ in 0..2 {
+        test_app.post_login(&wrong_password(&username)).await;
+    }
+
+    // Even the right password is refused while the lockout lasts.
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": &username,
+            "password": &test_app.test_user.password
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = test_app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many failed attempts, try again in 30 seconds.</i></p>"));
+}
+
+#[tokio::test]
+async fn failures_on_other_usernames_lock_the_address_out() {
+    let test_app = spawn_app_with(lock_after(2)).await;
+    test_app
+        .post_login(&wrong_password("random-username"))
+        .await;
+    test_app
+        .post_login(&wrong_password("another-username"))
+        .await;
+
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": &test_app.test_user.username,
+            "password": &test_app.test_user.password
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn forged_forwarded_addresses_do_not_escape_the_address_lockout() {
+    let test_app = spawn_app_with(lock_after(2)).await;
+    for (username, forwarded_for) in [
+        ("random-username", "192.0.2.1"),
+        ("another-username", "192.0.2.2"),
+    ] {
+        test_app
+            .api_client
+            .post(format!("{}/login", test_app.address))
+            .header("X-Forwarded-For", forwarded_for)
+            .form(&wrong_password(username))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": &test_app.test_user.username,
+            "password": &test_app.test_user.password
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn login_attempts_are_purged_after_the_retention_window() {
+    let test_app = spawn_app_with(|c| c.login_protection.attempt_retention_days = 30).await;
+    test_app
+        .post_login(&wrong_password("recent-username"))
+        .await;
+    sqlx::query!(
+        "INSERT INTO login_attempts (username, succeeded, attempted_at)
+        VALUES ('old-username', false, now() - interval '31 days')"
+    )
+    .execute(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+
+    let n_purged = purge_old_login_attempts(
+        &test_app.db_connection_pool,
+        &test_app.login_protection_settings,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(1, n_purged);
+    let usernames: Vec<String> = sqlx::query!("SELECT username FROM login_attempts")
+        .fetch_all(&test_app.db_connection_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.username)
+        .collect();
+    assert_eq!(vec!["recent-username"], usernames);
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failed_attempts_on_the_username() {
+    // Leave the address out of it: its failures are not reset by a success.
+    let test_app = spawn_app_with(|c| {
+        c.login_protection.max_failed_attempts = 2;
+        c.login_protection.address_failure_window_seconds = 0;
+    })
+    .await;
+    let username = test_app.test_user.username.clone();
+    test_app.post_login(&wrong_password(&username)).await;
+    test_app.test_user.login(&test_app).await;
+    test_app.post_logout().await;
+    test_app.post_login(&wrong_password(&username)).await;
+
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": &username,
+            "password": &test_app.test_user.password
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_successful_login_does_not_reset_the_failed_attempts_from_the_address() {
+    let test_app = spawn_app_with(lock_after(2)).await;
+    test_app
+        .post_login(&wrong_password("random-username"))
+        .await;
+    test_app.test_user.login(&test_app).await;
+    test_app.post_logout().await;
+    test_app
+        .post_login(&wrong_password("another-username"))
+        .await;
+
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": &test_app.test_user.username,
+            "password": &test_app.test_user.password
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn failures_from_the_address_expire_after_the_window() {
+    let test_app = spawn_app_with(|c| {
+        c.login_protection.max_failed_attempts = 2;
+        c.login_protection.max_lockout_seconds = 86400;
+        c.login_protection.address_failure_window_seconds = 3600;
+    })
+    .await;
+    // Enough failures for a lockout that would still last, were they counted.
+    sqlx::query!(
+        "INSERT INTO login_attempts (username, ip_address, succeeded, attempted_at)
+        SELECT 'random-username-' || n, '127.0.0.1', false, now() - interval '2 hours'
+        FROM generate_series(1, 10) AS n"
+    )
+    .execute(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": &test_app.test_user.username,
+            "password": &test_app.test_user.password
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn locked_out_api_clients_get_a_429() {
+    let test_app = spawn_app_with(lock_after(1)).await;
+    test_app
+        .post_login(&wrong_password(&test_app.test_user.username))
+        .await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({ "newsletter_issue_id": Uuid::new_v4() }))
+        .await;
+
+    assert_eq!(429, response.status().as_u16());
+    assert_eq!("30", response.headers()["Retry-After"]);
+}
+
+#[tokio::test]
+async fn failed_logins_are_listed_in_the_audit_log() {
+    let test_app = spawn_app().await;
+    test_app
+        .post_login(&wrong_password("random-username"))
+        .await;
+    test_app
+        .post_login(&wrong_password(&test_app.test_user.username))
+        .await;
+    test_app.test_user.login(&test_app).await;
+
+    let response = test_app.get_failed_logins().await;
+
+    assert_eq!(200, response.status().as_u16());
+    let attempts: Vec<Value> = response.json().await.unwrap();
+    let usernames: Vec<_> = attempts
+        .iter()
+        .map(|attempt| attempt["username"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        vec![test_app.test_user.username.as_str(), "random-username"],
+        usernames
+    );
+    assert_eq!("127.0.0.1", attempts[0]["ip_address"]);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_failed_logins() {
+    let test_app = spawn_app().await;
+
+    let response = test_app.get_failed_logins().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
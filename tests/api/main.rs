@@ -1,5 +1,22 @@
+mod admin_dashboard;
+mod admin_issues;
+mod admin_subscribers;
+mod admin_templates;
+mod change_password;
+mod consent_history;
+mod data_requests;
+mod feeds;
 mod health_check;
 mod helpers;
+mod issues;
+mod lists;
+mod login;
 mod newsletters;
+mod password_reset;
+mod preferences;
+mod rate_limiting;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
@@ -1,17 +1,30 @@
+use chrono::{Duration, Utc};
 use uuid::Uuid;
 use wiremock::{
-    matchers::{method, path},
+    matchers::{any, method, path},
     Mock, ResponseTemplate,
 };
+use zero2prod::idempotency::purge_expired_idempotency_keys;
+use zero2prod::newsletter_scheduler::dispatch_due_issues;
 
-use crate::helpers::{spawn_app, TestApp};
+use crate::helpers::{spawn_app, spawn_app_with};
+
+fn draft_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text":"Newsletter body as plain text",
+            "html":"<p> Newsletter body as HTML </p>"
+        }
+    })
+}
 
 #[tokio::test]
 async fn newsletter_are_not_delivered_to_unconfirmed_subscribers() {
     let test_app = spawn_app().await;
-    create_unconfirmed_subscriber(&test_app).await;
+    test_app.create_unconfirmed_subscriber().await;
 
-    test_app.email_mock_200_response_with_times(0).await;
+    test_app.batch_email_mock_200_response_with_times(0).await;
 
     let newsletter_request_body = serde_json::json!({
         "title": "Newsletter Title",
@@ -21,17 +34,17 @@ async fn newsletter_are_not_delivered_to_unconfirmed_subscribers() {
         }
     });
 
-    let response = test_app.post_newsletters(newsletter_request_body).await;
+    let response = test_app.publish_issue(&newsletter_request_body).await;
 
-    assert_eq!(200, response.status().as_u16());
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
 async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let test_app = spawn_app().await;
-    create_unconfirmed_subscriber(&test_app).await;
-    test_app.call_confirmation_link().await;
-    test_app.email_mock_200_response_with_times(1).await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
 
     let newsletter_request_body = serde_json::json!({
         "title": "Newsletter Title",
@@ -41,9 +54,134 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
         }
     });
 
-    let response = test_app.post_newsletters(newsletter_request_body).await;
+    let response = test_app.publish_issue(&newsletter_request_body).await;
 
-    assert_eq!(200, response.status().as_u16());
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_personalised_for_each_subscriber() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text":"Hello {{ subscriber.name }}",
+            "html":"<p>Hello {{ subscriber.name }}</p>"
+        }
+    });
+
+    let response = test_app.publish_issue(&newsletter_request_body).await;
+
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+    assert!(email["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("<p>Hello Jon Doe</p>"));
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Hello Jon Doe"));
+}
+
+#[tokio::test]
+async fn scheduled_newsletters_are_delivered_once_due() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+
+    let newsletter_issue_id = test_app
+        .schedule_newsletter(Utc::now() + Duration::hours(1))
+        .await;
+    assert_eq!(
+        0,
+        dispatch_due_issues(&test_app.db_connection_pool)
+            .await
+            .unwrap()
+    );
+    test_app.dispatch_all_pending_emails().await;
+
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = now() WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+    dispatch_due_issues(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    // Another replica polling afterwards finds nothing left to dispatch.
+    assert_eq!(
+        0,
+        dispatch_due_issues(&test_app.db_connection_pool)
+            .await
+            .unwrap()
+    );
+    test_app.dispatch_all_pending_emails().await;
+
+    let issue = sqlx::query!(
+        "SELECT status, published_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+    assert_eq!("published", issue.status);
+    assert!(issue.published_at.is_some());
+}
+
+#[tokio::test]
+async fn newsletters_scheduled_in_the_past_are_published_immediately() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body()).await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "send_at": Utc::now() - Duration::minutes(1)
+        }))
+        .await;
+
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("published", body["status"]);
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn drafts_that_no_longer_render_are_not_published() {
+    let test_app = spawn_app().await;
+    test_app.test_user.login(&test_app).await;
+    test_app
+        .put_admin_template("partials/greeting.html", "Hello")
+        .await;
+    let newsletter_issue_id = test_app
+        .create_draft_issue(&serde_json::json!({
+            "title": "Newsletter Title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "{% include \"partials/greeting.html\" %}"
+            }
+        }))
+        .await;
+
+    test_app
+        .put_admin_template("partials/greeting.html", "Hello {{ subscriber.age }}")
+        .await;
+    let response = test_app
+        .post_newsletters(serde_json::json!({ "newsletter_issue_id": newsletter_issue_id }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
 }
 
 #[tokio::test]
@@ -51,20 +189,14 @@ async fn newsletters_returns_400_for_invalid_data() {
     let test_app = spawn_app().await;
 
     let test_cases = vec![
+        (serde_json::json!({}), "missing issue id"),
         (
-            serde_json::json!(
-                {
-                    "content": {
-                        "text":"Newsletter body as plain text",
-                        "html":"<p> Newsletter body as HTML </p>"
-                    }
-                }
-            ),
-            "missing title",
+            serde_json::json!({"newsletter_issue_id": "not-a-uuid"}),
+            "invalid issue id",
         ),
         (
-            serde_json::json!({"title": "Newsletter title"}),
-            "missing content",
+            serde_json::json!({"newsletter_issue_id": Uuid::new_v4(), "send_at": "tomorrow"}),
+            "invalid send_at",
         ),
     ];
 
@@ -79,19 +211,42 @@ async fn newsletters_returns_400_for_invalid_data() {
     }
 }
 
+#[tokio::test]
+async fn unknown_issues_cannot_be_published() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({ "newsletter_issue_id": Uuid::new_v4() }))
+        .await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn issues_are_published_only_once() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body()).await;
+    let publish_request_body = serde_json::json!({ "newsletter_issue_id": newsletter_issue_id });
+
+    let response = test_app
+        .post_newsletters(publish_request_body.clone())
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    let response = test_app.post_newsletters(publish_request_body).await;
+    assert_eq!(409, response.status().as_u16());
+
+    test_app.dispatch_all_pending_emails().await;
+}
+
 #[tokio::test]
 async fn requests_without_authorization_header_are_rejected() {
     let test_app = spawn_app().await;
-    let newsletter_request_body = serde_json::json!({
-        "title": "Newsletter Title",
-        "content": {
-            "text":"Newsletter body as plain text",
-            "html":"<p> Newsletter body as HTML </p>"
-        }
-    });
+    let newsletter_request_body = serde_json::json!({ "newsletter_issue_id": Uuid::new_v4() });
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", test_app.address))
+        .post(format!("{}/newsletters", test_app.address))
         .json(&newsletter_request_body)
         .send()
         .await
@@ -106,16 +261,10 @@ async fn non_existing_user_is_rejected() {
     let username = Uuid::new_v4().to_string();
     let password = Uuid::new_v4().to_string();
 
-    let newsletter_request_body = serde_json::json!({
-        "title": "Newsletter Title",
-        "content": {
-            "text":"Newsletter body as plain text",
-            "html":"<p> Newsletter body as HTML </p>"
-        }
-    });
+    let newsletter_request_body = serde_json::json!({ "newsletter_issue_id": Uuid::new_v4() });
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", test_app.address))
+        .post(format!("{}/newsletters", test_app.address))
         .basic_auth(username, Some(password))
         .json(&newsletter_request_body)
         .send()
@@ -137,16 +286,10 @@ async fn invalid_password_is_rejected() {
 
     assert_ne!(password, test_app.test_user.password);
 
-    let newsletter_request_body = serde_json::json!({
-        "title": "Newsletter Title",
-        "content": {
-            "text":"Newsletter body as plain text",
-            "html":"<p> Newsletter body as HTML </p>"
-        }
-    });
+    let newsletter_request_body = serde_json::json!({ "newsletter_issue_id": Uuid::new_v4() });
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", test_app.address))
+        .post(format!("{}/newsletters", test_app.address))
         .basic_auth(username, Some(password))
         .json(&newsletter_request_body)
         .send()
@@ -160,20 +303,296 @@ async fn invalid_password_is_rejected() {
     );
 }
 
-async fn create_unconfirmed_subscriber(test_app: &TestApp) {
-    let body = "name=Jon%20Doe&email=jondoe%40email.com";
+#[tokio::test]
+async fn newsletter_is_accepted_even_if_delivery_fails() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1 + test_app.issue_delivery_settings.max_retries as u64)
+        .mount(&test_app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text":"Newsletter body as plain text",
+            "html":"<p> Newsletter body as HTML </p>"
+        }
+    });
+
+    let response = test_app.publish_issue(&newsletter_request_body).await;
+
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
 
-    let _mock_guard = Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
         .expect(1)
-        .mount_as_scoped(&test_app.email_server)
+        .mount(&test_app.email_server)
         .await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
 
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text":"Newsletter body as plain text",
+            "html":"<p> Newsletter body as HTML </p>"
+        }
+    });
+
+    let response = test_app.publish_issue(&newsletter_request_body).await;
+
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn the_worker_does_not_retry_inside_the_email_client() {
+    let test_app = spawn_app_with(|c| c.email_client.retry.max_attempts = 3).await;
+    test_app.create_confirmed_subscriber().await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text":"Newsletter body as plain text",
+            "html":"<p> Newsletter body as HTML </p>"
+        }
+    });
+    let response = test_app.publish_issue(&newsletter_request_body).await;
+    assert_eq!(202, response.status().as_u16());
+
+    test_app
+        .issue_delivery_worker
+        .try_execute_task()
+        .await
+        .unwrap();
+
+    // The failed delivery waits in the queue for the next attempt.
+    let n_retries = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap()
+        .n_retries;
+    assert_eq!(1, n_retries);
+}
+
+#[tokio::test]
+async fn only_throttled_recipients_of_a_batch_are_retried() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app
+        .insert_confirmed_subscriber("Jane Doe", "janedoe@email.com")
+        .await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 0, "Message": "OK"},
+            {"ErrorCode": 429, "Message": "Rate limit exceeded"},
+        ])))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text":"Newsletter body as plain text",
+            "html":"<p> Newsletter body as HTML </p>"
+        }
+    });
+
+    let response = test_app.publish_issue(&newsletter_request_body).await;
+
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+    // Both recipients in the first batch, only the throttled one in the retry.
+    assert_eq!(3, test_app.delivered_newsletters().await.len());
+}
+
+#[tokio::test]
+async fn permanently_rejected_recipients_are_not_retried() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
     test_app
-        .post_subscriptions(body.into())
+        .insert_confirmed_subscriber("Jane Doe", "janedoe@email.com")
+        .await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 0, "Message": "OK"},
+            {"ErrorCode": 406, "Message": "Inactive recipient"},
+        ])))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text":"Newsletter body as plain text",
+            "html":"<p> Newsletter body as HTML </p>"
+        }
+    });
+
+    let response = test_app.publish_issue(&newsletter_request_body).await;
+
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+    assert_eq!(2, test_app.delivered_newsletters().await.len());
+}
+
+#[tokio::test]
+async fn logged_in_editors_can_publish_without_basic_credentials() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body()).await;
+
+    let newsletter_request_body = serde_json::json!({ "newsletter_issue_id": newsletter_issue_id });
+    let response = test_app
+        .api_client
+        .post(format!("{}/newsletters", test_app.address))
+        .json(&newsletter_request_body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body()).await;
+    let newsletter_request_body = serde_json::json!({ "newsletter_issue_id": newsletter_issue_id });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let response = test_app
+        .post_newsletters_with_idempotency_key(&newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(202, response.status().as_u16());
+
+    let response = test_app
+        .post_newsletters_with_idempotency_key(&newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(202, response.status().as_u16());
+
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_purged_after_their_ttl() {
+    let test_app = spawn_app_with(|c| c.retention.idempotency_key_ttl_hours = 1).await;
+    for age in ["2 hours", "30 minutes"] {
+        let newsletter_issue_id = test_app.create_draft_issue(&draft_body()).await;
+        test_app
+            .post_newsletters_with_idempotency_key(
+                &serde_json::json!({ "newsletter_issue_id": newsletter_issue_id }),
+                &Uuid::new_v4().to_string(),
+            )
+            .await;
+        sqlx::query!(
+            "UPDATE idempotency SET created_at = now() - $1::text::interval
+            WHERE created_at > now() - interval '1 minute'",
+            age
+        )
+        .execute(&test_app.db_connection_pool)
         .await
-        .error_for_status()
         .unwrap();
+    }
+
+    let n_purged =
+        purge_expired_idempotency_keys(&test_app.db_connection_pool, &test_app.retention_settings)
+            .await
+            .unwrap();
+
+    assert_eq!(1, n_purged);
+}
+
+#[tokio::test]
+async fn concurrent_form_submission_is_handled_gracefully() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body()).await;
+    let newsletter_request_body = serde_json::json!({ "newsletter_issue_id": newsletter_issue_id });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let response1 =
+        test_app.post_newsletters_with_idempotency_key(&newsletter_request_body, &idempotency_key);
+    let response2 =
+        test_app.post_newsletters_with_idempotency_key(&newsletter_request_body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn invalid_idempotency_key_is_rejected() {
+    let test_app = spawn_app().await;
+    let newsletter_request_body = serde_json::json!({ "newsletter_issue_id": Uuid::new_v4() });
+
+    let response = test_app
+        .post_newsletters_with_idempotency_key(&newsletter_request_body, &"a".repeat(64))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_request_is_rejected() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let newsletter_issue_id = test_app.create_draft_issue(&draft_body()).await;
+    let response = test_app
+        .post_newsletters_with_idempotency_key(
+            &serde_json::json!({ "newsletter_issue_id": newsletter_issue_id }),
+            &idempotency_key,
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+
+    let other_issue_id = test_app.create_draft_issue(&draft_body()).await;
+    let response = test_app
+        .post_newsletters_with_idempotency_key(
+            &serde_json::json!({ "newsletter_issue_id": other_issue_id }),
+            &idempotency_key,
+        )
+        .await;
+
+    assert_eq!(422, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
 }
@@ -0,0 +1,241 @@
+use uuid::Uuid;
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+use zero2prod::authentication::{issue_password_reset_token, purge_expired_password_reset_tokens};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn get_reset_token(test_app: &TestApp, email_request: &wiremock::Request) -> String {
+    let links = test_app.get_confirmation_links(email_request);
+    links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn forgot_password_does_not_reveal_unknown_emails() {
+    let test_app = spawn_app().await;
+    test_app.email_mock_200_response_with_times(0).await;
+
+    let response = test_app
+        .post_forgot_password(&format!("{}@example.com", Uuid::new_v4()))
+        .await;
+
+    assert_is_redirect_to(&response, "/password/forgot");
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn forgot_password_does_not_wait_for_the_email_provider() {
+    let test_app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_forgot_password(&test_app.test_user.email)
+        .await;
+
+    assert_is_redirect_to(&response, "/password/forgot");
+}
+
+#[tokio::test]
+async fn forgot_password_sends_a_reset_link() {
+    let test_app = spawn_app().await;
+    test_app.email_mock_200_response().await;
+
+    let response = test_app
+        .post_forgot_password(&test_app.test_user.email)
+        .await;
+    assert_is_redirect_to(&response, "/password/forgot");
+
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], test_app.test_user.email.as_str());
+}
+
+#[tokio::test]
+async fn an_invalid_reset_token_is_rejected() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_reset_password(&serde_json::json!({
+            "token": "invalid",
+            "new_password": "Correct-horse-battery",
+            "new_password_check": "Correct-horse-battery",
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/password/forgot");
+}
+
+#[tokio::test]
+async fn resetting_the_password_works_only_once() {
+    let test_app = spawn_app().await;
+    let new_password = "Correct-horse-battery";
+    test_app.email_mock_200_response().await;
+
+    test_app
+        .post_forgot_password(&test_app.test_user.email)
+        .await;
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let token = get_reset_token(&test_app, email_request);
+
+    let reset_body = serde_json::json!({
+        "token": &token,
+        "new_password": new_password,
+        "new_password_check": new_password,
+    });
+    let response = test_app.post_reset_password(&reset_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": &test_app.test_user.username,
+            "password": new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let response = test_app.post_reset_password(&reset_body).await;
+    assert_is_redirect_to(&response, "/password/forgot");
+}
+
+#[tokio::test]
+async fn the_reset_link_is_sent_to_the_email_set_by_the_admin() {
+    let test_app = spawn_app().await;
+    let new_email = format!("{}@example.com", Uuid::new_v4());
+    let new_password = "Correct-horse-battery";
+    test_app.email_mock_200_response().await;
+
+    test_app.test_user.login(&test_app).await;
+    let response = test_app.post_change_email(&new_email).await;
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = test_app.get_change_email_html().await;
+    assert!(html_page.contains("<p><i>Your email has been changed.</i></p>"));
+    test_app.post_logout().await;
+
+    test_app.post_forgot_password(&new_email).await;
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], new_email.as_str());
+
+    let response = test_app
+        .post_reset_password(&serde_json::json!({
+            "token": get_reset_token(&test_app, email_request),
+            "new_password": new_password,
+            "new_password_check": new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = test_app
+        .post_login(&serde_json::json!({
+            "username": &test_app.test_user.username,
+            "password": new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_email_used_by_another_account_is_rejected() {
+    let test_app = spawn_app().await;
+    let other_email = format!("{}@example.com", Uuid::new_v4());
+    sqlx::query!(
+        "INSERT INTO users (user_id, username, password, email) VALUES ($1, 'other', '', $2)",
+        Uuid::new_v4(),
+        other_email
+    )
+    .execute(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+
+    test_app.test_user.login(&test_app).await;
+    let response = test_app.post_change_email(&other_email).await;
+
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = test_app.get_change_email_html().await;
+    assert!(html_page.contains("<p><i>This email is already used by another account.</i></p>"));
+}
+
+#[tokio::test]
+async fn changing_the_email_requires_the_current_password() {
+    let test_app = spawn_app().await;
+    let new_email = format!("{}@example.com", Uuid::new_v4());
+
+    test_app.test_user.login(&test_app).await;
+    let response = test_app
+        .post_change_email_with_password(&new_email, "wrong-password")
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/email");
+    let html_page = test_app.get_change_email_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+    let saved = sqlx::query!(
+        "SELECT email FROM users WHERE user_id = $1",
+        test_app.test_user.user_id
+    )
+    .fetch_one(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(Some(test_app.test_user.email.clone()), saved.email);
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_out_every_session() {
+    let test_app = spawn_app().await;
+    let new_password = "Correct-horse-battery";
+    test_app.email_mock_200_response().await;
+    test_app.test_user.login(&test_app).await;
+
+    test_app
+        .post_forgot_password(&test_app.test_user.email)
+        .await;
+    test_app.dispatch_all_pending_emails().await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    test_app
+        .post_reset_password(&serde_json::json!({
+            "token": get_reset_token(&test_app, email_request),
+            "new_password": new_password,
+            "new_password_check": new_password,
+        }))
+        .await;
+
+    let response = test_app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn expired_reset_tokens_are_purged() {
+    let test_app = spawn_app().await;
+    for _ in 0..2 {
+        issue_password_reset_token(&test_app.db_connection_pool, test_app.test_user.user_id)
+            .await
+            .unwrap();
+    }
+    sqlx::query!(
+        "UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'
+        WHERE token_hash = (SELECT MIN(token_hash) FROM password_reset_tokens)"
+    )
+    .execute(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+
+    let n_purged = purge_expired_password_reset_tokens(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(1, n_purged);
+    let remaining = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM password_reset_tokens"#)
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(1, remaining);
+}
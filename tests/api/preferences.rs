@@ -0,0 +1,466 @@
+use reqwest::Url;
+use serde_json::Value;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+fn newsletter_body() -> Value {
+    serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p> Newsletter body as HTML </p>"
+        }
+    })
+}
+
+/// Confirms Jon Doe, sends him an issue and returns the preferences link it
+/// carries.
+async fn preferences_link(test_app: &TestApp) -> Url {
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    let response = test_app.publish_issue(&newsletter_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+    let html_body = email["HtmlBody"].as_str().unwrap();
+    let text_body = email["TextBody"].as_str().unwrap();
+    let mut link = linkify::LinkFinder::new()
+        .links(text_body)
+        .map(|link| Url::parse(link.as_str()).unwrap())
+        .find(|link| link.path() == "/preferences")
+        .unwrap();
+    assert!(html_body.contains(&htmlescape::encode_minimal(link.as_str())));
+    link.set_port(Some(test_app.port)).unwrap();
+    link
+}
+
+fn api_link(link: &Url) -> Url {
+    let mut link = link.clone();
+    link.set_path("/api/preferences");
+    link
+}
+
+async fn get_preferences(test_app: &TestApp, link: &Url) -> Value {
+    let response = test_app
+        .api_client
+        .get(api_link(link))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+async fn put_preferences(test_app: &TestApp, link: &Url, body: &Value) -> reqwest::Response {
+    test_app
+        .api_client
+        .put(api_link(link))
+        .json(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn update(email: &str) -> Value {
+    serde_json::json!({
+        "name": "Jonathan Doe",
+        "email": email,
+        "digest_frequency": "weekly",
+        "paused_until": null
+    })
+}
+
+#[tokio::test]
+async fn preferences_require_a_valid_token() {
+    let test_app = spawn_app().await;
+
+    for path in ["/preferences", "/api/preferences"] {
+        let response = test_app
+            .api_client
+            .get(format!("{}{}?token=forged", test_app.address, path))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(400, response.status().as_u16(), "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn preferences_links_expire() {
+    let test_app = spawn_app_with(|c| c.subscriptions.preferences_link_ttl_days = 0).await;
+    let link = preferences_link(&test_app).await;
+
+    let response = test_app
+        .api_client
+        .get(api_link(&link))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletters_link_to_the_preferences_of_the_subscriber() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+
+    let preferences = get_preferences(&test_app, &link).await;
+
+    assert_eq!("Jon Doe", preferences["name"]);
+    assert_eq!("jondoe@email.com", preferences["email"]);
+    assert_eq!("immediate", preferences["digest_frequency"]);
+    assert!(preferences["paused_until"].is_null());
+    assert!(preferences["pending_email"].is_null());
+}
+
+#[tokio::test]
+async fn subscribers_can_update_their_name_schedule_and_pause() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+    let mut body = update("jondoe@email.com");
+    body["paused_until"] = "2099-01-31".into();
+
+    let response = put_preferences(&test_app, &link, &body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let preferences = get_preferences(&test_app, &link).await;
+    assert_eq!("Jonathan Doe", preferences["name"]);
+    assert_eq!("weekly", preferences["digest_frequency"]);
+    assert_eq!("2099-01-31", preferences["paused_until"]);
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected_with_a_400() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+    let test_cases = [
+        ("name", "<script>", "an invalid name"),
+        ("email", "not-an-email", "an invalid email"),
+        ("digest_frequency", "monthly", "an unknown schedule"),
+    ];
+
+    for (field, value, description) in test_cases {
+        let mut body = update("jondoe@email.com");
+        body[field] = value.into();
+
+        let response = put_preferences(&test_app, &link, &body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject {}",
+            description
+        );
+    }
+    let preferences = get_preferences(&test_app, &link).await;
+    assert_eq!("Jon Doe", preferences["name"]);
+    assert_eq!("immediate", preferences["digest_frequency"]);
+}
+
+#[tokio::test]
+async fn a_new_email_replaces_the_current_one_once_confirmed() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+    test_app.email_mock_200_response().await;
+
+    let response = put_preferences(&test_app, &link, &update("jon@new.com")).await;
+    assert_eq!(200, response.status().as_u16());
+    let preferences: Value = response.json().await.unwrap();
+    assert_eq!("jondoe@email.com", preferences["email"]);
+    assert_eq!("jon@new.com", preferences["pending_email"]);
+
+    test_app.dispatch_all_pending_emails().await;
+    let requests = test_app.email_server.received_requests().await.unwrap();
+    let confirmation_email = requests
+        .iter()
+        .rfind(|request| request.url.path() == "/email")
+        .unwrap();
+    let sent_to: Value = serde_json::from_slice(&confirmation_email.body).unwrap();
+    assert_eq!("jon@new.com", sent_to["To"]);
+    let confirmation_link = test_app.get_confirmation_links(confirmation_email).html;
+    let response = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let preferences = get_preferences(&test_app, &link).await;
+    assert_eq!("jon@new.com", preferences["email"]);
+    assert!(preferences["pending_email"].is_null());
+}
+
+#[tokio::test]
+async fn a_new_email_taken_before_its_confirmation_can_be_confirmed_once_free() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+    test_app.email_mock_200_response().await;
+    put_preferences(&test_app, &link, &update("jon@new.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    test_app.dispatch_all_pending_emails().await;
+    let requests = test_app.email_server.received_requests().await.unwrap();
+    let confirmation_email = requests
+        .iter()
+        .rfind(|request| request.url.path() == "/email")
+        .unwrap();
+    let confirmation_link = test_app.get_confirmation_links(confirmation_email).html;
+
+    test_app
+        .insert_confirmed_subscriber("Jane Doe", "jon@new.com")
+        .await;
+    let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+    assert_eq!(409, response.status().as_u16());
+
+    sqlx::query!("UPDATE subscriptions SET email = 'janedoe@email.com' WHERE name = 'Jane Doe'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    let response = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let preferences = get_preferences(&test_app, &link).await;
+    assert_eq!("jon@new.com", preferences["email"]);
+    assert!(preferences["pending_email"].is_null());
+}
+
+#[tokio::test]
+async fn emails_of_other_subscribers_cannot_be_taken() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+    test_app
+        .insert_confirmed_subscriber("Jane Doe", "janedoe@email.com")
+        .await;
+
+    let response = put_preferences(&test_app, &link, &update("janedoe@email.com")).await;
+
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn repeated_email_changes_are_rate_limited() {
+    let test_app = spawn_app_with(|c| c.rate_limiting.per_email.capacity = 1).await;
+    let link = preferences_link(&test_app).await;
+    test_app.email_mock_200_response().await;
+
+    let response = put_preferences(&test_app, &link, &update("jon@new.com")).await;
+    assert_eq!(200, response.status().as_u16());
+    let response = put_preferences(&test_app, &link, &update("jon@new.com")).await;
+
+    assert_eq!(429, response.status().as_u16());
+    assert!(response.headers().contains_key("Retry-After"));
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn paused_subscribers_do_not_receive_issues() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+    let mut body = update("jondoe@email.com");
+    body["digest_frequency"] = "immediate".into();
+    body["paused_until"] = "2099-01-31".into();
+    put_preferences(&test_app, &link, &body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = test_app.publish_issue(&newsletter_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    // The batch mock expects the first issue only.
+    assert_eq!(1, test_app.delivered_newsletters().await.len());
+}
+
+#[tokio::test]
+async fn delayed_deliveries_are_held_back() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+    put_preferences(&test_app, &link, &update("jondoe@email.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = test_app.publish_issue(&newsletter_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    assert_eq!(1, test_app.delivered_newsletters().await.len());
+    let held_back = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM issue_delivery_queue \
+        WHERE execute_after > now() + interval '1 hour'"
+    )
+    .fetch_one(&test_app.db_connection_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(Some(1), held_back);
+}
+
+/// Confirms Jon Doe on a weekly digest and publishes two issues for him.
+async fn publish_two_issues_for_a_weekly_digest(test_app: &TestApp) {
+    test_app.create_confirmed_subscriber().await;
+    sqlx::query!("UPDATE subscriptions SET digest_frequency = 'weekly'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    for title in ["First issue", "Second issue"] {
+        let response = test_app
+            .publish_issue(&serde_json::json!({
+                "title": title,
+                "content": {
+                    "text": format!("{} as plain text", title),
+                    "html": format!("<p>{} as HTML</p>", title)
+                }
+            }))
+            .await;
+        assert_eq!(202, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn digests_gather_the_issues_published_in_the_meantime() {
+    let test_app = spawn_app().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    publish_two_issues_for_a_weekly_digest(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+    assert!(test_app.delivered_newsletters().await.is_empty());
+
+    test_app.make_digests_due().await;
+    test_app.dispatch_all_pending_emails().await;
+
+    let emails = test_app.delivered_newsletters().await;
+    assert_eq!(1, emails.len());
+    assert_eq!("Your weekly digest", emails[0]["Subject"]);
+    let html_body = emails[0]["HtmlBody"].as_str().unwrap();
+    let text_body = emails[0]["TextBody"].as_str().unwrap();
+    assert!(
+        html_body.find("<p>First issue as HTML</p>")
+            < html_body.find("<p>Second issue as HTML</p>")
+    );
+    assert!(text_body.contains("First issue as plain text"));
+    assert!(text_body.contains("Second issue as plain text"));
+    assert!(text_body.contains("/subscriptions/unsubscribe?token="));
+    let deliveries = sqlx::query!("SELECT status, message_id FROM issue_deliveries")
+        .fetch_all(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(2, deliveries.len());
+    assert!(deliveries.iter().all(|d| d.status == "sent"));
+    assert_eq!(deliveries[0].message_id, deliveries[1].message_id);
+    let pending = sqlx::query!(
+        r#"SELECT (SELECT COUNT(*) FROM issue_delivery_queue)
+        + (SELECT COUNT(*) FROM digest_queue) AS "count!""#
+    )
+    .fetch_one(&test_app.db_connection_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(0, pending);
+}
+
+#[tokio::test]
+async fn failed_digests_are_retried_as_a_whole() {
+    let test_app = spawn_app().await;
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    publish_two_issues_for_a_weekly_digest(&test_app).await;
+    test_app.make_digests_due().await;
+
+    test_app.dispatch_all_pending_emails().await;
+
+    // The failed attempt and the retry carried the same two issues.
+    let attempts = test_app.delivered_newsletters().await;
+    assert_eq!(2, attempts.len());
+    assert_eq!(attempts[0]["HtmlBody"], attempts[1]["HtmlBody"]);
+    let sent =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries WHERE status = 'sent'"#)
+            .fetch_one(&test_app.db_connection_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(2, sent);
+}
+
+#[tokio::test]
+async fn the_preferences_page_saves_the_submitted_form() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+    let location = format!("{}?{}", link.path(), link.query().unwrap());
+
+    let html = test_app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains(r#"value="Jon Doe""#));
+    assert!(html.contains(r#"<option value="immediate" selected>"#));
+
+    let response = test_app
+        .api_client
+        .post(link.clone())
+        .form(&[
+            ("name", "Jonathan Doe"),
+            ("email", "jondoe@email.com"),
+            ("digest_frequency", "daily"),
+            ("paused_until", ""),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, &location);
+
+    let html = test_app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<p><i>Your preferences have been saved.</i></p>"));
+    assert!(html.contains(r#"value="Jonathan Doe""#));
+    assert!(html.contains(r#"<option value="daily" selected>"#));
+}
+
+#[tokio::test]
+async fn the_preferences_page_reports_invalid_values() {
+    let test_app = spawn_app().await;
+    let link = preferences_link(&test_app).await;
+
+    let response = test_app
+        .api_client
+        .post(link.clone())
+        .form(&[
+            ("name", "Jon Doe"),
+            ("email", "jondoe@email.com"),
+            ("digest_frequency", "immediate"),
+            ("paused_until", "someday"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(303, response.status().as_u16());
+
+    let html = test_app
+        .api_client
+        .get(link)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<p><i>someday is not a valid date.</i></p>"));
+}
@@ -0,0 +1,183 @@
+use zero2prod::configuration::{RateLimitStoreKind, Settings};
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+fn subscription(email: &str) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("name", "Jon Doe")
+        .append_pair("email", email)
+        .finish()
+}
+
+fn limits(per_ip: u32, per_email: u32) -> impl FnOnce(&mut Settings) {
+    move |c| {
+        c.rate_limiting.per_ip.capacity = per_ip;
+        c.rate_limiting.per_email.capacity = per_email;
+    }
+}
+
+async fn sent_emails(test_app: &TestApp) -> usize {
+    test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .len()
+}
+
+#[tokio::test]
+async fn repeated_subscriptions_of_an_email_are_rejected_with_a_429() {
+    let test_app = spawn_app_with(limits(100, 2)).await;
+    test_app.email_mock_200_response_with_times(2).await;
+
+    for _ in 0..2 {
+        let response = test_app
+            .post_subscriptions(subscription("jondoe@email.com"))
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    let response = test_app
+        .post_subscriptions(subscription("JonDoe@email.com"))
+        .await;
+
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+    assert_eq!(2, sent_emails(&test_app).await);
+}
+
+#[tokio::test]
+async fn other_emails_are_not_affected_by_the_limit_of_one_email() {
+    let test_app = spawn_app_with(limits(100, 1)).await;
+    test_app.email_mock_200_response_with_times(2).await;
+
+    test_app
+        .post_subscriptions(subscription("jondoe@email.com"))
+        .await;
+    let limited = test_app
+        .post_subscriptions(subscription("jondoe@email.com"))
+        .await;
+    let other = test_app
+        .post_subscriptions(subscription("janedoe@email.com"))
+        .await;
+
+    assert_eq!(429, limited.status().as_u16());
+    assert_eq!(200, other.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_client_subscribing_many_emails_is_rejected_with_a_429() {
+    let test_app = spawn_app_with(limits(2, 100)).await;
+    test_app.email_mock_200_response_with_times(2).await;
+
+    for i in 0..2 {
+        let response = test_app
+            .post_subscriptions(subscription(&format!("jondoe{}@email.com", i)))
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    let response = test_app
+        .post_subscriptions(subscription("jondoe2@email.com"))
+        .await;
+
+    assert_eq!(429, response.status().as_u16());
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn the_postgres_store_keeps_the_buckets_in_the_database() {
+    let test_app = spawn_app_with(|c| {
+        limits(100, 1)(c);
+        c.rate_limiting.store = RateLimitStoreKind::Postgres;
+    })
+    .await;
+    test_app.email_mock_200_response_with_times(1).await;
+
+    let first = test_app
+        .post_subscriptions(subscription("jondoe@email.com"))
+        .await;
+    let second = test_app
+        .post_subscriptions(subscription("jondoe@email.com"))
+        .await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(429, second.status().as_u16());
+    let keys: Vec<String> = sqlx::query!("SELECT key FROM rate_limit_buckets ORDER BY key")
+        .fetch_all(&test_app.db_connection_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.key)
+        .collect();
+    assert_eq!(vec!["email:jondoe@email.com", "ip:127.0.0.1"], keys);
+}
+
+async fn post_subscription_from(
+    test_app: &TestApp,
+    forwarded_for: &str,
+    email: &str,
+) -> reqwest::Response {
+    test_app
+        .api_client
+        .post(format!("{}/subscriptions", test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Forwarded-For", forwarded_for)
+        .body(subscription(email))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn clients_cannot_escape_the_limit_by_forging_their_address() {
+    let test_app = spawn_app_with(limits(1, 100)).await;
+    test_app.email_mock_200_response_with_times(1).await;
+
+    let first = post_subscription_from(&test_app, "192.0.2.1", "jondoe@email.com").await;
+    let second = post_subscription_from(&test_app, "192.0.2.2", "janedoe@email.com").await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(429, second.status().as_u16());
+}
+
+#[tokio::test]
+async fn trusted_proxies_tell_the_address_of_their_clients() {
+    let test_app = spawn_app_with(|c| {
+        limits(1, 100)(c);
+        c.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+    test_app.email_mock_200_response_with_times(2).await;
+
+    let first = post_subscription_from(&test_app, "192.0.2.1", "jondoe@email.com").await;
+    let second = post_subscription_from(&test_app, "192.0.2.2", "janedoe@email.com").await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+}
+
+#[tokio::test]
+async fn password_resets_and_data_requests_are_rate_limited() {
+    let test_app = spawn_app_with(limits(100, 1)).await;
+
+    for path in ["/password/forgot", "/data-requests"] {
+        let email = format!(
+            "{}@email.com",
+            path.trim_start_matches('/').replace('/', "-")
+        );
+        for expected_status in [303, 429] {
+            let response = test_app
+                .api_client
+                .post(format!("{}{}", test_app.address, path))
+                .form(&[("email", &email)])
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(expected_status, response.status().as_u16(), "{}", path);
+        }
+    }
+}
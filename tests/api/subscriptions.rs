@@ -1,3 +1,5 @@
+use zero2prod::retention_worker::purge_stale_pending_subscriptions;
+
 use crate::helpers::spawn_app;
 
 #[tokio::test]
@@ -88,6 +90,78 @@ async fn subcribing_twice_sends_two_confirmation_emails() {
     assert_eq!(200, second_request.status().as_u16());
 }
 
+#[tokio::test]
+async fn undeliverable_addresses_are_not_sent_a_confirmation_email() {
+    let test_app = spawn_app().await;
+    let body = "name=Jon%20Doe&email=jondoe%40email.com";
+    test_app.email_mock_200_response_with_times(1).await;
+    test_app.post_subscriptions(body.into()).await;
+
+    for status in ["bounced", "complained"] {
+        sqlx::query!("UPDATE subscriptions SET status = $1", status)
+            .execute(&test_app.db_connection_pool)
+            .await
+            .unwrap();
+
+        let response = test_app
+            .post_subscriptions("name=Jon%20Doe&email=JonDoe%40email.com".into())
+            .await;
+
+        assert_eq!(200, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn subscribing_again_issues_a_fresh_token() {
+    let test_app = spawn_app().await;
+    let body = "name=Jon%20Doe&email=jondoe%40email.com";
+
+    test_app.email_mock_200_response_with_times(2).await;
+    test_app.post_subscriptions(body.into()).await;
+    test_app.post_subscriptions(body.into()).await;
+
+    let email_requests = test_app.email_server.received_requests().await.unwrap();
+    let first_link = test_app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = test_app.get_confirmation_links(&email_requests[1]).html;
+    assert_ne!(first_link, second_link);
+
+    let response = reqwest::get(first_link).await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+    let response = reqwest::get(second_link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn stale_pending_subscriptions_are_purged() {
+    let test_app = spawn_app().await;
+
+    test_app.create_confirmed_subscriber().await;
+    test_app.email_mock_200_response().await;
+    test_app
+        .post_subscriptions("name=Jane%20Doe&email=janedoe%40email.com".into())
+        .await;
+    sqlx::query!("UPDATE subscription_tokens SET issued_at = now() - interval '1 year'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+
+    let n_purged = purge_stale_pending_subscriptions(
+        &test_app.db_connection_pool,
+        &test_app.subscription_settings,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(1, n_purged);
+    let remaining = sqlx::query!("SELECT email, status FROM subscriptions")
+        .fetch_all(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(1, remaining.len());
+    assert_eq!("jondoe@email.com", remaining[0].email);
+    assert_eq!("confirmed", remaining[0].status);
+}
+
 #[tokio::test]
 async fn subcribe_fails_if_there_is_a_database_error() {
     let test_app = spawn_app().await;
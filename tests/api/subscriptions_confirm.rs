@@ -57,3 +57,52 @@ async fn clicking_confirmation_link_set_status_to_confirmed_in_db() {
     assert_eq!(saved.name, "Jon Doe");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn expired_confirmation_tokens_are_rejected_with_a_410() {
+    let test_app = spawn_app().await;
+    let body = "name=Jon%20Doe&email=jondoe%40email.com";
+
+    test_app.email_mock_200_response().await;
+    test_app.post_subscriptions(body.into()).await;
+
+    sqlx::query!("UPDATE subscription_tokens SET issued_at = now() - interval '1 year'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(410, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "pending confirmation");
+}
+
+#[tokio::test]
+async fn bounced_addresses_cannot_be_confirmed() {
+    let test_app = spawn_app().await;
+    let body = "name=Jon%20Doe&email=jondoe%40email.com";
+
+    test_app.email_mock_200_response().await;
+    test_app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'bounced'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(409, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    assert_eq!("bounced", saved.status);
+}
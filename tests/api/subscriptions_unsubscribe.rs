@@ -0,0 +1,161 @@
+use wiremock::{matchers::path, Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish_and_deliver_newsletter(test_app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter Title",
+        "content": {
+            "text":"Newsletter body as plain text",
+            "html":"<p> Newsletter body as HTML </p>"
+        }
+    });
+    let response = test_app.publish_issue(&newsletter_request_body).await;
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+}
+
+async fn get_status(test_app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .expect("Failed to fetch saved subscription")
+        .status
+}
+
+#[tokio::test]
+async fn newsletters_carry_an_unsubscribe_link_and_one_click_headers() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+
+    publish_and_deliver_newsletter(&test_app).await;
+
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email);
+    let unsubscribe_path = unsubscribe_link.path();
+
+    assert_eq!("/subscriptions/unsubscribe", unsubscribe_path);
+    assert!(email["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(unsubscribe_path));
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(unsubscribe_path));
+    assert!(email["Headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(
+            |h| h["Name"] == "List-Unsubscribe-Post" && h["Value"] == "List-Unsubscribe=One-Click"
+        ));
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_asks_for_confirmation() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    publish_and_deliver_newsletter(&test_app).await;
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email);
+
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("confirmed", get_status(&test_app).await);
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    publish_and_deliver_newsletter(&test_app).await;
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email);
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("unsubscribed", get_status(&test_app).await);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    publish_and_deliver_newsletter(&test_app).await;
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email);
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    publish_and_deliver_newsletter(&test_app).await;
+}
+
+#[tokio::test]
+async fn bounced_addresses_stay_suppressed_after_unsubscribing() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    publish_and_deliver_newsletter(&test_app).await;
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+    let unsubscribe_link = test_app.get_unsubscribe_link(&email);
+    sqlx::query!("UPDATE subscriptions SET status = 'bounced'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("bounced", get_status(&test_app).await);
+
+    // No confirmation email: the address is still undeliverable.
+    let _mock_guard = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+    let response = test_app
+        .post_subscriptions("name=Jon%20Doe&email=jondoe%40email.com".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("bounced", get_status(&test_app).await);
+}
+
+#[tokio::test]
+async fn invalid_unsubscribe_tokens_are_rejected() {
+    let test_app = spawn_app().await;
+
+    for token in ["", "invalid", "Zm9v.YmFy"] {
+        let url = format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            test_app.address, token
+        );
+        let get_response = reqwest::get(&url).await.unwrap();
+        let post_response = reqwest::Client::new().post(&url).send().await.unwrap();
+
+        assert_eq!(400, get_response.status().as_u16());
+        assert_eq!(400, post_response.status().as_u16());
+    }
+}
@@ -0,0 +1,123 @@
+use reqwest::Url;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn draft_body(tracking_enabled: bool) -> Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Read https://example.org/article",
+            "html": "<p>Read <a href=\"https://example.org/article\">this</a></p>"
+        },
+        "tracking_enabled": tracking_enabled
+    })
+}
+
+/// Publishes the issue to the confirmed subscriber, returning its id and the
+/// HTML body they received.
+async fn deliver_issue(test_app: &TestApp, tracking_enabled: bool) -> (Uuid, String) {
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    let newsletter_issue_id = test_app
+        .create_draft_issue(&draft_body(tracking_enabled))
+        .await;
+    let response = test_app
+        .post_newsletters(serde_json::json!({ "newsletter_issue_id": newsletter_issue_id }))
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    let email = test_app.delivered_newsletters().await.pop().unwrap();
+    (
+        newsletter_issue_id,
+        email["HtmlBody"].as_str().unwrap().to_owned(),
+    )
+}
+
+fn tracking_link(test_app: &TestApp, html: &str, path: &str) -> Url {
+    let mut link = linkify::LinkFinder::new()
+        .links(html)
+        .map(|link| Url::parse(link.as_str()).unwrap())
+        .find(|link| link.path() == path)
+        .unwrap();
+    link.set_port(Some(test_app.port)).unwrap();
+    link
+}
+
+async fn get_stats(test_app: &TestApp, newsletter_issue_id: Uuid) -> Value {
+    let response = test_app.get_issue_stats(newsletter_issue_id).await;
+    assert_eq!(200, response.status().as_u16());
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn issues_are_not_tracked_by_default() {
+    let test_app = spawn_app().await;
+
+    let (newsletter_issue_id, html) = deliver_issue(&test_app, false).await;
+
+    assert!(html.contains("<a href=\"https://example.org/article\">"));
+    assert!(!html.contains("/track/"));
+    let stats = get_stats(&test_app, newsletter_issue_id).await;
+    assert_eq!(false, stats["tracking_enabled"]);
+    assert_eq!(1, stats["delivered"]);
+    assert_eq!(0, stats["unique_opens"]);
+    assert_eq!(0, stats["unique_clicks"]);
+}
+
+#[tokio::test]
+async fn unique_opens_and_clicks_of_tracked_issues_are_counted() {
+    let test_app = spawn_app().await;
+    let (newsletter_issue_id, html) = deliver_issue(&test_app, true).await;
+    assert!(!html.contains("href=\"https://example.org/article\""));
+    let open_link = tracking_link(&test_app, &html, "/track/open");
+    let click_link = tracking_link(&test_app, &html, "/track/click");
+
+    for _ in 0..2 {
+        let response = test_app
+            .api_client
+            .get(open_link.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!("image/gif", response.headers()["Content-Type"]);
+    }
+    let response = test_app.api_client.get(click_link).send().await.unwrap();
+
+    assert_is_redirect_to(&response, "https://example.org/article");
+    let stats = get_stats(&test_app, newsletter_issue_id).await;
+    assert_eq!(true, stats["tracking_enabled"]);
+    assert_eq!(1, stats["unique_opens"]);
+    assert_eq!(1, stats["unique_clicks"]);
+}
+
+#[tokio::test]
+async fn forged_tracking_tokens_are_rejected() {
+    let test_app = spawn_app().await;
+
+    for path in ["open", "click"] {
+        let response = test_app
+            .api_client
+            .get(format!("{}/track/{}?token=abc.def", test_app.address, path))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(400, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn stats_are_reserved_to_logged_in_users_and_known_issues() {
+    let test_app = spawn_app().await;
+
+    let response = test_app.get_issue_stats(Uuid::new_v4()).await;
+    assert_is_redirect_to(&response, "/login");
+
+    test_app.test_user.login(&test_app).await;
+    let response = test_app.get_issue_stats(Uuid::new_v4()).await;
+    assert_eq!(404, response.status().as_u16());
+}
@@ -0,0 +1,238 @@
+use secrecy::ExposeSecret;
+use serde_json::Value;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn post_webhook(test_app: &TestApp, body: &Value) -> reqwest::Response {
+    let secret = test_app.webhook_settings.postmark_secret.expose_secret();
+    test_app.post_postmark_webhook(body, secret).await
+}
+
+fn bounce(bounce_type: &str) -> Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Email": "JonDoe@email.com",
+        "BouncedAt": "2023-05-14T11:13:28Z"
+    })
+}
+
+async fn get_status(test_app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .expect("Failed to fetch saved subscription")
+        .status
+}
+
+#[tokio::test]
+async fn webhooks_without_the_shared_secret_are_rejected() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+
+    let response = test_app
+        .post_postmark_webhook(&bounce("HardBounce"), "wrong-secret")
+        .await;
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!("confirmed", get_status(&test_app).await);
+}
+
+#[tokio::test]
+async fn hard_bounced_subscribers_stop_receiving_issues() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(0).await;
+
+    let response = post_webhook(&test_app, &bounce("HardBounce")).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("bounced", get_status(&test_app).await);
+
+    let response = test_app
+        .publish_issue(&serde_json::json!({
+            "title": "Newsletter Title",
+            "content": {"text": "Body", "html": "<p>Body</p>"}
+        }))
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn bounces_are_recorded_against_the_delivery_they_refer_to() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(2).await;
+    for title in ["First issue", "Second issue"] {
+        test_app
+            .publish_issue(&serde_json::json!({
+                "title": title,
+                "content": {"text": "Body", "html": "<p>Body</p>"}
+            }))
+            .await;
+        test_app.dispatch_all_pending_emails().await;
+    }
+    let first_delivery = sqlx::query!(
+        r#"SELECT message_id AS "message_id!" FROM issue_deliveries
+        JOIN newsletter_issues USING (newsletter_issue_id)
+        WHERE title = 'First issue'"#
+    )
+    .fetch_one(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+
+    let mut body = bounce("HardBounce");
+    body["MessageID"] = first_delivery.message_id.into();
+    post_webhook(&test_app, &body).await;
+
+    let deliveries = sqlx::query!(
+        r#"SELECT title, issue_deliveries.status FROM issue_deliveries
+        JOIN newsletter_issues USING (newsletter_issue_id)
+        ORDER BY title"#
+    )
+    .fetch_all(&test_app.db_connection_pool)
+    .await
+    .unwrap();
+    assert_eq!("First issue", deliveries[0].title);
+    assert_eq!("bounced", deliveries[0].status);
+    assert_eq!("sent", deliveries[1].status);
+}
+
+#[tokio::test]
+async fn bounces_reach_the_recipient_of_the_issue_even_if_their_address_changed() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    test_app
+        .publish_issue(&serde_json::json!({
+            "title": "Newsletter Title",
+            "content": {"text": "Body", "html": "<p>Body</p>"}
+        }))
+        .await;
+    test_app.dispatch_all_pending_emails().await;
+    let delivery = sqlx::query!(r#"SELECT message_id AS "message_id!" FROM issue_deliveries"#)
+        .fetch_one(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+
+    let mut body = bounce("HardBounce");
+    body["MessageID"] = delivery.message_id.into();
+    body["Email"] = "someone.else@email.com".into();
+    let response = post_webhook(&test_app, &body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("bounced", get_status(&test_app).await);
+}
+
+#[tokio::test]
+async fn a_bounced_digest_is_recorded_against_every_issue_it_carried() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+    sqlx::query!("UPDATE subscriptions SET digest_frequency = 'daily'")
+        .execute(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    test_app.batch_email_mock_200_response_with_times(1).await;
+    for title in ["First issue", "Second issue"] {
+        test_app
+            .publish_issue(&serde_json::json!({
+                "title": title,
+                "content": {"text": "Body", "html": "<p>Body</p>"}
+            }))
+            .await;
+    }
+    test_app.make_digests_due().await;
+    test_app.dispatch_all_pending_emails().await;
+    let digest = test_app.delivered_newsletters().await.pop().unwrap();
+    let message_id =
+        sqlx::query!(r#"SELECT DISTINCT message_id AS "message_id!" FROM issue_deliveries"#)
+            .fetch_one(&test_app.db_connection_pool)
+            .await
+            .unwrap()
+            .message_id;
+    assert_eq!("Your daily digest", digest["Subject"]);
+
+    let mut body = bounce("HardBounce");
+    body["MessageID"] = message_id.into();
+    let response = post_webhook(&test_app, &body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("bounced", get_status(&test_app).await);
+    let statuses = sqlx::query!("SELECT status FROM issue_deliveries")
+        .fetch_all(&test_app.db_connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(2, statuses.len());
+    assert!(statuses.iter().all(|d| d.status == "bounced"));
+}
+
+#[tokio::test]
+async fn spam_complaints_unsubscribe_the_subscriber() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+
+    let response = post_webhook(
+        &test_app,
+        &serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Email": "jondoe@email.com",
+            "BouncedAt": "2023-05-14T11:13:28Z"
+        }),
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("complained", get_status(&test_app).await);
+}
+
+#[tokio::test]
+async fn soft_bounces_and_other_events_are_ignored() {
+    let test_app = spawn_app().await;
+    test_app.create_confirmed_subscriber().await;
+
+    let test_cases = [
+        (bounce("SoftBounce"), "a soft bounce"),
+        (
+            serde_json::json!({"RecordType": "Delivery", "Recipient": "jondoe@email.com"}),
+            "a delivery",
+        ),
+        (
+            bounce("HardBounce")
+                .as_object()
+                .map(|bounce| {
+                    let mut bounce = bounce.clone();
+                    bounce.insert("Email".into(), "someone@else.com".into());
+                    Value::Object(bounce)
+                })
+                .unwrap(),
+            "a bounce for an unknown address",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = post_webhook(&test_app, &body).await;
+        assert_eq!(
+            200,
+            response.status().as_u16(),
+            "The webhook did not acknowledge {}",
+            description
+        );
+        assert_eq!(
+            "confirmed",
+            get_status(&test_app).await,
+            "The subscriber was flagged after {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn invalid_payloads_are_rejected() {
+    let test_app = spawn_app().await;
+
+    let response = post_webhook(&test_app, &serde_json::json!({"Email": "jondoe@email.com"})).await;
+
+    assert_eq!(400, response.status().as_u16());
+}